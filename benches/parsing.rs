@@ -0,0 +1,100 @@
+//! Measures the throughput of the daily pipeline over a synthetic fixture of `.dly` files, with one
+//! file parsed at a time and with a file per CPU.
+//!
+//! The `ushcn` binary is run against a local `ghcnd_hcn.tar.gz` with `--no-extract`, so a run
+//! decompresses, parses and writes the readings without touching the network. Run with:
+//! `cargo bench --bench parsing`
+
+use std::{
+    fs::{self, File},
+    num::NonZeroUsize,
+    path::Path,
+    process::Command,
+    thread,
+    time::Duration,
+};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use flate2::{write::GzEncoder, Compression};
+use tempfile::TempDir;
+
+/// Stations in the fixture, each with 5 elements over 50 years.
+const STATIONS: usize = 200;
+
+/// Writes the daily archive and the GHCN station list of the fixture to a directory. Returns the
+/// uncompressed size of the `.dly` files.
+fn write_fixture(dir: &Path) -> u64 {
+    let values = "  123  6  -45  6 9999  6    0  6".repeat(8);
+    let mut builder = tar::Builder::new(GzEncoder::new(
+        File::create(dir.join("ghcnd_hcn.tar.gz")).unwrap(),
+        Compression::fast(),
+    ));
+    let mut stations = String::new();
+    let mut bytes = 0;
+
+    for station in 0..STATIONS {
+        let id = format!("USC00{:06}", station);
+        let mut lines = String::new();
+        for year in 1950..2000 {
+            for month in 1..=12 {
+                for element in ["TMAX", "TMIN", "PRCP", "SNOW", "SNWD"] {
+                    lines.push_str(&format!("{}{}{:02}{}{}\n", id, year, month, element, values));
+                }
+            }
+        }
+
+        let mut header = tar::Header::new_gnu();
+        header.set_size(lines.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, format!("ghcnd_hcn/{}.dly", id), lines.as_bytes())
+            .unwrap();
+        bytes += lines.len() as u64;
+
+        stations.push_str(&format!(
+            "{} {:>8.4} {:>9.4} {:>6.1} AL {:<30}\n",
+            id, 32.0114, -86.9522, 57.9, "BENCH STATION"
+        ));
+    }
+    builder.into_inner().unwrap().finish().unwrap();
+    fs::write(dir.join("ghcnd-stations.txt"), stations).unwrap();
+
+    bytes
+}
+
+fn bench_daily(c: &mut Criterion) {
+    let fixture_dir = TempDir::new().unwrap();
+    let output_dir = TempDir::new().unwrap();
+    let bytes = write_fixture(fixture_dir.path());
+
+    let mut group = c.benchmark_group("daily");
+    group.sample_size(10);
+    group.measurement_time(Duration::from_secs(60));
+    group.throughput(Throughput::Bytes(bytes));
+
+    let mut job_counts = vec![1, thread::available_parallelism().map_or(1, NonZeroUsize::get)];
+    job_counts.dedup();
+    for jobs in job_counts {
+        group.bench_with_input(BenchmarkId::new("jobs", jobs), &jobs, |b, jobs| {
+            b.iter(|| {
+                let output = Command::new(env!("CARGO_BIN_EXE_ushcn"))
+                    .arg("daily")
+                    .arg("--from-dir")
+                    .arg(fixture_dir.path())
+                    .arg("--no-extract")
+                    .args(["--jobs", &jobs.to_string()])
+                    .arg("--output-dir")
+                    .arg(output_dir.path())
+                    .output()
+                    .unwrap();
+                assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_daily);
+criterion_main!(benches);
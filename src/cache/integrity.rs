@@ -0,0 +1,88 @@
+//! Checks that cached downloads are intact.
+
+use std::{
+    fs::File,
+    io::{self, BufReader},
+    path::Path,
+};
+
+use anyhow::{anyhow, Result};
+use flate2::read::GzDecoder;
+use sha2::{Digest, Sha256};
+
+#[derive(Debug, Clone, PartialEq)]
+/// The state of a cached download or extraction.
+pub enum Integrity {
+    /// Present and matching the manifest.
+    Intact,
+    /// Not in the cache.
+    Missing,
+    /// Present but damaged, for the given reason.
+    Corrupt(String),
+}
+
+/// Returns the size and hex encoded SHA-256 digest of the file.
+pub fn digest_file(path: &Path) -> Result<(u64, String)> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut hasher = Sha256::new();
+    let size = io::copy(&mut reader, &mut hasher)?;
+
+    Ok((size, format!("{:x}", hasher.finalize())))
+}
+
+/// Returns true if the file is gzip compressed, judging by its name.
+pub fn is_gzip(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "gz")
+}
+
+/// Checks that the gzip file decompresses completely, including its trailing checksum.
+pub fn check_gzip(path: &Path) -> Result<()> {
+    let mut decoder = GzDecoder::new(BufReader::new(File::open(path)?));
+    io::copy(&mut decoder, &mut io::sink())
+        .map_err(|e| anyhow!("{} is not a complete gzip stream: {}", path.display(), e))?;
+
+    Ok(())
+}
+
+// -- Tests -------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use std::{fs, io::Write};
+
+    use flate2::{write::GzEncoder, Compression};
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn should_digest_file() {
+        let tmp_dir = TempDir::new().unwrap();
+        let path = tmp_dir.path().join("stations.txt");
+        fs::write(&path, "abc").unwrap();
+
+        let (size, sha256) = digest_file(&path).unwrap();
+
+        assert_eq!(size, 3);
+        assert_eq!(
+            sha256,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn should_detect_truncated_gzip() {
+        let tmp_dir = TempDir::new().unwrap();
+        let path = tmp_dir.path().join("archive.tar.gz");
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&[7; 4096]).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        fs::write(&path, &compressed).unwrap();
+        assert!(is_gzip(&path));
+        assert!(check_gzip(&path).is_ok());
+
+        fs::write(&path, &compressed[..compressed.len() - 4]).unwrap();
+        assert!(check_gzip(&path).is_err());
+    }
+}
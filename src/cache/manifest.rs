@@ -0,0 +1,137 @@
+//! Record of what is in the cache, stored as `manifest.json` in the cache directory.
+
+use std::{collections::BTreeMap, fs, path::Path};
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::download::Validators;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+/// The cached downloads, keyed by URL.
+pub struct Manifest {
+    pub entries: BTreeMap<String, Entry>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+/// What is known about one cached download.
+pub struct Entry {
+    /// `ETag` response header of the download
+    pub etag: Option<String>,
+    /// `Last-Modified` response header of the download
+    pub last_modified: Option<String>,
+    /// when the file was downloaded
+    pub downloaded_at: Option<DateTime<Utc>>,
+    /// size of the download in bytes
+    pub size: Option<u64>,
+    /// hex encoded SHA-256 digest of the download
+    pub sha256: Option<String>,
+    /// modification time of the download when its digest was last checked
+    pub modified: Option<DateTime<Utc>>,
+    /// set once the download has been extracted completely
+    pub extracted: Option<Extraction>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// Marks a complete extraction of a downloaded archive.
+pub struct Extraction {
+    /// SHA-256 digest of the archive that was extracted
+    pub archive_sha256: Option<String>,
+    /// when the extraction completed
+    pub completed_at: DateTime<Utc>,
+}
+
+impl Entry {
+    /// Returns true if the extraction on record belongs to the current download.
+    pub fn is_extracted(&self) -> bool {
+        self.extracted
+            .as_ref()
+            .is_some_and(|extraction| extraction.archive_sha256 == self.sha256)
+    }
+
+    /// Returns the validators to revalidate the download with.
+    pub fn validators(&self) -> Validators {
+        Validators {
+            etag: self.etag.clone(),
+            last_modified: self.last_modified.clone(),
+        }
+    }
+}
+
+impl Manifest {
+    /// Loads the manifest, or an empty one if the file doesn't exist yet.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Manifest::default());
+        }
+
+        let json = fs::read_to_string(path)?;
+        serde_json::from_str(&json)
+            .map_err(|e| anyhow!("Invalid cache manifest {}: {}", path.display(), e))
+    }
+
+    /// Writes the manifest, replacing the file in one step so readers never see a partial one.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let temp_path = path.with_extension("json.tmp");
+        fs::write(&temp_path, serde_json::to_string_pretty(self)?)?;
+        fs::rename(temp_path, path)?;
+
+        Ok(())
+    }
+}
+
+// -- Tests -------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn should_round_trip_manifest() {
+        let tmp_dir = TempDir::new().unwrap();
+        let path = tmp_dir.path().join("manifest.json");
+        assert_eq!(Manifest::load(&path).unwrap(), Manifest::default());
+
+        let mut manifest = Manifest::default();
+        manifest.entries.insert(
+            "https://example.com/a.tar.gz".to_string(),
+            Entry {
+                etag: Some("\"abc\"".to_string()),
+                last_modified: Some("Tue, 16 Jul 2024 10:00:00 GMT".to_string()),
+                downloaded_at: Some(Utc::now()),
+                size: Some(1024),
+                sha256: Some("ba7816bf".to_string()),
+                modified: Some(Utc::now()),
+                extracted: Some(Extraction {
+                    archive_sha256: Some("ba7816bf".to_string()),
+                    completed_at: Utc::now(),
+                }),
+            },
+        );
+        manifest.save(&path).unwrap();
+
+        assert_eq!(Manifest::load(&path).unwrap(), manifest);
+    }
+
+    #[test]
+    fn should_load_manifest_without_integrity_fields() {
+        let tmp_dir = TempDir::new().unwrap();
+        let path = tmp_dir.path().join("manifest.json");
+        fs::write(
+            &path,
+            r#"{"entries":{"https://example.com/a.tar.gz":{"etag":"\"abc\""}}}"#,
+        )
+        .unwrap();
+
+        let manifest = Manifest::load(&path).unwrap();
+
+        let entry = &manifest.entries["https://example.com/a.tar.gz"];
+        assert_eq!(entry.etag.as_deref(), Some("\"abc\""));
+        assert_eq!(entry.sha256, None);
+        assert!(!entry.is_extracted());
+    }
+}
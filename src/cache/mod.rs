@@ -0,0 +1,824 @@
+//! Cache of downloaded files and their extracted contents, keyed by URL.
+//!
+//! Every download goes through the cache. With `--cache` it lives in the user's cache directory
+//! and is reused across runs, otherwise in a temporary directory removed when the run ends.
+//! Cached downloads are revalidated with the server before use, see [`CacheMode`], and checked
+//! against the size and SHA-256 digest recorded in the manifest; a damaged download is fetched
+//! again and an incomplete extraction redone. The digest is only recomputed when the download's
+//! modification time has changed since it was last checked, or by `cache verify`.
+
+pub mod integrity;
+pub mod manifest;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use indicatif::ProgressBar;
+use reqwest::Url;
+use sha2::{Digest, Sha256};
+use tempfile::TempDir;
+
+use crate::download::{
+    download, extract_tar, extract_tar_with_progress, partial_download_paths, Download,
+    RetryPolicy, Validators,
+};
+use integrity::{check_gzip, digest_file, is_gzip, Integrity};
+use manifest::{Entry, Extraction, Manifest};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// How cached downloads are used.
+pub enum CacheMode {
+    /// Use cached downloads the server reports unchanged, re-downloading changed ones.
+    #[default]
+    Revalidate,
+    /// Download everything again.
+    Refresh,
+    /// Only use cached downloads, never contact the server.
+    Offline,
+}
+
+pub struct Cache {
+    root: PathBuf,
+    mode: CacheMode,
+    retry_policy: RetryPolicy,
+    manifest: Mutex<Manifest>,
+    // Keeps a non-persistent cache alive until the run ends.
+    _temp_dir: Option<TempDir>,
+}
+
+impl Cache {
+    /// Creates the persistent cache in the user's cache directory, or a temporary one.
+    pub fn new(persistent: bool, mode: CacheMode) -> Result<Self> {
+        if !persistent {
+            let temp_dir = TempDir::new()?;
+            let mut cache = Cache::at(temp_dir.path().to_path_buf(), mode)?;
+            cache._temp_dir = Some(temp_dir);
+            return Ok(cache);
+        }
+
+        let root = dirs::cache_dir()
+            .ok_or_else(|| anyhow!("Could not determine cache directory"))?
+            .join("ushcn");
+
+        Cache::at(root, mode)
+    }
+
+    /// Creates a cache rooted at the given directory.
+    pub fn at(root: PathBuf, mode: CacheMode) -> Result<Self> {
+        let manifest = Manifest::load(&root.join(MANIFEST_FILE_NAME))?;
+
+        Ok(Cache {
+            root,
+            mode,
+            retry_policy: RetryPolicy::default(),
+            manifest: Mutex::new(manifest),
+            _temp_dir: None,
+        })
+    }
+
+    /// Sets how downloads are retried and timed out.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Returns the cache directory.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns the path a URL is downloaded to, mirroring its host, port, query and path.
+    pub fn download_path(&self, url: &str) -> Result<PathBuf> {
+        Ok(self.root.join("downloads").join(url_key(url)?))
+    }
+
+    /// Returns the directory the archive at a URL is extracted to.
+    pub fn extraction_path(&self, url: &str) -> Result<PathBuf> {
+        Ok(self.root.join("extracted").join(url_key(url)?))
+    }
+
+    /// Returns true if the URL has been downloaded.
+    pub fn contains(&self, url: &str) -> bool {
+        self.download_path(url).is_ok_and(|path| path.exists())
+    }
+
+    /// Returns true if the archive at the URL has been extracted completely.
+    pub fn is_extracted(&self, url: &str) -> bool {
+        self.verify_extraction(url)
+            .is_ok_and(|integrity| integrity == Integrity::Intact)
+    }
+
+    /// Returns the path of the downloaded file. A cached download is used if the server reports
+    /// it unchanged, or without asking in offline mode; otherwise the file is downloaded. A cached
+    /// download that doesn't match the manifest is downloaded again.
+    pub async fn fetch(&self, url: &str, progress_bar: Option<ProgressBar>) -> Result<PathBuf> {
+        if let Some(local_path) = local_file_path(url)? {
+            return self.fetch_local(url, local_path);
+        }
+
+        let file_path = self.download_path(url)?;
+        let integrity = self.check_download(url)?;
+        if let (Integrity::Corrupt(reason), false) = (&integrity, self.mode == CacheMode::Offline) {
+            eprintln!(
+                "Cached {} is corrupt ({}), downloading it again",
+                url, reason
+            );
+        }
+
+        let validators = match (self.mode, integrity) {
+            (CacheMode::Offline, Integrity::Intact) => return Ok(file_path),
+            (CacheMode::Offline, Integrity::Missing) => {
+                return Err(anyhow!(
+                    "{} is not in the cache at {}, it can't be downloaded with --offline, --from-archive or --from-dir",
+                    url,
+                    self.root.display()
+                ))
+            }
+            (CacheMode::Offline, Integrity::Corrupt(reason)) => {
+                return Err(anyhow!(
+                    "Cached {} is corrupt ({}), run without --offline to download it again",
+                    url,
+                    reason
+                ))
+            }
+            (CacheMode::Revalidate, Integrity::Intact) => self.entry(url).validators(),
+            _ => Validators::default(),
+        };
+
+        create_parent_dir(&file_path)?;
+        let result = download(
+            url,
+            &file_path,
+            &validators,
+            progress_bar.as_ref(),
+            &self.retry_policy,
+        )
+        .await;
+
+        match result {
+            Ok(Download::NotModified) => Ok(file_path),
+            Ok(Download::Complete(validators)) => {
+                // The extracted contents belong to the previous version
+                let extraction_dir = self.extraction_path(url)?;
+                if extraction_dir.exists() {
+                    fs::remove_dir_all(&extraction_dir)?;
+                }
+
+                if is_gzip(&file_path) {
+                    if let Err(e) = check_gzip(&file_path) {
+                        fs::remove_file(&file_path)?;
+                        return Err(anyhow!("Downloaded {} is damaged: {}", url, e));
+                    }
+                }
+                let (size, sha256) = digest_file(&file_path)?;
+                let modified = modified_time(&file_path)?;
+
+                self.update_entry(url, |entry| {
+                    entry.etag = validators.etag;
+                    entry.last_modified = validators.last_modified;
+                    entry.downloaded_at = Some(Utc::now());
+                    entry.size = Some(size);
+                    entry.sha256 = Some(sha256);
+                    entry.modified = Some(modified);
+                    entry.extracted = None;
+                })?;
+
+                Ok(file_path)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Uses a local file in place, recording its digest so a changed file is extracted again.
+    fn fetch_local(&self, url: &str, file_path: PathBuf) -> Result<PathBuf> {
+        if !file_path.is_file() {
+            return Err(anyhow!("{} does not exist", file_path.display()));
+        }
+
+        let (size, sha256) = digest_file(&file_path)?;
+        if self.entry(url).sha256.as_ref() != Some(&sha256) {
+            let extraction_dir = self.extraction_path(url)?;
+            if extraction_dir.exists() {
+                fs::remove_dir_all(&extraction_dir)?;
+            }
+
+            self.update_entry(url, |entry| {
+                entry.size = Some(size);
+                entry.sha256 = Some(sha256);
+                entry.extracted = None;
+            })?;
+        }
+
+        Ok(file_path)
+    }
+
+    // Checks the cached download of a URL like `verify_download`, but trusts the recorded digest
+    // while the size and modification time are unchanged, recording the time once the digest
+    // matches.
+    fn check_download(&self, url: &str) -> Result<Integrity> {
+        let file_path = self.download_path(url)?;
+        if !file_path.is_file() {
+            return Ok(Integrity::Missing);
+        }
+
+        let entry = self.entry(url);
+        let modified = modified_time(&file_path)?;
+        if entry.sha256.is_some()
+            && entry.size == Some(fs::metadata(&file_path)?.len())
+            && entry.modified == Some(modified)
+        {
+            return Ok(Integrity::Intact);
+        }
+
+        let integrity = self.verify_download(url)?;
+        if integrity == Integrity::Intact && entry.sha256.is_some() {
+            self.update_entry(url, |entry| entry.modified = Some(modified))?;
+        }
+        Ok(integrity)
+    }
+
+    /// Checks the cached download of a URL against the size and digest in the manifest. A
+    /// download without a recorded digest is only checked to be a complete gzip stream.
+    pub fn verify_download(&self, url: &str) -> Result<Integrity> {
+        let file_path = self.download_path(url)?;
+        if !file_path.is_file() {
+            return Ok(Integrity::Missing);
+        }
+
+        let entry = self.entry(url);
+        let Some(expected_sha256) = entry.sha256 else {
+            if is_gzip(&file_path) {
+                if let Err(e) = check_gzip(&file_path) {
+                    return Ok(Integrity::Corrupt(e.to_string()));
+                }
+            }
+            return Ok(Integrity::Intact);
+        };
+
+        let size = fs::metadata(&file_path)?.len();
+        if let Some(expected_size) = entry.size.filter(|expected| *expected != size) {
+            return Ok(Integrity::Corrupt(format!(
+                "{} bytes, expected {}",
+                size, expected_size
+            )));
+        }
+
+        let (_, sha256) = digest_file(&file_path)?;
+        if sha256 != expected_sha256 {
+            return Ok(Integrity::Corrupt(format!(
+                "SHA-256 {}, expected {}",
+                sha256, expected_sha256
+            )));
+        }
+
+        Ok(Integrity::Intact)
+    }
+
+    /// Checks that the extraction of a URL's archive completed and belongs to the cached download.
+    pub fn verify_extraction(&self, url: &str) -> Result<Integrity> {
+        let extraction_dir = self.extraction_path(url)?;
+        if !extraction_dir.exists() {
+            return Ok(Integrity::Missing);
+        }
+
+        let entry = self.entry(url);
+        if entry.extracted.is_none() {
+            Ok(Integrity::Corrupt(
+                "extraction did not complete".to_string(),
+            ))
+        } else if !entry.is_extracted() {
+            Ok(Integrity::Corrupt(
+                "extracted from a different download".to_string(),
+            ))
+        } else if !is_non_empty_dir(&extraction_dir) {
+            Ok(Integrity::Corrupt("extraction is empty".to_string()))
+        } else {
+            Ok(Integrity::Intact)
+        }
+    }
+
+    /// Returns the URLs in the manifest.
+    pub fn urls(&self) -> Vec<String> {
+        let manifest = self.manifest.lock().unwrap();
+        manifest.entries.keys().cloned().collect()
+    }
+
+    /// Returns the manifest entry of a URL, empty if it has none.
+    pub fn entry(&self, url: &str) -> Entry {
+        let manifest = self.manifest.lock().unwrap();
+        manifest.entries.get(url).cloned().unwrap_or_default()
+    }
+
+    /// Returns the top level directories the archive at the URL extracted to, which name the NOAA
+    /// version, e.g. `ushcn.v2.5.5.20240716`.
+    pub fn versions(&self, url: &str) -> Vec<String> {
+        let Some(entries) = self
+            .extraction_path(url)
+            .ok()
+            .and_then(|path| fs::read_dir(path).ok())
+        else {
+            return Vec::new();
+        };
+
+        let mut versions: Vec<String> = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_dir())
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .collect();
+        versions.sort();
+        versions
+    }
+
+    /// Returns the bytes on disk used by the URL's download and extraction.
+    pub fn disk_usage(&self, url: &str) -> u64 {
+        self.paths(url)
+            .map(|paths| paths.iter().map(|path| disk_usage(path)).sum())
+            .unwrap_or(0)
+    }
+
+    /// Removes the URL's download, unfinished download and extraction from the cache. Returns the
+    /// bytes freed.
+    pub fn remove(&self, url: &str) -> Result<u64> {
+        let mut freed = 0;
+        for path in self.paths(url)? {
+            freed += disk_usage(&path);
+            if path.is_dir() {
+                fs::remove_dir_all(&path)?;
+            } else if path.exists() {
+                fs::remove_file(&path)?;
+            }
+        }
+
+        let mut manifest = self.manifest.lock().unwrap();
+        if manifest.entries.remove(url).is_some() {
+            manifest.save(&self.root.join(MANIFEST_FILE_NAME))?;
+        }
+
+        Ok(freed)
+    }
+
+    /// Removes everything from the cache. Returns the bytes freed.
+    pub fn clear(&self) -> Result<u64> {
+        let mut freed = 0;
+        for dir in ["downloads", "extracted"] {
+            let path = self.root.join(dir);
+            freed += disk_usage(&path);
+            if path.exists() {
+                fs::remove_dir_all(&path)?;
+            }
+        }
+
+        let mut manifest = self.manifest.lock().unwrap();
+        if !manifest.entries.is_empty() {
+            manifest.entries.clear();
+            manifest.save(&self.root.join(MANIFEST_FILE_NAME))?;
+        }
+
+        Ok(freed)
+    }
+
+    // Returns every path the cache may store for the URL.
+    fn paths(&self, url: &str) -> Result<Vec<PathBuf>> {
+        let download_path = self.download_path(url)?;
+        let [part_path, part_validators_path] = partial_download_paths(&download_path);
+
+        Ok(vec![
+            download_path,
+            part_path,
+            part_validators_path,
+            self.extraction_path(url)?,
+        ])
+    }
+
+    // Applies the update to the URL's manifest entry and saves the manifest.
+    fn update_entry(&self, url: &str, update: impl FnOnce(&mut Entry)) -> Result<()> {
+        let mut manifest = self.manifest.lock().unwrap();
+        update(manifest.entries.entry(url.to_string()).or_default());
+
+        fs::create_dir_all(&self.root)?;
+        manifest.save(&self.root.join(MANIFEST_FILE_NAME))
+    }
+
+    /// Returns the directory the downloaded archive was extracted to, extracting it unless a
+    /// complete extraction is cached.
+    pub async fn extract(
+        &self,
+        url: &str,
+        archive_path: &PathBuf,
+        progress_bar: Option<ProgressBar>,
+    ) -> Result<PathBuf> {
+        let extraction_dir = self.extraction_path(url)?;
+        match self.verify_extraction(url)? {
+            Integrity::Intact => return Ok(extraction_dir),
+            Integrity::Corrupt(_) => fs::remove_dir_all(&extraction_dir)?,
+            Integrity::Missing => (),
+        }
+
+        fs::create_dir_all(&extraction_dir)?;
+        match progress_bar {
+            Some(bar) => extract_tar_with_progress(archive_path, &extraction_dir, bar).await?,
+            None => extract_tar(archive_path, &extraction_dir).await?,
+        }
+
+        // Only now is the extraction complete, an interrupted one is redone next time
+        self.update_entry(url, |entry| {
+            entry.extracted = Some(Extraction {
+                archive_sha256: entry.sha256.clone(),
+                completed_at: Utc::now(),
+            })
+        })?;
+
+        Ok(extraction_dir)
+    }
+}
+
+const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+// Turns a URL into a relative path, e.g. `www.ncei.noaa.gov/pub/data/ghcn/daily/ghcnd_hcn.tar.gz`.
+// Keys a URL by its host, port and query, followed by its path segments so that the file keeps
+// its name. The query is hashed as it may hold characters that aren't valid in a file name.
+fn url_key(url: &str) -> Result<PathBuf> {
+    let parsed = Url::parse(url).map_err(|e| anyhow!("Invalid URL '{}': {}", url, e))?;
+
+    let mut origin = parsed.host_str().unwrap_or("local").to_string();
+    if let Some(port) = parsed.port() {
+        origin.push_str(&format!("_{}", port));
+    }
+    if let Some(query) = parsed.query() {
+        let digest = format!("{:x}", Sha256::digest(query.as_bytes()));
+        origin.push_str(&format!("_q{}", &digest[..16]));
+    }
+
+    let mut key = PathBuf::from(origin);
+    for segment in parsed.path().split('/') {
+        if !segment.is_empty() && segment != "." && segment != ".." {
+            key.push(segment);
+        }
+    }
+
+    Ok(key)
+}
+
+fn create_parent_dir(file_path: &Path) -> Result<()> {
+    if let Some(parent) = file_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    Ok(())
+}
+
+// Returns the path of a `file://` URL, or None for other URLs.
+fn local_file_path(url: &str) -> Result<Option<PathBuf>> {
+    let parsed = Url::parse(url).map_err(|e| anyhow!("Invalid URL '{}': {}", url, e))?;
+    if parsed.scheme() != "file" {
+        return Ok(None);
+    }
+
+    parsed
+        .to_file_path()
+        .map(Some)
+        .map_err(|_| anyhow!("Invalid file URL '{}'", url))
+}
+
+/// Returns the bytes used by the file, or by the files in the directory.
+pub fn disk_usage(path: &Path) -> u64 {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::read_dir(path)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok())
+                    .map(|entry| disk_usage(&entry.path()))
+                    .sum()
+            })
+            .unwrap_or(0),
+        Ok(metadata) => metadata.len(),
+        Err(_) => 0,
+    }
+}
+
+fn is_non_empty_dir(path: &Path) -> bool {
+    fs::read_dir(path)
+        .map(|mut entries| entries.next().is_some())
+        .unwrap_or(false)
+}
+
+fn modified_time(path: &Path) -> Result<DateTime<Utc>> {
+    Ok(fs::metadata(path)?.modified()?.into())
+}
+
+// -- Tests -------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::test_server::{serve, Response};
+
+    #[test]
+    fn should_key_paths_by_url() {
+        let cache = Cache::at(PathBuf::from("/nonexistent/cache"), CacheMode::Offline).unwrap();
+        let url = "https://www.ncei.noaa.gov/pub/data/ghcn/daily/ghcnd_hcn.tar.gz";
+
+        assert_eq!(
+            cache.download_path(url).unwrap(),
+            PathBuf::from(
+                "/nonexistent/cache/downloads/www.ncei.noaa.gov/pub/data/ghcn/daily/ghcnd_hcn.tar.gz"
+            )
+        );
+        assert_eq!(
+            cache.extraction_path(url).unwrap(),
+            PathBuf::from(
+                "/nonexistent/cache/extracted/www.ncei.noaa.gov/pub/data/ghcn/daily/ghcnd_hcn.tar.gz"
+            )
+        );
+        assert!(cache.download_path("not a url").is_err());
+    }
+
+    #[test]
+    fn should_key_paths_by_port_and_query() {
+        let cache = Cache::at(PathBuf::from("/nonexistent/cache"), CacheMode::Offline).unwrap();
+        let paths = [
+            "http://127.0.0.1/ushcn.tar.gz",
+            "http://127.0.0.1:8080/ushcn.tar.gz",
+            "http://127.0.0.1:8081/ushcn.tar.gz",
+            "http://127.0.0.1:8081/ushcn.tar.gz?release=20240716",
+            "http://127.0.0.1:8081/ushcn.tar.gz?release=20240718",
+        ]
+        .map(|url| cache.download_path(url).unwrap());
+
+        assert_eq!(
+            paths[1],
+            PathBuf::from("/nonexistent/cache/downloads/127.0.0.1_8080/ushcn.tar.gz")
+        );
+        assert!(paths[3].ends_with("ushcn.tar.gz"));
+        for (i, path) in paths.iter().enumerate() {
+            assert!(!paths[i + 1..].contains(path), "{} is shared", path.display());
+        }
+    }
+
+    #[tokio::test]
+    async fn should_use_cached_download_offline() {
+        let tmp_dir = TempDir::new().unwrap();
+        let cache = Cache::at(tmp_dir.path().to_path_buf(), CacheMode::Offline).unwrap();
+        // an unreachable URL, so only a cache hit succeeds
+        let url = "http://127.0.0.1:9/ushcn-v2.5-stations.txt";
+
+        let error = cache.fetch(url, None).await.unwrap_err();
+        assert!(error.to_string().contains("--offline"));
+
+        let file_path = cache.download_path(url).unwrap();
+        create_parent_dir(&file_path).unwrap();
+        fs::write(&file_path, "cached").unwrap();
+
+        assert!(cache.contains(url));
+        assert_eq!(cache.fetch(url, None).await.unwrap(), file_path);
+    }
+
+    #[tokio::test]
+    async fn should_revalidate_cached_download() {
+        let version = Arc::new(Mutex::new("v1"));
+        let served = Arc::clone(&version);
+        let (base_url, requests) = serve(move |request| {
+            let version = *served.lock().unwrap();
+            let etag = format!("\"{}\"", version);
+            if request.header("If-None-Match") == Some(etag.as_str()) {
+                Response::status(304)
+            } else {
+                Response::ok(version.as_bytes()).with_header("ETag", &etag)
+            }
+        })
+        .await;
+        let tmp_dir = TempDir::new().unwrap();
+        let url = format!("{}/data/stations.txt", base_url);
+
+        let cache = Cache::at(tmp_dir.path().to_path_buf(), CacheMode::Revalidate).unwrap();
+        let file_path = cache.fetch(&url, None).await.unwrap();
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "v1");
+        assert_eq!(cache.entry(&url).etag.as_deref(), Some("\"v1\""));
+        assert_eq!(requests.lock().unwrap()[0].path, "/data/stations.txt");
+        fs::create_dir_all(cache.extraction_path(&url).unwrap().join("stale")).unwrap();
+        cache
+            .update_entry(&url, |entry| {
+                entry.extracted = Some(Extraction {
+                    archive_sha256: entry.sha256.clone(),
+                    completed_at: Utc::now(),
+                })
+            })
+            .unwrap();
+
+        // unchanged: the manifest is reloaded and the cached copy kept
+        let cache = Cache::at(tmp_dir.path().to_path_buf(), CacheMode::Revalidate).unwrap();
+        cache.fetch(&url, None).await.unwrap();
+        assert_eq!(
+            requests.lock().unwrap()[1].header("If-None-Match"),
+            Some("\"v1\"")
+        );
+        assert!(cache.is_extracted(&url));
+
+        // changed: downloaded again and the stale extraction removed
+        *version.lock().unwrap() = "v2";
+        cache.fetch(&url, None).await.unwrap();
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "v2");
+        assert_eq!(cache.entry(&url).etag.as_deref(), Some("\"v2\""));
+        assert!(!cache.is_extracted(&url));
+
+        // refresh downloads without validators
+        let cache = Cache::at(tmp_dir.path().to_path_buf(), CacheMode::Refresh).unwrap();
+        cache.fetch(&url, None).await.unwrap();
+        assert_eq!(requests.lock().unwrap()[3].header("If-None-Match"), None);
+    }
+
+    #[tokio::test]
+    async fn should_extract_archive_once() {
+        let tmp_dir = TempDir::new().unwrap();
+        let cache = Cache::at(tmp_dir.path().to_path_buf(), CacheMode::Offline).unwrap();
+        let url = "https://example.com/data/archive.tar.gz";
+        let archive_path = tmp_dir.path().join("archive.tar.gz");
+        write_archive(&archive_path, "ushcn.v2.5.5.20240716/USH00011084.raw.tmax");
+
+        assert!(!cache.is_extracted(url));
+        let extraction_dir = cache.extract(url, &archive_path, None).await.unwrap();
+        assert!(extraction_dir
+            .join("ushcn.v2.5.5.20240716/USH00011084.raw.tmax")
+            .is_file());
+
+        // a cached extraction is reused without reading the archive
+        fs::remove_file(&archive_path).unwrap();
+        assert_eq!(
+            cache.extract(url, &archive_path, None).await.unwrap(),
+            extraction_dir
+        );
+    }
+
+    #[tokio::test]
+    async fn should_download_corrupt_file_again() {
+        let (base_url, requests) =
+            serve(|_| Response::ok(b"stations").with_header("ETag", "\"v1\"")).await;
+        let tmp_dir = TempDir::new().unwrap();
+        let url = format!("{}/stations.txt", base_url);
+
+        let cache = Cache::at(tmp_dir.path().to_path_buf(), CacheMode::Revalidate).unwrap();
+        let file_path = cache.fetch(&url, None).await.unwrap();
+        assert_eq!(cache.entry(&url).size, Some(8));
+        assert_eq!(cache.verify_download(&url).unwrap(), Integrity::Intact);
+
+        fs::write(&file_path, "statioms").unwrap();
+        assert!(matches!(
+            cache.verify_download(&url).unwrap(),
+            Integrity::Corrupt(reason) if reason.starts_with("SHA-256")
+        ));
+
+        let offline = Cache::at(tmp_dir.path().to_path_buf(), CacheMode::Offline).unwrap();
+        let error = offline.fetch(&url, None).await.unwrap_err();
+        assert!(error.to_string().contains("corrupt"));
+
+        // not revalidated, since the cached copy can't be kept
+        cache.fetch(&url, None).await.unwrap();
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "stations");
+        assert_eq!(requests.lock().unwrap()[1].header("If-None-Match"), None);
+    }
+
+    #[tokio::test]
+    async fn should_only_digest_download_with_changed_modification_time() {
+        let (base_url, _) =
+            serve(|_| Response::ok(b"stations").with_header("ETag", "\"v1\"")).await;
+        let tmp_dir = TempDir::new().unwrap();
+        let url = format!("{}/stations.txt", base_url);
+
+        let cache = Cache::at(tmp_dir.path().to_path_buf(), CacheMode::Revalidate).unwrap();
+        let file_path = cache.fetch(&url, None).await.unwrap();
+        let modified = cache.entry(&url).modified.unwrap();
+
+        // a damaged file with the recorded size and time passes the quick check, not `verify`
+        fs::write(&file_path, "statioms").unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&file_path)
+            .unwrap()
+            .set_modified(modified.into())
+            .unwrap();
+        let offline = Cache::at(tmp_dir.path().to_path_buf(), CacheMode::Offline).unwrap();
+        assert_eq!(offline.check_download(&url).unwrap(), Integrity::Intact);
+        assert!(matches!(offline.verify_download(&url).unwrap(), Integrity::Corrupt(_)));
+
+        // a touched but intact file is digested once and its new time recorded
+        fs::write(&file_path, "stations").unwrap();
+        assert_eq!(offline.check_download(&url).unwrap(), Integrity::Intact);
+        assert_eq!(
+            offline.entry(&url).modified,
+            Some(modified_time(&file_path).unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn should_reject_truncated_archive() {
+        let tmp_dir = TempDir::new().unwrap();
+        let archive_path = tmp_dir.path().join("archive.tar.gz");
+        write_archive(&archive_path, "ushcn.v2.5.5.20240716/USH00011084.raw.tmax");
+        let archive = fs::read(&archive_path).unwrap();
+        let truncated = archive[..archive.len() / 2].to_vec();
+        let (base_url, _) = serve(move |_| Response::ok(&truncated)).await;
+        let url = format!("{}/archive.tar.gz", base_url);
+
+        let cache = Cache::at(tmp_dir.path().join("cache"), CacheMode::Revalidate).unwrap();
+        let error = cache.fetch(&url, None).await.unwrap_err();
+
+        assert!(error.to_string().contains("damaged"));
+        assert!(!cache.contains(&url));
+    }
+
+    #[tokio::test]
+    async fn should_extract_incomplete_extraction_again() {
+        let tmp_dir = TempDir::new().unwrap();
+        let cache = Cache::at(tmp_dir.path().to_path_buf(), CacheMode::Offline).unwrap();
+        let url = "https://example.com/data/archive.tar.gz";
+        let archive_path = tmp_dir.path().join("archive.tar.gz");
+        write_archive(&archive_path, "ushcn.v2.5.5.20240716/USH00011084.raw.tmax");
+
+        // left behind by an interrupted extraction
+        let extraction_dir = cache.extraction_path(url).unwrap();
+        fs::create_dir_all(extraction_dir.join("ushcn.v2.5.5.20240716")).unwrap();
+        assert!(matches!(
+            cache.verify_extraction(url).unwrap(),
+            Integrity::Corrupt(_)
+        ));
+        assert!(!cache.is_extracted(url));
+
+        cache.extract(url, &archive_path, None).await.unwrap();
+
+        assert!(extraction_dir
+            .join("ushcn.v2.5.5.20240716/USH00011084.raw.tmax")
+            .is_file());
+        assert_eq!(cache.verify_extraction(url).unwrap(), Integrity::Intact);
+    }
+
+    #[tokio::test]
+    async fn should_report_and_remove_cached_archive() {
+        let tmp_dir = TempDir::new().unwrap();
+        let cache = Cache::at(tmp_dir.path().to_path_buf(), CacheMode::Offline).unwrap();
+        let url = "https://example.com/data/archive.tar.gz";
+        let archive_path = cache.download_path(url).unwrap();
+        create_parent_dir(&archive_path).unwrap();
+        write_archive(&archive_path, "ushcn.v2.5.5.20240716/USH00011084.raw.tmax");
+        cache.extract(url, &archive_path, None).await.unwrap();
+
+        assert_eq!(cache.urls(), vec![url.to_string()]);
+        assert_eq!(cache.versions(url), vec!["ushcn.v2.5.5.20240716"]);
+        let archive_size = fs::metadata(&archive_path).unwrap().len();
+        // the archive plus the 24 byte extracted file
+        assert_eq!(cache.disk_usage(url), archive_size + 24);
+
+        assert_eq!(cache.remove(url).unwrap(), archive_size + 24);
+        assert!(!cache.contains(url));
+        assert!(!cache.extraction_path(url).unwrap().exists());
+        assert!(cache.urls().is_empty());
+        assert!(Manifest::load(&tmp_dir.path().join(MANIFEST_FILE_NAME))
+            .unwrap()
+            .entries
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_use_local_file_in_place() {
+        let tmp_dir = TempDir::new().unwrap();
+        let cache = Cache::at(tmp_dir.path().join("cache"), CacheMode::Offline).unwrap();
+        let archive_path = tmp_dir.path().join("ghcnd_hcn.tar.gz");
+        write_archive(&archive_path, "ghcnd_hcn/USC00011084.dly");
+        let url = Url::from_file_path(&archive_path).unwrap().to_string();
+
+        assert_eq!(cache.fetch(&url, None).await.unwrap(), archive_path);
+        cache.extract(&url, &archive_path, None).await.unwrap();
+        assert!(cache.is_extracted(&url));
+        assert!(!cache.contains(&url));
+
+        // a changed file is extracted again
+        write_archive(&archive_path, "ghcnd_hcn/USC00011085.dly");
+        cache.fetch(&url, None).await.unwrap();
+        assert!(!cache.is_extracted(&url));
+
+        fs::remove_file(&archive_path).unwrap();
+        let error = cache.fetch(&url, None).await.unwrap_err();
+        assert!(error.to_string().contains("does not exist"));
+    }
+
+    fn write_archive(path: &Path, entry_path: &str) {
+        let file = fs::File::create(path).unwrap();
+        let encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+        let mut builder = tar::Builder::new(encoder);
+        let data = b"USH00011084 1895  1234  ";
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, entry_path, &data[..])
+            .unwrap();
+        builder.into_inner().unwrap().finish().unwrap();
+    }
+}
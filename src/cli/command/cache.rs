@@ -0,0 +1,192 @@
+use anyhow::{anyhow, Result};
+use indicatif::HumanBytes;
+
+use crate::{
+    cache::{disk_usage, integrity::Integrity, Cache},
+    cli::CleanArgs,
+};
+
+/// Prints each cached download with its download time, NOAA version and disk usage.
+pub fn list(cache: &Cache) -> Result<String> {
+    let urls = cache.urls();
+    if urls.is_empty() {
+        return Ok(format!("The cache at {} is empty", cache.root().display()));
+    }
+
+    println!(
+        "{:<16}  {:<24}  {:>10}  URL",
+        "DOWNLOADED", "VERSION", "SIZE"
+    );
+    for url in &urls {
+        let downloaded_at = cache
+            .entry(url)
+            .downloaded_at
+            .map(|time| time.format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_else(|| "-".to_string());
+        let versions = cache.versions(url);
+        let version = if versions.is_empty() {
+            "-".to_string()
+        } else {
+            versions.join(",")
+        };
+
+        println!(
+            "{:<16}  {:<24}  {:>10}  {}",
+            downloaded_at,
+            version,
+            HumanBytes(cache.disk_usage(url)).to_string(),
+            url
+        );
+    }
+
+    Ok(format!("{} cached downloads", urls.len()))
+}
+
+/// Returns the disk usage of the cache.
+pub fn size(cache: &Cache) -> Result<String> {
+    Ok(format!(
+        "{} in {} cached downloads at {}",
+        HumanBytes(disk_usage(cache.root())),
+        cache.urls().len(),
+        cache.root().display()
+    ))
+}
+
+/// Removes the selected cached downloads, or everything.
+pub fn clean(cache: &Cache, args: &CleanArgs) -> Result<String> {
+    if args.all {
+        let freed = cache.clear()?;
+        return Ok(format!("Removed everything, freeing {}", HumanBytes(freed)));
+    }
+
+    let selected: Vec<String> = cache
+        .urls()
+        .into_iter()
+        .filter(|url| {
+            args.urls
+                .iter()
+                .any(|pattern| url.contains(pattern.as_str()))
+        })
+        .collect();
+    if selected.is_empty() {
+        return Ok(format!(
+            "No cached download matches {}",
+            args.urls.join(", ")
+        ));
+    }
+
+    let mut freed = 0;
+    for url in &selected {
+        freed += cache.remove(url)?;
+        println!("Removed {}", url);
+    }
+
+    Ok(format!(
+        "Removed {} cached downloads, freeing {}",
+        selected.len(),
+        HumanBytes(freed)
+    ))
+}
+
+/// Returns the cache directory.
+pub fn path(cache: &Cache) -> Result<String> {
+    Ok(cache.root().display().to_string())
+}
+
+/// Checks every cached download and extraction, failing if any is damaged.
+pub fn verify(cache: &Cache) -> Result<String> {
+    let urls = cache.urls();
+    let mut damaged = 0;
+
+    for url in &urls {
+        let download = cache.verify_download(url)?;
+        let extraction = cache.verify_extraction(url)?;
+        let problems: Vec<String> = [("download", download), ("extraction", extraction)]
+            .into_iter()
+            .filter_map(|(what, integrity)| match integrity {
+                Integrity::Corrupt(reason) => Some(format!("{}: {}", what, reason)),
+                _ => None,
+            })
+            .collect();
+
+        if problems.is_empty() {
+            println!("✓ {}", url);
+        } else {
+            damaged += 1;
+            println!("✗ {} ({})", url, problems.join(", "));
+        }
+    }
+
+    if damaged > 0 {
+        return Err(anyhow!(
+            "{} of {} cached downloads are damaged, they are downloaded or extracted again on the next run",
+            damaged,
+            urls.len()
+        ));
+    }
+
+    Ok(format!("Verified {} cached downloads", urls.len()))
+}
+
+// -- Tests -------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{
+        cache::CacheMode,
+        test_server::{serve, Response},
+    };
+
+    #[tokio::test]
+    async fn should_verify_cache() {
+        let (base_url, _) = serve(|_| Response::ok(b"stations")).await;
+        let tmp_dir = TempDir::new().unwrap();
+        let url = format!("{}/stations.txt", base_url);
+
+        let cache = Cache::at(tmp_dir.path().to_path_buf(), CacheMode::Revalidate).unwrap();
+        let file_path = cache.fetch(&url, None).await.unwrap();
+        assert_eq!(verify(&cache).unwrap(), "Verified 1 cached downloads");
+
+        fs::write(&file_path, "truncated").unwrap();
+        let error = verify(&cache).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("1 of 1 cached downloads are damaged"));
+    }
+
+    #[tokio::test]
+    async fn should_clean_selected_downloads() {
+        let (base_url, _) = serve(|_| Response::ok(b"stations")).await;
+        let tmp_dir = TempDir::new().unwrap();
+        let ushcn_url = format!("{}/ushcn/stations.txt", base_url);
+        let ghcn_url = format!("{}/ghcn/stations.txt", base_url);
+
+        let cache = Cache::at(tmp_dir.path().to_path_buf(), CacheMode::Revalidate).unwrap();
+        cache.fetch(&ushcn_url, None).await.unwrap();
+        cache.fetch(&ghcn_url, None).await.unwrap();
+        assert!(size(&cache).unwrap().contains(" in 2 cached downloads at "));
+
+        let args = CleanArgs {
+            urls: vec!["ghcn/".to_string()],
+            all: false,
+        };
+        assert_eq!(
+            clean(&cache, &args).unwrap(),
+            "Removed 1 cached downloads, freeing 8 B"
+        );
+        assert_eq!(cache.urls(), vec![ushcn_url]);
+
+        let args = CleanArgs {
+            all: true,
+            ..CleanArgs::default()
+        };
+        clean(&cache, &args).unwrap();
+        assert!(cache.urls().is_empty());
+        assert!(!tmp_dir.path().join("downloads").exists());
+    }
+}
@@ -2,109 +2,163 @@ use std::{
     collections::HashMap,
     fs::{self},
     path::{Path, PathBuf},
+    time::Instant,
 };
 
-use dirs;
-
 use anyhow::{anyhow, Result};
+use futures::StreamExt;
 
 use crate::{
-    cli::{command::stations::Station, create_indeterminate_progress_bar},
-    deserialise::deserialise,
-    download::{download_tar_with_progress, extract_tar_with_progress},
-    parquet,
-    reading::{DailyReading, Dataset},
+    cache::Cache,
+    cli::{
+        command::stations::{make_state_lookup, Station},
+        create_indeterminate_progress_bar, create_progress_bar, DailyArgs,
+    },
+    deserialise::{deserialise, deserialise_archives, list_files},
+    parquet::{DailyWriter, Partition, PartitionKey},
+    reading::{DailyReading, ReadingFilter},
+    report::{DatasetReport, DatasetStats, Report, SourceReport},
+    sources::Sources,
 };
 
-use super::make_parquet_file_name;
-
-pub async fn daily(use_persistent_cache: bool, stations: &[Station]) -> Result<String> {
-    let cache_dir = get_cache_dir(use_persistent_cache)?;
-
-    // Download and extract daily archive
-    let daily_archive_filepath = download_archive_cached(&cache_dir).await?;
-    let archive_dir = extract_archive_cached(&daily_archive_filepath, &cache_dir).await?;
+use super::{
+    create_output_writer, download_version, make_parquet_file_name, report_rejected_lines,
+    OutputConfig, ParseConfig,
+};
 
-    // Deserialize readings and inject coordinates
-    let mut readings = deserialise(&archive_dir).await?;
-    readings = inject_coords(readings, stations.to_vec())?;
+pub async fn daily(
+    cache: &Cache,
+    sources: &Sources,
+    output: &OutputConfig,
+    parse: &ParseConfig,
+    args: &DailyArgs,
+    stations: &[Station],
+    report: &Report,
+) -> Result<String> {
+    let start = Instant::now();
+
+    // Download daily archive
+    let url = sources.daily_archive_url();
+    let daily_archive_filepath = download_archive_cached(cache, &url).await?;
+    // GHCN daily files are one per station, so the files of unselected stations are skipped
+    let filter = ReadingFilter::new(args.elements.clone())
+        .with_period(parse.start, parse.end)
+        .with_stations(parse.select_stations(stations, report)?);
+
+    // Read the archive as it is decompressed, or extract it once to the cache and read the files
+    let (pb, mut readings) = if parse.stream_archives {
+        let pb = create_indeterminate_progress_bar("Processing archive files".to_string());
+        let archives = vec![daily_archive_filepath];
+        let readings = deserialise_archives::<DailyReading>(
+            archives,
+            &filter,
+            parse.jobs,
+            parse.policy,
+            pb.clone(),
+        );
+        (pb, readings.boxed())
+    } else {
+        let archive_dir = extract_archive_cached(cache, &url, &daily_archive_filepath).await?;
+        let files = list_files(&[archive_dir], &filter)?;
+        let pb = create_progress_bar(files.len() as u64, "Processing files".to_string());
+        let readings = deserialise::<DailyReading>(
+            files,
+            &filter,
+            parse.jobs,
+            parse.policy,
+            pb.clone(),
+        );
+        (pb, readings.boxed())
+    };
 
-    // Create single daily parquet file (GHCN daily data is not separated by dataset type)
-    let parquet_file_name = make_parquet_file_name("daily");
-    parquet::save_daily(&readings, &parquet_file_name)?;
-    
-    println!("✓ Created daily parquet file with {} readings", readings.len());
+    // Create single daily parquet file (GHCN daily data is not separated by dataset type), or a
+    // directory partitioned by --partition-by
+    let version = download_version(cache, &url);
+    let parquet_file_name = make_parquet_file_name(output, "daily", version.as_deref())?;
+    let (drop_qc_failed, units) = (args.drop_qc_failed, output.units);
+    let create_writer = move |path: &Path, partition_by: &[PartitionKey]| {
+        DailyWriter::create(path, drop_qc_failed, units, partition_by)
+    };
+    let (output_path, mut writer) =
+        create_output_writer(&parquet_file_name, &output.partition_by, create_writer)?;
+
+    // Stream readings file by file, injecting coordinates, into the parquet file
+    let lookup = make_lookup(stations);
+    let states = make_state_lookup(stations);
+    let mut stats = DatasetStats::default();
+    let mut rejected = Vec::new();
+    let mut failed_files = Vec::new();
+    while let Some(parsed_file) = readings.next().await {
+        let parsed_file = parsed_file?;
+        rejected.extend(parsed_file.rejected);
+        failed_files.extend(parsed_file.failed);
+        for mut reading in parsed_file.readings {
+            // The report counts what is written, so readings left empty by --drop-qc-failed are
+            // skipped
+            if !reading.has_values(drop_qc_failed) {
+                continue;
+            }
+            inject_coords(&mut reading, &lookup);
+            stats.record(&reading.id, reading.year, reading.month, reading.lat.is_some());
+            let partition = Partition {
+                dataset: &reading.properties.dataset,
+                element: &reading.properties.element,
+                year: reading.year,
+                state: states.get(&reading.id).map(String::as_str),
+            };
+            writer.write(&reading, &partition)?;
+        }
+    }
+    pb.finish_with_message("Processing complete");
+    report_rejected_lines(output, "daily", version.as_deref(), &rejected)?;
+
+    let rows = writer.close()?;
+    println!("✓ Created daily parquet file with {} rows", rows);
+    report.add_dataset(DatasetReport {
+        rejected_lines: rejected.len(),
+        failed_files,
+        sources: vec![SourceReport::from_cache(cache, &url)],
+        seconds: start.elapsed().as_secs_f64(),
+        ..DatasetReport::new("daily", &output_path, rows, &stats)
+    });
 
     Ok(format!("Created 1 daily file: {}", 
-              parquet_file_name.to_string_lossy()))
-}
-
-fn dataset_to_string(dataset: &Dataset) -> String {
-    match dataset {
-        Dataset::Raw => "RAW".to_string(),
-        Dataset::Tob => "TOB".to_string(),
-        Dataset::Fls52 => "FLS52".to_string(),
-        _ => "UNKNOWN".to_string(),
-    }
+              output_path.to_string_lossy()))
 }
 
-fn get_cache_dir(use_persistent_cache: bool) -> Result<PathBuf> {
-    let cache_dir = if use_persistent_cache {
-        // Use persistent cache in Library directory
-        dirs::cache_dir()
-            .ok_or_else(|| anyhow!("Could not determine cache directory"))?
-            .join("ushcn")
+async fn download_archive_cached(cache: &Cache, url: &str) -> Result<PathBuf> {
+    // A cached archive is only downloaded again if NOAA has changed it
+    let message = if cache.contains(url) {
+        "Checking cached daily archive for updates..."
     } else {
-        // Use temporary directory
-        let temp_dir = std::env::temp_dir().join("ushcn");
-        temp_dir
+        "Downloading daily archive..."
     };
-    
-    if !cache_dir.exists() {
-        fs::create_dir_all(&cache_dir)?;
-    }
-    
-    Ok(cache_dir)
-}
-
-async fn download_archive_cached(cache_dir: &Path) -> Result<PathBuf> {
-    let url = "https://www.ncei.noaa.gov/pub/data/ghcn/daily/ghcnd_hcn.tar.gz";
-    let file_name = url.split('/').last().unwrap();
-    let file_path = cache_dir.join(file_name);
-
-    // Check if cached file exists
-    if file_path.exists() {
-        println!("✓ Using cached daily archive: {}", file_path.display());
-        return Ok(file_path);
-    }
 
-    let bar = create_indeterminate_progress_bar("Downloading daily archive...".to_string());
-    download_tar_with_progress(url, file_path.clone(), bar.clone()).await?;
-    bar.finish_with_message("✓ Daily archive downloaded and cached");
+    let bar = create_indeterminate_progress_bar(message.to_string());
+    let file_path = cache.fetch(url, Some(bar.clone())).await?;
+    bar.finish_with_message(format!("✓ Daily archive cached: {}", file_path.display()));
 
     Ok(file_path)
 }
 
-
-async fn extract_archive_cached(archive_filepath: &PathBuf, cache_dir: &Path) -> Result<PathBuf> {
-    // Create extraction parent directory in cache
-    let extraction_parent = cache_dir.join("extracted");
-    
+async fn extract_archive_cached(
+    cache: &Cache,
+    url: &str,
+    archive_filepath: &PathBuf,
+) -> Result<PathBuf> {
     // Check if already extracted (look for the actual extracted directory)
-    if extraction_parent.exists() {
+    if cache.is_extracted(url) {
+        let extraction_parent = cache.extract(url, archive_filepath, None).await?;
         if let Ok(existing_dir) = get_archive_dir(&extraction_parent) {
             println!("✓ Using cached extracted daily archive: {}", existing_dir.display());
             return Ok(existing_dir);
         }
     }
 
-    // Ensure extraction parent directory exists
-    if !extraction_parent.exists() {
-        fs::create_dir_all(&extraction_parent)?;
-    }
-
     let bar = create_indeterminate_progress_bar("Extracting daily archive files...".to_string());
-    extract_tar_with_progress(archive_filepath, &extraction_parent, bar.clone()).await?;
+    let extraction_parent = cache
+        .extract(url, archive_filepath, Some(bar.clone()))
+        .await?;
     bar.finish_with_message("✓ Daily archive extracted and cached");
 
     let final_dir = get_archive_dir(&extraction_parent)?;
@@ -136,26 +190,16 @@ fn get_archive_dir(archive_dir: &Path) -> Result<PathBuf> {
     Ok(directories[0].clone())
 }
 
-fn inject_coords(readings: Vec<DailyReading>, stations: Vec<Station>) -> Result<Vec<DailyReading>> {
-    let mut readings_with_coords = Vec::new();
-    let lookup = make_lookup(&stations);
-
-    println!("Injecting coords into readings");
-
-    for mut reading in readings {
-        if let Some(coords) = lookup.get(&reading.id) {
-            reading.lat = Some(coords.0);
-            reading.lon = Some(coords.1);
-        }
-
-        readings_with_coords.push(reading);
+// Sets the reading's coordinates from the lookup table, if its station is in it
+fn inject_coords(reading: &mut DailyReading, lookup: &HashMap<String, (f32, f32)>) {
+    if let Some(coords) = lookup.get(&reading.id) {
+        reading.lat = Some(coords.0);
+        reading.lon = Some(coords.1);
     }
-
-    Ok(readings_with_coords)
 }
 
 // Make a lookup table of station IDs to lat/lon
-fn make_lookup(stations: &Vec<Station>) -> HashMap<String, (f32, f32)> {
+fn make_lookup(stations: &[Station]) -> HashMap<String, (f32, f32)> {
     let mut lookup = HashMap::new();
 
     for station in stations {
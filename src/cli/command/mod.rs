@@ -1,37 +1,456 @@
+pub mod cache;
 pub mod daily;
 pub mod monthly;
 pub mod stations;
 
-use std::path::PathBuf;
+use std::{
+    collections::HashSet,
+    fs,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+};
 
-use chrono::{Datelike, Local};
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Datelike, Local};
+
+use crate::{
+    cache::Cache,
+    deserialise::{write_rejected_lines, ErrorPolicy, RejectedLine},
+    parquet::{
+        partition::{ReadingWriter, MAX_OPEN_PARTITIONS},
+        OutputWriter, PartitionKey, PartitionedWriter,
+    },
+    reading::{UnitSystem, YearMonth},
+    report::Report,
+};
 pub use daily::daily;
 pub use monthly::monthly;
-pub use stations::{stations, ghcn_stations};
+pub use stations::{ghcn_stations, load_ghcn_stations, load_ushcn_stations, stations};
+use stations::{Station, StationFilter};
 
-pub fn make_parquet_file_name(period: &str) -> PathBuf {
-    let today = Local::now();
-    let file_name = format!(
-        "ushcn-{}-{}-{:02}-{:02}.parquet",
-        period,
-        today.year(),
-        today.month(),
-        today.day()
-    );
+/// The default output file name template, producing e.g. `ushcn-monthly-raw-2024-07-16.parquet`.
+pub const DEFAULT_NAME_TEMPLATE: &str = "{source}-{period}-{dataset}-{date}.parquet";
+
+/// Placeholders that may be used in a file name template.
+const PLACEHOLDERS: [&str; 6] = ["source", "period", "dataset", "date", "version", "tool_version"];
+
+/// Where output files are written, how they are named, which units values are written in and how
+/// the daily and monthly files are partitioned.
+#[derive(Debug, Clone)]
+pub struct OutputConfig {
+    /// directory the rendered file names are relative to
+    pub dir: PathBuf,
+    /// file name template, see [`DEFAULT_NAME_TEMPLATE`]
+    pub template: String,
+    /// unit system of the written values
+    pub units: UnitSystem,
+    /// columns the daily and monthly output is partitioned by, a single file if empty
+    pub partition_by: Vec<PartitionKey>,
+    /// paths already handed out this run, so that outputs never overwrite each other
+    claimed: Arc<Mutex<HashSet<PathBuf>>>,
+}
+
+impl OutputConfig {
+    /// Creates the output configuration, defaulting to the home directory and the default template.
+    pub fn new(dir: Option<PathBuf>, template: Option<String>, units: UnitSystem) -> Result<Self> {
+        let dir = match dir {
+            Some(dir) => dir,
+            None => dirs::home_dir().ok_or_else(|| {
+                anyhow!("Could not determine home directory, specify one with --output-dir")
+            })?,
+        };
+        let template = template.unwrap_or_else(|| DEFAULT_NAME_TEMPLATE.to_string());
+        validate_template(&template)?;
+
+        Ok(OutputConfig {
+            dir,
+            template,
+            units,
+            partition_by: Vec::new(),
+            claimed: Arc::new(Mutex::new(HashSet::new())),
+        })
+    }
+
+    /// Returns the path of an output file, creating any directories the template introduces.
+    /// `version` is the NOAA release the file is made from, if known. Fails if the template
+    /// renders an earlier output of this run to the same path.
+    pub fn file_path(
+        &self,
+        source: &str,
+        period: &str,
+        dataset: Option<&str>,
+        version: Option<&str>,
+    ) -> Result<PathBuf> {
+        let file_path = self.render(source, period, dataset, version);
+        self.claim(file_path)
+    }
+
+    /// As [`OutputConfig::file_path`], with the extension of the rendered name replaced.
+    pub fn file_path_with_extension(
+        &self,
+        source: &str,
+        period: &str,
+        dataset: Option<&str>,
+        version: Option<&str>,
+        extension: &str,
+    ) -> Result<PathBuf> {
+        let file_path = self
+            .render(source, period, dataset, version)
+            .with_extension(extension);
+        self.claim(file_path)
+    }
+
+    fn render(
+        &self,
+        source: &str,
+        period: &str,
+        dataset: Option<&str>,
+        version: Option<&str>,
+    ) -> PathBuf {
+        let today = Local::now();
+        let date = format!("{}-{:02}-{:02}", today.year(), today.month(), today.day());
+        let dataset = dataset.unwrap_or_default().to_lowercase();
+
+        let file_name = render_template(
+            &self.template,
+            &[
+                ("source", source),
+                ("period", period),
+                ("dataset", &dataset),
+                ("date", &date),
+                ("version", version.unwrap_or_default()),
+                ("tool_version", env!("CARGO_PKG_VERSION")),
+            ],
+        );
+
+        self.dir.join(file_name)
+    }
+
+    // Records the path as taken and creates its directory.
+    fn claim(&self, file_path: PathBuf) -> Result<PathBuf> {
+        let mut claimed = self.claimed.lock().expect("output paths lock poisoned");
+        if !claimed.insert(file_path.clone()) {
+            bail!(
+                "Name template '{}' renders more than one output to {}, use {{source}}, {{period}} \
+                 and {{dataset}} to tell them apart",
+                self.template,
+                file_path.display()
+            );
+        }
+
+        if let Some(parent) = file_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        Ok(file_path)
+    }
+}
+
+/// How the downloaded archives are read.
+#[derive(Debug, Clone)]
+pub struct ParseConfig {
+    /// parse archives as they are decompressed instead of extracting them to the cache first
+    pub stream_archives: bool,
+    /// number of files parsed at once
+    pub jobs: usize,
+    /// whether a line that fails to parse fails the run or is skipped
+    pub policy: ErrorPolicy,
+    /// first year, or month, of the readings kept
+    pub start: Option<YearMonth>,
+    /// last year, or month, of the readings kept
+    pub end: Option<YearMonth>,
+    /// stations whose readings are kept
+    pub stations: StationFilter,
+}
+
+impl Default for ParseConfig {
+    /// Extracts the archives and parses a file per available CPU at once, skipping bad lines, and
+    /// keeps readings of every year and station.
+    fn default() -> Self {
+        ParseConfig {
+            stream_archives: false,
+            jobs: thread::available_parallelism().map_or(1, NonZeroUsize::get),
+            policy: ErrorPolicy::default(),
+            start: None,
+            end: None,
+            stations: StationFilter::default(),
+        }
+    }
+}
+
+impl ParseConfig {
+    /// Checks that the period of the readings kept isn't empty.
+    pub fn validate(&self) -> Result<()> {
+        if let (Some(start), Some(end)) = (self.start, self.end) {
+            if start.first_month() > end.last_month() {
+                bail!("--start {} is after --end {}", start, end);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Resolves the station filter against the stations of a dataset. Returns the IDs of the
+    /// selected stations, or None if every station is kept. IDs that match no station are
+    /// reported as warnings rather than failing the run, as long as some station is selected.
+    pub fn select_stations(
+        &self,
+        stations: &[Station],
+        report: &Report,
+    ) -> Result<Option<HashSet<String>>> {
+        let Some(selected) = self.stations.select(stations)? else {
+            return Ok(None);
+        };
+
+        for id in self.stations.unknown_ids(stations) {
+            let warning = format!("Station {} is not in the station list", id);
+            eprintln!("⚠ {}", warning);
+            report.add_warning(&warning);
+        }
+        println!("✓ Selected {} of {} stations", selected.len(), stations.len());
+
+        Ok(Some(selected))
+    }
+}
+
+/// Opens the output for `file_path`: the file itself, or if the output is partitioned by `keys` a
+/// directory named like the file without its extension. `create` opens a file of a partition by
+/// the given keys. Returns the path of the output with its writer.
+pub fn create_output_writer<W: ReadingWriter>(
+    file_path: &Path,
+    keys: &[PartitionKey],
+    create: impl Fn(&Path, &[PartitionKey]) -> Result<W> + 'static,
+) -> Result<(PathBuf, OutputWriter<W>)> {
+    if keys.is_empty() {
+        return Ok((file_path.to_path_buf(), OutputWriter::File(create(file_path, &[])?)));
+    }
+
+    let dir = file_path.with_extension("");
+    let partition_by = keys.to_vec();
+    let writer = PartitionedWriter::create(&dir, keys, MAX_OPEN_PARTITIONS, move |path| {
+        create(path, &partition_by)
+    })?;
+
+    Ok((dir, OutputWriter::Partitioned(writer)))
+}
 
-    dirs::home_dir().unwrap().join(file_name)
+pub fn make_parquet_file_name(
+    output: &OutputConfig,
+    period: &str,
+    version: Option<&str>,
+) -> Result<PathBuf> {
+    output.file_path("ushcn", period, None, version)
 }
 
-pub fn make_dataset_parquet_file_name(period: &str, dataset: &str) -> PathBuf {
-    let today = Local::now();
-    let file_name = format!(
-        "ushcn-{}-{}-{}-{:02}-{:02}.parquet",
+pub fn make_dataset_parquet_file_name(
+    output: &OutputConfig,
+    period: &str,
+    dataset: &str,
+    version: Option<&str>,
+) -> Result<PathBuf> {
+    output.file_path("ushcn", period, Some(dataset), version)
+}
+
+/// Returns the NOAA release of a downloaded file for the `{version}` placeholder: the date NOAA
+/// last modified it, as `YYYYMMDD`, if the server sent one.
+pub fn download_version(cache: &Cache, url: &str) -> Option<String> {
+    last_modified_version(&cache.entry(url).last_modified?)
+}
+
+fn last_modified_version(last_modified: &str) -> Option<String> {
+    DateTime::parse_from_rfc2822(last_modified)
+        .ok()
+        .map(|date| date.format("%Y%m%d").to_string())
+}
+
+/// Writes the lines skipped by the lenient policy to a CSV report next to the output files, if
+/// there were any.
+pub fn report_rejected_lines(
+    output: &OutputConfig,
+    period: &str,
+    version: Option<&str>,
+    rejected: &[RejectedLine],
+) -> Result<()> {
+    if rejected.is_empty() {
+        return Ok(());
+    }
+
+    let report_path =
+        output.file_path_with_extension("ushcn", period, Some("rejected"), version, "csv")?;
+    write_rejected_lines(&report_path, rejected)?;
+    println!(
+        "⚠ Skipped {} {} lines that failed to parse, see {}",
+        rejected.len(),
         period,
-        dataset.to_lowercase(),
-        today.year(),
-        today.month(),
-        today.day()
+        report_path.display()
     );
 
-    dirs::home_dir().unwrap().join(file_name)
+    Ok(())
+}
+
+// Checks that every `{placeholder}` in the template is one we know how to fill.
+fn validate_template(template: &str) -> Result<()> {
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| anyhow!("Unclosed placeholder in name template '{}'", template))?;
+        let name = &rest[start + 1..start + end];
+        if !PLACEHOLDERS.contains(&name) {
+            return Err(anyhow!(
+                "Unknown placeholder '{{{}}}' in name template, expected one of: {}",
+                name,
+                PLACEHOLDERS.map(|p| format!("{{{}}}", p)).join(", ")
+            ));
+        }
+        rest = &rest[start + end + 1..];
+    }
+
+    Ok(())
+}
+
+// Substitutes placeholders. An empty value also removes one adjoining separator so that
+// e.g. `ushcn-daily-{dataset}-{date}` doesn't render with a doubled dash.
+fn render_template(template: &str, values: &[(&str, &str)]) -> String {
+    let is_separator = |c: char| matches!(c, '-' | '_' | '/');
+    let mut rendered = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}').map(|end| start + end) else {
+            break;
+        };
+        let name = &rest[start + 1..end];
+        rendered.push_str(&rest[..start]);
+        rest = &rest[end + 1..];
+
+        let value = values
+            .iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| *value)
+            .unwrap_or_default();
+
+        if !value.is_empty() {
+            rendered.push_str(value);
+        } else if rest.starts_with(is_separator) {
+            rest = &rest[1..];
+        } else if rendered.ends_with(is_separator) {
+            rendered.pop();
+        }
+    }
+    rendered.push_str(rest);
+
+    rendered
+}
+
+// -- Tests -------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_render_default_template() {
+        let values = [
+            ("source", "ushcn"),
+            ("period", "monthly"),
+            ("dataset", "raw"),
+            ("date", "2024-07-16"),
+        ];
+        let name = render_template(DEFAULT_NAME_TEMPLATE, &values);
+
+        assert_eq!(name, "ushcn-monthly-raw-2024-07-16.parquet");
+    }
+
+    #[test]
+    fn should_drop_separator_for_empty_placeholder() {
+        let values = [
+            ("source", "ushcn"),
+            ("period", "daily"),
+            ("dataset", ""),
+            ("date", "2024-07-16"),
+        ];
+
+        assert_eq!(
+            render_template(DEFAULT_NAME_TEMPLATE, &values),
+            "ushcn-daily-2024-07-16.parquet"
+        );
+        assert_eq!(
+            render_template("{period}_{dataset}.parquet", &values),
+            "daily.parquet"
+        );
+        assert_eq!(
+            render_template("{period}/{dataset}/{date}.parquet", &values),
+            "daily/2024-07-16.parquet"
+        );
+    }
+
+    #[test]
+    fn should_reject_unknown_placeholder() {
+        assert!(validate_template("{period}-{version}.parquet").is_ok());
+        assert!(validate_template("{period}-{tool_version}.parquet").is_ok());
+        assert!(validate_template("{period}-{release}.parquet").is_err());
+        assert!(validate_template("{period}-{station}.parquet").is_err());
+        assert!(validate_template("{period.parquet").is_err());
+    }
+
+    #[test]
+    fn should_make_file_path_in_output_dir() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let output = OutputConfig::new(
+            Some(tmp_dir.path().to_path_buf()),
+            Some("{version}/{dataset}/{source}-{period}-v{tool_version}.parquet".to_string()),
+            UnitSystem::Metric,
+        )
+        .unwrap();
+
+        let file_path =
+            make_dataset_parquet_file_name(&output, "monthly", "FLS52", Some("v2.5.5.20240716"))
+                .unwrap();
+
+        assert_eq!(
+            file_path,
+            tmp_dir.path().join(format!(
+                "v2.5.5.20240716/fls52/ushcn-monthly-v{}.parquet",
+                env!("CARGO_PKG_VERSION")
+            ))
+        );
+        assert!(tmp_dir.path().join("v2.5.5.20240716/fls52").is_dir());
+
+        // without a known release the placeholder is dropped with its separator
+        let file_path = make_parquet_file_name(&output, "daily", None).unwrap();
+        assert_eq!(
+            file_path,
+            tmp_dir.path().join(format!("ushcn-daily-v{}.parquet", env!("CARGO_PKG_VERSION")))
+        );
+    }
+
+    #[test]
+    fn should_use_last_modified_date_as_version() {
+        assert_eq!(
+            last_modified_version("Tue, 16 Jul 2024 10:00:00 GMT").as_deref(),
+            Some("20240716")
+        );
+        assert_eq!(last_modified_version("yesterday"), None);
+    }
+
+    #[test]
+    fn should_reject_repeated_file_path() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let output = OutputConfig::new(
+            Some(tmp_dir.path().to_path_buf()),
+            Some("{source}-{period}.parquet".to_string()),
+            UnitSystem::Metric,
+        )
+        .unwrap();
+
+        assert!(make_dataset_parquet_file_name(&output, "monthly", "raw", None).is_ok());
+        assert!(make_dataset_parquet_file_name(&output, "monthly", "tob", None).is_err());
+        assert!(output
+            .file_path_with_extension("ushcn", "monthly", Some("rejected"), None, "csv")
+            .is_ok());
+        assert!(make_parquet_file_name(&output, "daily", None).is_ok());
+    }
 }
@@ -1,55 +1,201 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     path::{Path, PathBuf},
+    time::Instant,
 };
 
 use anyhow::Result;
-use tempfile::TempDir;
+use futures::StreamExt;
 
 use crate::{
-    cli::{command::stations::Station, create_progress_bar},
-    deserialise::deserialise,
-    download::{download_tar, extract_tar, get_extraction_folder},
-    parquet,
-    reading::{Dataset, MonthlyReading},
+    cache::Cache,
+    cli::{
+        command::stations::{make_state_lookup, Station},
+        create_indeterminate_progress_bar, create_progress_bar, MonthlyArgs,
+    },
+    deserialise::{deserialise, deserialise_archives, list_files},
+    download::{get_extraction_folders, get_tar_root_dir},
+    parquet::{MonthlyLayout, MonthlyWriter, OutputWriter, Partition, PartitionKey},
+    reading::{Dataset, ElementSelection, FileProperties, MonthlyReading, ReadingFilter},
+    report::{DatasetReport, DatasetStats, Report, SourceReport},
+    sources::Sources,
 };
 
-use super::make_dataset_parquet_file_name;
+use super::{
+    create_output_writer, make_dataset_parquet_file_name, make_parquet_file_name,
+    report_rejected_lines, OutputConfig, ParseConfig,
+};
 
-pub async fn monthly(_use_persistent_cache: bool, stations: &[Station]) -> Result<String> {
-    let temp_dir = TempDir::new()?;
+pub async fn monthly(
+    cache: &Cache,
+    sources: &Sources,
+    output: &OutputConfig,
+    parse: &ParseConfig,
+    args: &MonthlyArgs,
+    stations: &[Station],
+    report: &Report,
+) -> Result<String> {
+    let start = Instant::now();
+    let archives = download_archives(cache, sources).await?;
+    // Monthly files are one per station and element, so the files of unselected stations are
+    // skipped
+    let filter = ReadingFilter::new(ElementSelection::All)
+        .with_period(parse.start, parse.end)
+        .with_stations(parse.select_stations(stations, report)?);
+
+    // Read the archives as they are decompressed, or extract them once to the cache and read the files
+    let (pb, mut readings) = if parse.stream_archives {
+        let pb = create_indeterminate_progress_bar("Processing archive files".to_string());
+        let archive_paths = archives.iter().map(|(_, path)| path.clone()).collect();
+        let readings = deserialise_archives::<MonthlyReading>(
+            archive_paths,
+            &filter,
+            parse.jobs,
+            parse.policy,
+            pb.clone(),
+        );
+        (pb, readings.boxed())
+    } else {
+        let extraction_folders = extract_archives(cache, &archives).await?;
+        let files = list_files(&extraction_folders, &filter)?;
+        let pb = create_progress_bar(files.len() as u64, "Processing files".to_string());
+        let readings = deserialise::<MonthlyReading>(
+            files,
+            &filter,
+            parse.jobs,
+            parse.policy,
+            pb.clone(),
+        );
+        (pb, readings.boxed())
+    };
+    let lookup = make_lookup_monthly(stations);
+    let states = make_state_lookup(stations);
+
+    // Archives of different elements and datasets may come from different NOAA releases
+    let releases = monthly_releases(cache, &archives, !parse.stream_archives)?;
+    match release_mismatch(&releases) {
+        Some(warning) => {
+            eprintln!("⚠ {}", warning);
+            report.add_warning(&warning);
+        }
+        None => {
+            if let Some(version) = releases.iter().flat_map(|release| &release.versions).next() {
+                println!("✓ Monthly archives from NOAA release {}", version);
+            }
+        }
+    }
 
-    let archive_paths = download_archives(temp_dir.path()).await?;
-    let extraction_folder = extract_archives(&archive_paths, temp_dir.path()).await?;
-    let mut readings: Vec<MonthlyReading> = deserialise(&extraction_folder).await?;
-    
-    // Inject coordinates using provided stations data
-    readings = inject_coords_monthly(readings, stations.to_vec())?;
-    
-    // Group readings by dataset type
-    let mut datasets = HashMap::new();
-    for reading in readings {
-        datasets.entry(reading.properties.dataset.clone())
-               .or_insert_with(Vec::new)
-               .push(reading);
+    // Stream readings into a separate parquet file for each dataset in the wide layout, or a single
+    // file for every dataset in the other layouts, opened on its first reading. A partitioned
+    // output is a single directory, which the wide layout always partitions by dataset
+    let layout = args.monthly_layout;
+    let mut partition_by = output.partition_by.clone();
+    if layout == MonthlyLayout::Wide
+        && !partition_by.is_empty()
+        && !partition_by.contains(&PartitionKey::Dataset)
+    {
+        partition_by.insert(0, PartitionKey::Dataset);
+    }
+    let units = output.units;
+    let create_writer = move |path: &Path, partition_by: &[PartitionKey]| {
+        MonthlyWriter::create(path, layout, units, partition_by)
+    };
+    let mut writers: Vec<(Option<Dataset>, PathBuf, OutputWriter<MonthlyWriter>, DatasetStats)> =
+        Vec::new();
+    let mut rejected = Vec::new();
+    let mut failed_files = Vec::new();
+    while let Some(parsed_file) = readings.next().await {
+        let parsed_file = parsed_file?;
+        rejected.extend(parsed_file.rejected);
+        failed_files.extend(parsed_file.failed);
+        for mut reading in parsed_file.readings {
+            inject_coords_monthly(&mut reading, &lookup);
+
+            let dataset = match layout {
+                MonthlyLayout::Wide if partition_by.is_empty() => {
+                    Some(reading.properties.dataset.clone())
+                }
+                _ => None,
+            };
+            let index = match writers.iter().position(|(d, ..)| *d == dataset) {
+                Some(index) => index,
+                None => {
+                    let version = release_version(&releases, dataset.as_ref());
+                    let parquet_file_name = match &dataset {
+                        Some(dataset) => make_dataset_parquet_file_name(
+                            output,
+                            "monthly",
+                            dataset.as_str(),
+                            version.as_deref(),
+                        )?,
+                        None => make_parquet_file_name(output, "monthly", version.as_deref())?,
+                    };
+                    let (output_path, writer) =
+                        create_output_writer(&parquet_file_name, &partition_by, create_writer)?;
+                    let stats = DatasetStats::default();
+                    writers.push((dataset, output_path, writer, stats));
+                    writers.len() - 1
+                }
+            };
+            let (_, _, writer, stats) = &mut writers[index];
+            stats.record(&reading.id, reading.year, None, reading.lat.is_some());
+            let partition = Partition {
+                dataset: &reading.properties.dataset,
+                element: &reading.properties.element,
+                year: reading.year,
+                state: states.get(&reading.id).map(String::as_str),
+            };
+            writer.write(&reading, &partition)?;
+        }
     }
+    pb.finish_with_message("Processing complete");
+    let version = release_version(&releases, None);
+    report_rejected_lines(output, "monthly", version.as_deref(), &rejected)?;
 
     let mut created_files = Vec::new();
+    for (dataset, parquet_file_name, writer, stats) in writers {
+        let rows = writer.close()?;
+        created_files.push(parquet_file_name.to_string_lossy().to_string());
 
-    // Create separate parquet files for each dataset
-    for (dataset, dataset_readings) in datasets {
-        if dataset_readings.is_empty() {
+        let Some(dataset) = dataset else {
+            println!("✓ Created monthly parquet file with {} rows", rows);
+            report.add_dataset(DatasetReport {
+                rejected_lines: rejected.len(),
+                failed_files: failed_files.clone(),
+                sources: releases.iter().map(|release| release.source_report(cache)).collect(),
+                seconds: start.elapsed().as_secs_f64(),
+                ..DatasetReport::new("monthly", &parquet_file_name, rows, &stats)
+            });
             continue;
-        }
-
-        let dataset_name = dataset_to_string(&dataset);
-        let parquet_file_name = make_dataset_parquet_file_name("monthly", &dataset_name);
-        
-        parquet::save_monthly(&dataset_readings, &parquet_file_name)?;
-        created_files.push(parquet_file_name.to_string_lossy().to_string());
-        
-        println!("✓ Created {} monthly parquet file with {} readings", 
-                dataset_name, dataset_readings.len());
+        };
+
+        println!("✓ Created {} monthly parquet file with {} rows", dataset, rows);
+
+        // Rejected lines are attributed to a dataset by the name of their file
+        let rejected_lines = rejected
+            .iter()
+            .filter(|line| {
+                FileProperties::from_file(&line.file).is_ok_and(|file| file.dataset == dataset)
+            })
+            .count();
+        // Failed files are too, except for unreadable archives of unknown dataset
+        let failed_files = failed_files
+            .iter()
+            .filter(|failed| failed_file_dataset(&failed.file).is_none_or(|d| d == dataset))
+            .cloned()
+            .collect();
+        let name = format!("monthly-{}", dataset.as_str().to_lowercase());
+        report.add_dataset(DatasetReport {
+            rejected_lines,
+            failed_files,
+            sources: releases
+                .iter()
+                .filter(|release| release.dataset == dataset)
+                .map(|release| release.source_report(cache))
+                .collect(),
+            seconds: start.elapsed().as_secs_f64(),
+            ..DatasetReport::new(&name, &parquet_file_name, rows, &stats)
+        });
     }
 
     // Return summary of created files
@@ -58,33 +204,133 @@ pub async fn monthly(_use_persistent_cache: bool, stations: &[Station]) -> Resul
               created_files.join(", ")))
 }
 
-fn dataset_to_string(dataset: &Dataset) -> String {
-    match dataset {
-        Dataset::Raw => "RAW".to_string(),
-        Dataset::Tob => "TOB".to_string(),
-        Dataset::Fls52 => "FLS52".to_string(),
-        _ => "UNKNOWN".to_string(),
-    }
+/// A monthly archive's element and dataset, and the NOAA releases its files came from.
+#[derive(Debug, Clone, PartialEq)]
+struct MonthlyRelease {
+    url: String,
+    element: String,
+    dataset: Dataset,
+    /// version directories in the archive, e.g. `ushcn.v2.5.5.20240716`
+    versions: Vec<String>,
 }
 
-fn inject_coords_monthly(readings: Vec<MonthlyReading>, stations: Vec<Station>) -> Result<Vec<MonthlyReading>> {
-    let mut readings_with_coords = Vec::new();
-    let lookup = make_lookup_monthly(&stations);
+impl MonthlyRelease {
+    /// Reads the element and dataset from an archive named like `ushcn.tmax.latest.raw.tar.gz`.
+    fn new(url: &str, versions: Vec<String>) -> Self {
+        let file_name = url.rsplit('/').next().unwrap_or(url);
+        let (element, dataset) = file_name
+            .strip_prefix("ushcn.")
+            .and_then(|name| name.strip_suffix(".tar.gz"))
+            .and_then(|name| name.split_once(".latest."))
+            .unwrap_or(("unknown", "unknown"));
+        let dataset = match dataset {
+            "raw" => Dataset::Raw,
+            "tob" => Dataset::Tob,
+            dataset if dataset.starts_with("FLs") => Dataset::Fls52,
+            _ => Dataset::Unknown,
+        };
+
+        MonthlyRelease {
+            url: url.to_string(),
+            element: element.to_string(),
+            dataset,
+            versions,
+        }
+    }
+
+    fn dataset_name(&self) -> String {
+        self.dataset.as_str().to_lowercase()
+    }
 
-    for mut reading in readings {
-        if let Some(coords) = lookup.get(&reading.id) {
-            reading.lat = Some(coords.0);
-            reading.lon = Some(coords.1);
+    fn source_report(&self, cache: &Cache) -> SourceReport {
+        SourceReport {
+            element: Some(self.element.clone()),
+            dataset: Some(self.dataset_name()),
+            versions: self.versions.clone(),
+            ..SourceReport::from_cache(cache, &self.url)
         }
+    }
+}
 
-        readings_with_coords.push(reading);
+/// Returns the release of each monthly archive, from every version directory it extracted to or,
+/// if the archives aren't extracted, from the directory of each archive's first file.
+fn monthly_releases(
+    cache: &Cache,
+    archives: &[(String, PathBuf)],
+    extracted: bool,
+) -> Result<Vec<MonthlyRelease>> {
+    let mut releases = Vec::new();
+    for (url, archive_path) in archives {
+        let versions = if extracted {
+            cache.versions(url)
+        } else {
+            get_tar_root_dir(archive_path)?.into_iter().collect()
+        };
+        releases.push(MonthlyRelease::new(url, versions));
     }
 
-    Ok(readings_with_coords)
+    Ok(releases)
+}
+
+/// Describes which archives came from which release if they didn't all come from the same one.
+fn release_mismatch(releases: &[MonthlyRelease]) -> Option<String> {
+    let mut archives_by_version: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    for release in releases {
+        for version in &release.versions {
+            let archive = format!("{} {}", release.dataset_name(), release.element);
+            archives_by_version.entry(version).or_default().push(archive);
+        }
+    }
+    if archives_by_version.len() < 2 {
+        return None;
+    }
+
+    let versions: Vec<String> = archives_by_version
+        .into_iter()
+        .map(|(version, mut archives)| {
+            archives.sort();
+            format!("{} ({})", version, archives.join(", "))
+        })
+        .collect();
+    Some(format!(
+        "Monthly archives come from {} NOAA releases: {}",
+        versions.len(),
+        versions.join("; ")
+    ))
+}
+
+/// Returns the latest NOAA release of the archives of a dataset, or of every archive, for the
+/// `{version}` placeholder, e.g. `v2.5.5.20240716`.
+fn release_version(releases: &[MonthlyRelease], dataset: Option<&Dataset>) -> Option<String> {
+    releases
+        .iter()
+        .filter(|release| dataset.is_none_or(|dataset| release.dataset == *dataset))
+        .flat_map(|release| &release.versions)
+        .max()
+        .map(|version| version.trim_start_matches("ushcn.").to_string())
+}
+
+/// Returns the dataset of a station file or monthly archive that failed, if its name tells.
+fn failed_file_dataset(file: &str) -> Option<Dataset> {
+    let dataset = if file.ends_with(".tar.gz") {
+        MonthlyRelease::new(file, Vec::new()).dataset
+    } else {
+        FileProperties::from_file(file).ok()?.dataset
+    };
+
+    (dataset != Dataset::Unknown).then_some(dataset)
+}
+
+// Sets the reading's coordinates from the lookup table, if its station is in it
+fn inject_coords_monthly(reading: &mut MonthlyReading, lookup: &HashMap<String, (f32, f32)>) {
+    if let Some(coords) = lookup.get(&reading.id) {
+        reading.lat = Some(coords.0);
+        reading.lon = Some(coords.1);
+    }
 }
 
 // Make a lookup table of station IDs to lat/lon for monthly data
-fn make_lookup_monthly(stations: &Vec<Station>) -> HashMap<String, (f32, f32)> {
+fn make_lookup_monthly(stations: &[Station]) -> HashMap<String, (f32, f32)> {
     let mut lookup = HashMap::new();
 
     for station in stations {
@@ -96,25 +342,23 @@ fn make_lookup_monthly(stations: &Vec<Station>) -> HashMap<String, (f32, f32)> {
     lookup
 }
 
-/// Download the monthly archives and return a vector of the paths to the downloaded files.
-async fn download_archives(temp_dir: &Path) -> Result<Vec<PathBuf>> {
+/// Download the monthly archives and return their URLs with the paths to the downloaded files.
+async fn download_archives(cache: &Cache, sources: &Sources) -> Result<Vec<(String, PathBuf)>> {
     let element_map = element_map();
     let dataset_map = dataset_map();
-    let file_urls = generate_file_urls(&element_map, &dataset_map);
+    let file_urls = generate_file_urls(sources, &element_map, &dataset_map);
 
     let total_files = file_urls.len() as u64;
     let pb = create_progress_bar(total_files, "Downloading monthly archives (parallel)...".to_string());
     
     // Download all files in parallel
     let download_tasks: Vec<_> = file_urls.into_iter().map(|file_url| {
-        let filename = file_url.split('/').last().unwrap().to_string();
-        let file_path = temp_dir.join(&filename);
         let pb_clone = pb.clone();
         
         async move {
-            let result = download_tar(&file_url, file_path.clone()).await;
+            let result = cache.fetch(&file_url, None).await;
             pb_clone.inc(1);
-            result.map(|_| file_path)
+            result.map(|file_path| (file_url, file_path))
         }
     }).collect();
     
@@ -126,20 +370,20 @@ async fn download_archives(temp_dir: &Path) -> Result<Vec<PathBuf>> {
     Ok(files)
 }
 
-///Extract the archives in `archive_paths` to the `working_dir` and return the path to the extraction folder.
-async fn extract_archives(archive_paths: &Vec<PathBuf>, working_dir: &Path) -> Result<PathBuf> {
-    let total_files = archive_paths.len() as u64;
+/// Extract the downloaded `archives` and return the path to each archive's extraction folder.
+async fn extract_archives(cache: &Cache, archives: &[(String, PathBuf)]) -> Result<Vec<PathBuf>> {
+    let total_files = archives.len() as u64;
     let pb = create_progress_bar(total_files, "Extracting monthly archives...".to_string());
 
-    for archive_path in archive_paths {
-        extract_tar(archive_path, working_dir).await?;
+    let mut extraction_folders = Vec::new();
+    for (url, archive_path) in archives {
+        let extraction_dir = cache.extract(url, archive_path, None).await?;
+        extraction_folders.extend(get_extraction_folders(&extraction_dir)?);
         pb.inc(1);
     }
     pb.finish_with_message("Monthly archives extracted");
 
-    let extraction_folder = get_extraction_folder(working_dir)?;
-
-    Ok(extraction_folder)
+    Ok(extraction_folders)
 }
 
 fn element_map() -> HashMap<&'static str, &'static str> {
@@ -147,6 +391,7 @@ fn element_map() -> HashMap<&'static str, &'static str> {
     element_map.insert("max", "tmax");
     element_map.insert("min", "tmin");
     element_map.insert("avg", "tavg");
+    element_map.insert("prcp", "prcp");
 
     element_map
 }
@@ -161,18 +406,23 @@ fn dataset_map() -> HashMap<&'static str, &'static str> {
 }
 
 fn generate_file_urls(
+    sources: &Sources,
     element_map: &HashMap<&str, &str>,
     dataset_map: &HashMap<&str, &str>,
 ) -> Vec<String> {
-    let root = "https://www.ncei.noaa.gov/pub/data/ushcn/v2.5";
+    // Imported archives replace the whole set
+    let imported = sources.imported_monthly_archive_urls();
+    if !imported.is_empty() {
+        return imported;
+    }
+
     let mut urls = vec![];
 
     for element in element_map.keys() {
         for dataset in dataset_map.keys() {
             let element_name = element_map.get(element).unwrap();
             let dataset_name = dataset_map.get(dataset).unwrap();
-            let file_name = format!("ushcn.{}.latest.{}.tar.gz", element_name, dataset_name);
-            urls.push(format!("{}/{}", root, file_name));
+            urls.push(sources.monthly_archive_url(element_name, dataset_name));
         }
     }
 
@@ -189,13 +439,90 @@ mod test {
     fn should_generate_file_urls() {
         let element_map = element_map();
         let dataset_map = dataset_map();
-        let file_urls = generate_file_urls(&element_map, &dataset_map);
+        let file_urls = generate_file_urls(&Sources::default(), &element_map, &dataset_map);
 
-        assert_eq!(file_urls.len(), 9);
+        assert_eq!(file_urls.len(), 12);
 
         assert!(file_urls.contains(
             &"https://www.ncei.noaa.gov/pub/data/ushcn/v2.5/ushcn.tmin.latest.raw.tar.gz"
                 .to_string()
         ));
+        assert!(file_urls.contains(
+            &"https://www.ncei.noaa.gov/pub/data/ushcn/v2.5/ushcn.prcp.latest.FLs.52j.tar.gz"
+                .to_string()
+        ));
+    }
+
+    #[test]
+    fn should_read_release_from_archive_name() {
+        let url = "https://www.ncei.noaa.gov/pub/data/ushcn/v2.5/ushcn.prcp.latest.FLs.52j.tar.gz";
+        let release = MonthlyRelease::new(url, vec!["ushcn.v2.5.5.20240716".to_string()]);
+
+        assert_eq!(release.element, "prcp");
+        assert_eq!(release.dataset, Dataset::Fls52);
+        assert_eq!(release.dataset_name(), "fls52");
+
+        let release = MonthlyRelease::new("file:///noaa/ushcn.tmin.latest.tob.tar.gz", vec![]);
+        assert_eq!(release.element, "tmin");
+        assert_eq!(release.dataset, Dataset::Tob);
+    }
+
+    #[test]
+    fn should_detect_release_mismatch() {
+        let release = |name: &str, version: &str| {
+            MonthlyRelease::new(name, vec![format!("ushcn.v2.5.5.{}", version)])
+        };
+        let same = vec![
+            release("ushcn.tmax.latest.raw.tar.gz", "20240716"),
+            release("ushcn.tmin.latest.raw.tar.gz", "20240716"),
+        ];
+        assert_eq!(release_mismatch(&same), None);
+
+        let mixed = vec![
+            release("ushcn.tmin.latest.raw.tar.gz", "20240716"),
+            release("ushcn.prcp.latest.tob.tar.gz", "20240718"),
+            release("ushcn.tmax.latest.raw.tar.gz", "20240716"),
+        ];
+        assert_eq!(
+            release_mismatch(&mixed).unwrap(),
+            "Monthly archives come from 2 NOAA releases: \
+             ushcn.v2.5.5.20240716 (raw tmax, raw tmin); ushcn.v2.5.5.20240718 (tob prcp)"
+        );
+    }
+
+    #[test]
+    fn should_use_latest_release_as_version() {
+        let releases = vec![
+            MonthlyRelease::new("ushcn.tmin.latest.raw.tar.gz", vec!["ushcn.v2.5.5.20240716".into()]),
+            MonthlyRelease::new("ushcn.prcp.latest.tob.tar.gz", vec!["ushcn.v2.5.5.20240718".into()]),
+        ];
+        assert_eq!(
+            release_version(&releases, Some(&Dataset::Raw)).as_deref(),
+            Some("v2.5.5.20240716")
+        );
+        assert_eq!(release_version(&releases, None).as_deref(), Some("v2.5.5.20240718"));
+        assert_eq!(release_version(&releases, Some(&Dataset::Fls52)), None);
+    }
+
+    #[test]
+    fn should_attribute_failed_files_to_dataset() {
+        assert_eq!(failed_file_dataset("USH00011084.tob.tmax"), Some(Dataset::Tob));
+        assert_eq!(failed_file_dataset("USH00011084.FLs.52j.prcp"), Some(Dataset::Fls52));
+        assert_eq!(failed_file_dataset("ushcn.tmax.latest.raw.tar.gz"), Some(Dataset::Raw));
+        assert_eq!(failed_file_dataset("archive.tar.gz"), None);
+    }
+
+    #[test]
+    fn should_use_imported_archives() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let archive_path = tmp_dir.path().join("ushcn.tmax.latest.raw.tar.gz");
+        std::fs::write(&archive_path, "").unwrap();
+        let sources = Sources::default().import_files(&[archive_path]).unwrap();
+
+        let file_urls = generate_file_urls(&sources, &element_map(), &dataset_map());
+
+        assert_eq!(file_urls.len(), 1);
+        assert!(file_urls[0].starts_with("file://"));
+        assert!(file_urls[0].ends_with("/ushcn.tmax.latest.raw.tar.gz"));
     }
 }
@@ -3,16 +3,24 @@
 //! See "USHCN v2.5 readme.txt Section 2" for format.
 
 use std::{
-    fs::File,
+    collections::{HashMap, HashSet},
+    fs::{self, File},
     io::{self, BufRead},
     path::{Path, PathBuf},
+    str::FromStr,
+    time::Instant,
 };
 
-use anyhow::Result;
-use chrono::{Datelike, Local};
-use tempfile::TempDir;
+use anyhow::{bail, Context, Result};
 
-use crate::{cli::create_spinner, download::download_tar, parquet};
+use super::{download_version, OutputConfig};
+use crate::{
+    cache::Cache,
+    cli::create_spinner,
+    parquet,
+    report::{DatasetReport, DatasetStats, Report, SourceReport},
+    sources::Sources,
+};
 
 #[derive(Debug, Default, Clone)]
 pub struct Station {
@@ -84,47 +92,266 @@ impl Station {
         )
     }
 }
-pub async fn stations(_use_persistent_cache: bool) -> Result<(String, Vec<Station>)> {
-    let tmp_dir = TempDir::new()?;
 
-    let archive_filepath = download_ushcn_archive(tmp_dir.path()).await?;
-    let stations = extract_ushcn_stations(&archive_filepath)?;
-    let parquet_file_name = make_ushcn_parquet_file_name();
+/// Returns the state of each station by ID, for the stations that have one.
+pub fn make_state_lookup(stations: &[Station]) -> HashMap<String, String> {
+    stations
+        .iter()
+        .filter_map(|station| Some((station.station_id(), station.state.clone()?)))
+        .collect()
+}
+
+/// Selects stations by ID, state and location. A station is selected if it meets every criterion
+/// given.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StationFilter {
+    /// GHCN or USHCN station IDs, matched in full except that USHCN (`USH`) and GHCN COOP (`USC`)
+    /// IDs of the same COOP number select the same station, see [`station_key`].
+    pub ids: Option<Vec<String>>,
+    /// two letter state codes
+    pub states: Option<Vec<String>>,
+    pub bbox: Option<BoundingBox>,
+}
+
+/// A longitude and latitude range, in degrees.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub min_lon: f32,
+    pub min_lat: f32,
+    pub max_lon: f32,
+    pub max_lat: f32,
+}
+
+impl StationFilter {
+    /// Returns true if every station is selected.
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_none() && self.states.is_none() && self.bbox.is_none()
+    }
+
+    /// Returns the IDs of the selected stations, or None if every station is selected. It is an
+    /// error if no station is selected.
+    pub fn select(&self, stations: &[Station]) -> Result<Option<HashSet<String>>> {
+        if self.is_empty() {
+            return Ok(None);
+        }
+
+        let selected: HashSet<String> = stations
+            .iter()
+            .filter(|station| self.matches(station))
+            .map(Station::station_id)
+            .collect();
+        if selected.is_empty() {
+            bail!("No stations match the --stations, --state and --bbox filters");
+        }
+
+        Ok(Some(selected))
+    }
+
+    /// Returns the given IDs that match none of the stations.
+    pub fn unknown_ids<'a>(&'a self, stations: &[Station]) -> Vec<&'a str> {
+        let known: HashSet<String> = stations
+            .iter()
+            .map(|station| station_key(&station.station_id()))
+            .collect();
+
+        self.ids
+            .iter()
+            .flatten()
+            .filter(|id| !known.contains(&station_key(id)))
+            .map(String::as_str)
+            .collect()
+    }
+
+    fn matches(&self, station: &Station) -> bool {
+        let key = station_key(&station.station_id());
+        let id_matches = self
+            .ids
+            .as_ref()
+            .is_none_or(|ids| ids.iter().any(|id| station_key(id) == key));
+        let state_matches = self.states.as_ref().is_none_or(|states| {
+            station
+                .state
+                .as_ref()
+                .is_some_and(|state| states.iter().any(|s| s.eq_ignore_ascii_case(state)))
+        });
+        let location_matches = self.bbox.is_none_or(|bbox| {
+            matches!((station.latitude, station.longitude),
+                (Some(lat), Some(lon)) if bbox.contains(lat, lon))
+        });
+
+        id_matches && state_matches && location_matches
+    }
+}
+
+impl BoundingBox {
+    pub fn contains(&self, lat: f32, lon: f32) -> bool {
+        (self.min_lat..=self.max_lat).contains(&lat) && (self.min_lon..=self.max_lon).contains(&lon)
+    }
+}
+
+impl FromStr for BoundingBox {
+    type Err = String;
+
+    /// Parses `minlon,minlat,maxlon,maxlat`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "expected minlon,minlat,maxlon,maxlat in degrees, got '{}'",
+                s
+            )
+        };
+        let values = s
+            .split(',')
+            .map(|value| value.trim().parse::<f32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| invalid())?;
+        let [min_lon, min_lat, max_lon, max_lat] = values[..] else {
+            return Err(invalid());
+        };
+        if min_lon > max_lon || min_lat > max_lat {
+            return Err(format!(
+                "the minimum of '{}' is greater than the maximum",
+                s
+            ));
+        }
+        if min_lat < -90.0 || max_lat > 90.0 || min_lon < -180.0 || max_lon > 180.0 {
+            return Err(format!(
+                "'{}' is outside -180..180 longitude, -90..90 latitude",
+                s
+            ));
+        }
+
+        Ok(BoundingBox {
+            min_lon,
+            min_lat,
+            max_lon,
+            max_lat,
+        })
+    }
+}
+
+/// Returns the key a station ID is matched by: the ID itself, except that the USHCN (`USH`) and
+/// GHCN COOP (`USC`) IDs of a COOP station share a key, as USHCN stations are COOP stations.
+/// Other networks, e.g. `USW` for WBAN stations, number their stations independently.
+fn station_key(station_id: &str) -> String {
+    match station_id.get(..3) {
+        Some("USH" | "USC") if station_id.len() == 11 => format!("US*{}", &station_id[3..]),
+        _ => station_id.to_string(),
+    }
+}
+
+/// Reads station IDs from a file with an ID per line, or else from a comma separated list.
+pub fn parse_station_ids(ids_or_file: &str) -> Result<Vec<String>> {
+    let path = Path::new(ids_or_file);
+    let ids = if path.is_file() {
+        fs::read_to_string(path)
+            .with_context(|| format!("Failed to read station IDs from {}", path.display()))?
+    } else {
+        ids_or_file.to_string()
+    };
+
+    let ids: Vec<String> = ids
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|id| !id.is_empty())
+        .map(|id| id.to_uppercase())
+        .collect();
+    if ids.is_empty() {
+        bail!("No station IDs in --stations {}", ids_or_file);
+    }
+
+    Ok(ids)
+}
+
+pub async fn stations(
+    cache: &Cache,
+    sources: &Sources,
+    output: &OutputConfig,
+    report: &Report,
+) -> Result<(String, Vec<Station>)> {
+    let start = Instant::now();
+    let stations = load_ushcn_stations(cache, sources).await?;
+    let version = download_version(cache, &sources.ushcn_stations_url());
+    let parquet_file_name = make_ushcn_parquet_file_name(output, version.as_deref())?;
     parquet::save_stations(&stations, &parquet_file_name)?;
 
+    let source = SourceReport::from_cache(cache, &sources.ushcn_stations_url());
+    report.add_dataset(stations_report(
+        "ushcn-stations",
+        &parquet_file_name,
+        &stations,
+        source,
+        start,
+    ));
     Ok((parquet_file_name.to_string_lossy().to_string(), stations))
 }
 
-pub async fn ghcn_stations(_use_persistent_cache: bool) -> Result<(String, Vec<Station>)> {
-    let tmp_dir = TempDir::new()?;
-
-    let archive_filepath = download_ghcn_archive(tmp_dir.path()).await?;
-    let stations = extract_ghcn_stations(&archive_filepath)?;
-    let parquet_file_name = make_ghcn_parquet_file_name();
+pub async fn ghcn_stations(
+    cache: &Cache,
+    sources: &Sources,
+    output: &OutputConfig,
+    report: &Report,
+) -> Result<(String, Vec<Station>)> {
+    let start = Instant::now();
+    let stations = load_ghcn_stations(cache, sources).await?;
+    let version = download_version(cache, &sources.ghcn_stations_url());
+    let parquet_file_name = make_ghcn_parquet_file_name(output, version.as_deref())?;
     parquet::save_stations(&stations, &parquet_file_name)?;
 
+    let source = SourceReport::from_cache(cache, &sources.ghcn_stations_url());
+    report.add_dataset(stations_report(
+        "ghcn-stations",
+        &parquet_file_name,
+        &stations,
+        source,
+        start,
+    ));
     Ok((parquet_file_name.to_string_lossy().to_string(), stations))
 }
 
-pub async fn download_ushcn_archive(temp_dir: &Path) -> Result<PathBuf> {
-    let url = "https://www.ncei.noaa.gov/pub/data/ushcn/v2.5/ushcn-v2.5-stations.txt";
-    let file_name = url.split('/').last().unwrap();
-    let file_path = temp_dir.join(file_name);
+// Makes the report of a saved station list
+fn stations_report(
+    dataset: &str,
+    output: &Path,
+    stations: &[Station],
+    source: SourceReport,
+    start: Instant,
+) -> DatasetReport {
+    let mut stats = DatasetStats::default();
+    for station in stations {
+        let has_coords = station.latitude.is_some() && station.longitude.is_some();
+        stats.record_station(&station.station_id(), has_coords);
+    }
+
+    DatasetReport {
+        sources: vec![source],
+        seconds: start.elapsed().as_secs_f64(),
+        ..DatasetReport::new(dataset, output, stations.len() as u64, &stats)
+    }
+}
+
+/// Downloads the USHCN station list without saving it, for coordinate injection.
+pub async fn load_ushcn_stations(cache: &Cache, sources: &Sources) -> Result<Vec<Station>> {
+    let archive_filepath = download_ushcn_archive(cache, sources).await?;
+    extract_ushcn_stations(&archive_filepath)
+}
+
+/// Downloads the GHCN station list without saving it, for coordinate injection.
+pub async fn load_ghcn_stations(cache: &Cache, sources: &Sources) -> Result<Vec<Station>> {
+    let archive_filepath = download_ghcn_archive(cache, sources).await?;
+    extract_ghcn_stations(&archive_filepath)
+}
 
+pub async fn download_ushcn_archive(cache: &Cache, sources: &Sources) -> Result<PathBuf> {
     let bar = create_spinner("Downloading USHCN stations data...".to_string());
-    download_tar(url, file_path.clone()).await?;
+    let file_path = cache.fetch(&sources.ushcn_stations_url(), None).await?;
     bar.finish_with_message("✓ USHCN stations data downloaded");
 
     Ok(file_path)
 }
 
-pub async fn download_ghcn_archive(temp_dir: &Path) -> Result<PathBuf> {
-    let url = "https://www.ncei.noaa.gov/pub/data/ghcn/daily/ghcnd-stations.txt";
-    let file_name = url.split('/').last().unwrap();
-    let file_path = temp_dir.join(file_name);
-
+pub async fn download_ghcn_archive(cache: &Cache, sources: &Sources) -> Result<PathBuf> {
     let bar = create_spinner("Downloading GHCN stations data...".to_string());
-    download_tar(url, file_path.clone()).await?;
+    let file_path = cache.fetch(&sources.ghcn_stations_url(), None).await?;
     bar.finish_with_message("✓ GHCN stations data downloaded");
 
     Ok(file_path)
@@ -168,28 +395,18 @@ fn parse_str(s: &str) -> Option<String> {
     s.trim().parse::<String>().ok().filter(|v| !v.is_empty())
 }
 
-pub fn make_ushcn_parquet_file_name() -> PathBuf {
-    let today = Local::now();
-    let file_name = format!(
-        "ushcn-stations-{}-{:02}-{:02}.parquet",
-        today.year(),
-        today.month(),
-        today.day()
-    );
-
-    dirs::home_dir().unwrap().join(file_name)
+pub fn make_ushcn_parquet_file_name(
+    output: &OutputConfig,
+    version: Option<&str>,
+) -> Result<PathBuf> {
+    output.file_path("ushcn", "stations", None, version)
 }
 
-pub fn make_ghcn_parquet_file_name() -> PathBuf {
-    let today = Local::now();
-    let file_name = format!(
-        "ghcnd-stations-{}-{:02}-{:02}.parquet",
-        today.year(),
-        today.month(),
-        today.day()
-    );
-
-    dirs::home_dir().unwrap().join(file_name)
+pub fn make_ghcn_parquet_file_name(
+    output: &OutputConfig,
+    version: Option<&str>,
+) -> Result<PathBuf> {
+    output.file_path("ghcnd", "stations", None, version)
 }
 
 // -- Tests -------------------------------------------------------------------
@@ -263,4 +480,106 @@ mod test {
 
         assert_eq!(s.station_id(), "USC00437054");
     }
+
+    #[test]
+    fn should_select_stations_meeting_every_criterion() {
+        let stations = [
+            "USH00011084  31.0581  -87.0547   25.9 AL BREWTON 3 SSE                  ------ ------ ------ +6",
+            "USH00090586  32.6106  -85.4781  198.1 GA AUBURN                         ------ ------ ------ -5",
+            "USH00437054  44.4200  -72.0194  213.4 VT SAINT JOHNSBURY                ------ ------ ------ +6",
+        ]
+        .map(|line| Station::from_ushcn_line(line).unwrap());
+
+        assert_eq!(StationFilter::default().select(&stations).unwrap(), None);
+
+        let filter = StationFilter {
+            states: Some(vec!["AL".to_string(), "GA".to_string()]),
+            bbox: "-86,30,-80,35".parse().ok(),
+            ..StationFilter::default()
+        };
+        let selected = filter.select(&stations).unwrap().unwrap();
+        assert_eq!(selected, HashSet::from(["USH00090586".to_string()]));
+
+        // IDs are matched in full, so a bare COOP number matches nothing
+        let filter = StationFilter {
+            ids: Some(
+                ["011084", "USH00437054", "USH00999999"]
+                    .map(String::from)
+                    .to_vec(),
+            ),
+            ..StationFilter::default()
+        };
+        let selected = filter.select(&stations).unwrap().unwrap();
+        assert_eq!(selected, HashSet::from(["USH00437054".to_string()]));
+        assert_eq!(filter.unknown_ids(&stations), ["011084", "USH00999999"]);
+
+        let filter = StationFilter {
+            ids: Some(vec!["USC00011084".to_string()]),
+            ..StationFilter::default()
+        };
+        let selected = filter.select(&stations).unwrap().unwrap();
+        assert_eq!(selected, HashSet::from(["USH00011084".to_string()]));
+
+        let filter = StationFilter {
+            states: Some(vec!["TX".to_string()]),
+            ..StationFilter::default()
+        };
+        assert!(filter.select(&stations).is_err());
+    }
+
+    #[test]
+    fn should_only_match_coop_ids_across_networks() {
+        let stations = [
+            "USC00094728  40.7789  -73.9692   39.6 NY NY CITY CNTRL PARK            ",
+            "USW00094728  40.7789  -73.9692   39.6 NY NY CITY CNTRL PARK            ",
+            "CA001011500  48.9333 -123.7500   75.0 BC CHEMAINUS                     ",
+            "USC00011084  31.0581  -87.0547   25.9 AL BREWTON 3 SSE                 ",
+        ]
+        .map(|line| Station::from_ghcn_line(line).unwrap());
+        let select = |ids: &[&str]| {
+            let filter = StationFilter {
+                ids: Some(ids.iter().map(|id| id.to_string()).collect()),
+                ..StationFilter::default()
+            };
+            let mut selected: Vec<String> = filter
+                .select(&stations)
+                .map(|selected| selected.unwrap().into_iter().collect())
+                .unwrap_or_default();
+            selected.sort();
+            (selected, filter.unknown_ids(&stations).join(","))
+        };
+
+        // a WBAN ID doesn't select the COOP station with the same number
+        assert_eq!(select(&["USW00094728"]), (vec!["USW00094728".to_string()], String::new()));
+
+        // nor does a foreign ID whose last six characters match a US station
+        assert_eq!(
+            select(&["CA001011084", "USC00011084"]),
+            (vec!["USC00011084".to_string()], "CA001011084".to_string())
+        );
+
+        // a USHCN ID selects the GHCN COOP station of the same COOP number, and no other
+        assert_eq!(
+            select(&["USH00094728", "USW00011084"]),
+            (vec!["USC00094728".to_string()], "USW00011084".to_string())
+        );
+    }
+
+    #[test]
+    fn should_parse_station_ids_from_list_or_file() {
+        assert_eq!(
+            parse_station_ids("usc00011084, 011690").unwrap(),
+            ["USC00011084", "011690"]
+        );
+
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let file_path = tmp_dir.path().join("stations.txt");
+        std::fs::write(&file_path, "USC00011084\nUSC00012813\n\n").unwrap();
+        assert_eq!(
+            parse_station_ids(file_path.to_str().unwrap()).unwrap(),
+            ["USC00011084", "USC00012813"]
+        );
+
+        assert!(parse_station_ids(" , ").is_err());
+    }
 }
@@ -2,18 +2,331 @@
 
 pub mod command;
 
-use std::time::Duration;
+use std::{path::PathBuf, time::Duration};
 
-use clap::{command, Parser};
+use anyhow::Result;
+use clap::{error::ErrorKind, Args, CommandFactory, Parser, Subcommand};
 use indicatif::{ProgressBar, ProgressStyle};
 
+use crate::{
+    cache::CacheMode,
+    cli::command::{
+        stations::{parse_station_ids, BoundingBox, StationFilter},
+        ParseConfig,
+    },
+    deserialise::ErrorPolicy,
+    download::RetryPolicy,
+    parquet::{MonthlyLayout, PartitionKey},
+    reading::{ElementSelection, UnitSystem, YearMonth},
+};
+
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 /// Download and process US Historical Climate Network data
 pub struct Cli {
     /// Use persistent cache in Library directory
-    #[arg(long)]
+    #[arg(long, global = true)]
     pub cache: bool,
+
+    /// Download everything again instead of revalidating cached downloads
+    #[arg(long, global = true, conflicts_with = "offline")]
+    pub refresh: bool,
+
+    /// Only use cached downloads, without contacting NOAA
+    #[arg(long, global = true)]
+    pub offline: bool,
+
+    /// Times to retry a download that failed with a network error, timeout or server error
+    #[arg(long, global = true, value_name = "N", default_value_t = 3)]
+    pub retries: u32,
+
+    /// Seconds to wait for NOAA to respond before a download attempt fails
+    #[arg(long, global = true, value_name = "SECS", default_value_t = 60)]
+    pub timeout: u64,
+
+    /// Config file with the source URLs [default: ushcn/config.toml in the user's config directory]
+    #[arg(long, global = true, value_name = "FILE", env = "USHCN_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Base URL of the USHCN v2.5 files, e.g. a mirror or a file:// directory
+    #[arg(long, global = true, value_name = "URL", env = "USHCN_URL")]
+    pub ushcn_url: Option<String>,
+
+    /// Base URL of the GHCN-Daily files, e.g. a mirror or a file:// directory
+    #[arg(long, global = true, value_name = "URL", env = "GHCND_URL")]
+    pub ghcn_daily_url: Option<String>,
+
+    /// Use a local NOAA file (ghcnd_hcn.tar.gz, ushcn.*.latest.*.tar.gz, *-stations.txt) instead
+    /// of downloading it, without contacting NOAA. May be repeated
+    #[arg(long, global = true, value_name = "PATH")]
+    pub from_archive: Vec<PathBuf>,
+
+    /// Use the NOAA files in a local directory instead of downloading them, without contacting
+    /// NOAA
+    #[arg(long, global = true, value_name = "PATH")]
+    pub from_dir: Option<PathBuf>,
+
+    /// Parse archives as they are decompressed instead of extracting them to the cache, saving
+    /// disk space and I/O
+    #[arg(long, global = true)]
+    pub no_extract: bool,
+
+    /// Number of files to parse at once [default: the number of CPUs]
+    #[arg(long, short, global = true, value_name = "N",
+          value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub jobs: Option<usize>,
+
+    /// Fail the run at the first line that fails to parse, naming the file and line
+    #[arg(long, global = true, conflicts_with = "lenient")]
+    pub strict: bool,
+
+    /// Skip lines that fail to parse and list them in a rejected-lines report [default]
+    #[arg(long, global = true)]
+    pub lenient: bool,
+
+    /// Write a JSON summary of the run, e.g. row and station counts per output file, to FILE
+    #[arg(long, global = true, value_name = "FILE")]
+    pub report: Option<PathBuf>,
+
+    /// Only process daily and monthly readings from this year or month on, e.g. 1991 or 1991-06
+    #[arg(long, global = true, value_name = "YYYY[-MM]")]
+    pub start: Option<YearMonth>,
+
+    /// Only process daily and monthly readings up to this year or month, e.g. 2020 or 2020-12
+    #[arg(long, global = true, value_name = "YYYY[-MM]")]
+    pub end: Option<YearMonth>,
+
+    /// Only process daily and monthly readings of these comma separated station IDs, or of the
+    /// IDs in this file, one per line, e.g. USH00011084. IDs are matched in full, except that USH
+    /// and USC IDs of the same COOP number select the station in both the daily and monthly data
+    #[arg(long, global = true, value_name = "IDS|FILE")]
+    pub stations: Option<String>,
+
+    /// Only process daily and monthly readings of stations in these comma separated states,
+    /// e.g. AL,GA
+    #[arg(long, global = true, value_delimiter = ',', value_name = "STATES")]
+    pub state: Vec<String>,
+
+    /// Only process daily and monthly readings of stations in this box of degrees
+    #[arg(
+        long,
+        global = true,
+        allow_hyphen_values = true,
+        value_name = "MINLON,MINLAT,MAXLON,MAXLAT"
+    )]
+    pub bbox: Option<BoundingBox>,
+
+    /// Directory to write output files to [default: home directory]
+    #[arg(long, global = true, value_name = "DIR")]
+    pub output_dir: Option<PathBuf>,
+
+    /// Output file name template, relative to the output directory. Placeholders: {source},
+    /// {period}, {dataset}, {date}, {version} (the NOAA data release), {tool_version} (the version
+    /// of this tool) [default: {source}-{period}-{dataset}-{date}.parquet]
+    #[arg(long, global = true, value_name = "TEMPLATE")]
+    pub name_template: Option<String>,
+
+    /// Unit system of the daily and monthly values
+    #[arg(long, global = true, value_enum, default_value_t)]
+    pub units: UnitSystem,
+
+    /// Write the daily and monthly data to a Hive-partitioned directory instead of a file, with a
+    /// level for each of these comma separated columns, e.g. `element,decade`. The wide monthly
+    /// layout is always partitioned by dataset first; daily data has no dataset
+    #[arg(long, global = true, value_enum, value_delimiter = ',', value_name = "COLUMNS")]
+    pub partition_by: Vec<PartitionKey>,
+
+    /// Dataset to process (defaults to `all`)
+    #[command(subcommand)]
+    pub command: Option<Commands>,
+}
+
+impl Cli {
+    /// Checks the combinations of global and subcommand options that clap can't.
+    pub fn validate(&self) -> Result<(), clap::Error> {
+        let monthly_layout = match &self.command {
+            Some(Commands::Monthly(args)) => args.monthly_layout,
+            Some(Commands::All(args)) => args.monthly.monthly_layout,
+            _ => MonthlyLayout::default(),
+        };
+        // Pivoted rows are only complete once every reading is in, so they can't be split over
+        // partitions
+        if monthly_layout == MonthlyLayout::Pivoted && !self.partition_by.is_empty() {
+            return Err(Cli::command().error(
+                ErrorKind::ArgumentConflict,
+                "--monthly-layout pivoted can't be used with --partition-by, as each pivoted row \
+                 joins every element and dataset of a station and month",
+            ));
+        }
+        // Daily readings have no dataset, so every one would land in `dataset=unknown`
+        let runs_daily = matches!(
+            &self.command,
+            None | Some(Commands::All(_)) | Some(Commands::Daily(_))
+        );
+        if runs_daily && self.partition_by.contains(&PartitionKey::Dataset) {
+            return Err(Cli::command().error(
+                ErrorKind::ArgumentConflict,
+                "--partition-by dataset can't be used for daily data, which has no dataset; \
+                 partition the monthly command by dataset on its own",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Returns how cached downloads are used. Importing local files never contacts NOAA.
+    pub fn cache_mode(&self) -> CacheMode {
+        if self.offline || self.imports_local_files() {
+            CacheMode::Offline
+        } else if self.refresh {
+            CacheMode::Refresh
+        } else {
+            CacheMode::Revalidate
+        }
+    }
+
+    /// Returns true if local files are used instead of downloads.
+    pub fn imports_local_files(&self) -> bool {
+        !self.from_archive.is_empty() || self.from_dir.is_some()
+    }
+
+    /// Returns how the downloaded archives are read, keeping every station; see station_filter.
+    pub fn parse_config(&self) -> ParseConfig {
+        let default = ParseConfig::default();
+        ParseConfig {
+            stream_archives: self.no_extract,
+            jobs: self.jobs.unwrap_or(default.jobs),
+            policy: if self.strict {
+                ErrorPolicy::Strict
+            } else {
+                ErrorPolicy::Lenient
+            },
+            start: self.start,
+            end: self.end,
+            ..default
+        }
+    }
+
+    /// Returns the stations whose readings are kept, reading the `--stations` file if one is given.
+    pub fn station_filter(&self) -> Result<StationFilter> {
+        let states = self.state.iter().map(|state| state.trim().to_uppercase());
+        Ok(StationFilter {
+            ids: self.stations.as_deref().map(parse_station_ids).transpose()?,
+            states: (!self.state.is_empty()).then(|| states.collect()),
+            bbox: self.bbox,
+        })
+    }
+
+    /// Returns how downloads are retried and timed out.
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            retries: self.retries,
+            timeout: Duration::from_secs(self.timeout),
+            ..RetryPolicy::default()
+        }
+    }
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq)]
+/// The datasets that can be processed independently.
+pub enum Commands {
+    /// Process stations, GHCN stations, daily and monthly data
+    All(AllArgs),
+    /// Process GHCN daily data for the USHCN stations
+    Daily(DailyArgs),
+    /// Process USHCN monthly data (raw, tob and FLs.52j)
+    Monthly(MonthlyArgs),
+    /// Process USHCN station metadata
+    Stations,
+    /// Process GHCN station metadata
+    GhcnStations,
+    /// Inspect the persistent download cache
+    Cache(CacheArgs),
+}
+
+impl Default for Commands {
+    fn default() -> Self {
+        Commands::All(AllArgs::default())
+    }
+}
+
+impl Commands {
+    /// Returns the name of the subcommand, as used in the run report.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Commands::All(_) => "all",
+            Commands::Daily(_) => "daily",
+            Commands::Monthly(_) => "monthly",
+            Commands::Stations => "ushcn-stations",
+            Commands::GhcnStations => "ghcn-stations",
+            Commands::Cache(_) => "cache",
+        }
+    }
+}
+
+#[derive(Args, Debug, Clone, PartialEq)]
+/// Options for the cache subcommand.
+pub struct CacheArgs {
+    #[command(subcommand)]
+    pub command: CacheCommands,
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq)]
+/// Operations on the persistent cache.
+pub enum CacheCommands {
+    /// Check cached downloads against their recorded size and SHA-256, and that extractions
+    /// completed
+    Verify,
+    /// List cached downloads with their download time, NOAA version and disk usage
+    List,
+    /// Show the disk usage of the cache
+    Size,
+    /// Remove cached downloads and their extractions
+    Clean(CleanArgs),
+    /// Print the cache directory
+    Path,
+}
+
+#[derive(Args, Debug, Clone, Default, PartialEq)]
+/// Options for cleaning the cache.
+pub struct CleanArgs {
+    /// Remove the cached downloads whose URL contains any of these, e.g. `ghcnd_hcn` or `v2.5`
+    #[arg(value_name = "URL", required_unless_present = "all")]
+    pub urls: Vec<String>,
+
+    /// Remove everything in the cache
+    #[arg(long, conflicts_with = "urls")]
+    pub all: bool,
+}
+
+#[derive(Args, Debug, Clone, Default, PartialEq)]
+/// Options for processing every dataset.
+pub struct AllArgs {
+    #[command(flatten)]
+    pub daily: DailyArgs,
+
+    #[command(flatten)]
+    pub monthly: MonthlyArgs,
+}
+
+#[derive(Args, Debug, Clone, Default, PartialEq)]
+/// Options for processing daily data.
+pub struct DailyArgs {
+    /// Drop daily values that failed NOAA quality control (non-blank QFLAG)
+    #[arg(long)]
+    pub drop_qc_failed: bool,
+
+    /// Comma separated GHCN-Daily element codes to keep, e.g. TMAX,TMIN,SNOW,WT03, or `all`
+    #[arg(long, value_name = "ELEMENTS", default_value_t)]
+    pub elements: ElementSelection,
+}
+
+#[derive(Args, Debug, Clone, Default, PartialEq)]
+/// Options for processing monthly data.
+pub struct MonthlyArgs {
+    /// Layout of the monthly parquet files
+    #[arg(long, value_enum, default_value_t)]
+    pub monthly_layout: MonthlyLayout,
 }
 
 /// Creates a spinner.
@@ -49,3 +362,299 @@ pub fn create_indeterminate_progress_bar(message: String) -> ProgressBar {
     bar
 }
 
+
+// -- Tests -------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_default_to_all() {
+        let cli = Cli::try_parse_from(["ushcn"]).unwrap();
+
+        assert_eq!(cli.command, None);
+        assert_eq!(cli.command.unwrap_or_default(), Commands::All(AllArgs::default()));
+    }
+
+    #[test]
+    fn should_parse_report_path() {
+        let cli = Cli::try_parse_from(["ushcn", "stations", "--report", "report.json"]).unwrap();
+
+        assert_eq!(cli.report, Some(PathBuf::from("report.json")));
+        assert_eq!(cli.command.unwrap().name(), "ushcn-stations");
+    }
+
+    #[test]
+    fn should_parse_subcommands() {
+        let cli = Cli::try_parse_from(["ushcn", "monthly", "--cache"]).unwrap();
+        assert_eq!(cli.command, Some(Commands::Monthly(MonthlyArgs::default())));
+        assert!(cli.cache);
+
+        let cli = Cli::try_parse_from(["ushcn", "ghcn-stations"]).unwrap();
+        assert_eq!(cli.command, Some(Commands::GhcnStations));
+        assert!(!cli.cache);
+    }
+
+    #[test]
+    fn should_parse_daily_options() {
+        let cli = Cli::try_parse_from(["ushcn", "daily", "--drop-qc-failed"]).unwrap();
+        assert_eq!(
+            cli.command,
+            Some(Commands::Daily(DailyArgs {
+                drop_qc_failed: true,
+                ..DailyArgs::default()
+            }))
+        );
+
+        let cli = Cli::try_parse_from(["ushcn", "daily"]).unwrap();
+        assert_eq!(cli.command, Some(Commands::Daily(DailyArgs::default())));
+
+        let cli = Cli::try_parse_from(["ushcn", "daily", "--elements", "all"]).unwrap();
+        let Some(Commands::Daily(args)) = cli.command else {
+            panic!("expected the daily subcommand");
+        };
+        assert_eq!(args.elements, ElementSelection::All);
+
+        assert!(Cli::try_parse_from(["ushcn", "daily", "--elements", "TMAX,XXXX"]).is_err());
+
+        let cli = Cli::try_parse_from(["ushcn", "all", "--drop-qc-failed"]).unwrap();
+        let Some(Commands::All(args)) = cli.command else {
+            panic!("expected the all subcommand");
+        };
+        assert!(args.daily.drop_qc_failed);
+    }
+
+    #[test]
+    fn should_parse_monthly_layout() {
+        let cli = Cli::try_parse_from(["ushcn", "monthly"]).unwrap();
+        let Some(Commands::Monthly(args)) = cli.command else {
+            panic!("expected the monthly subcommand");
+        };
+        assert_eq!(args.monthly_layout, MonthlyLayout::Wide);
+
+        let cli = Cli::try_parse_from(["ushcn", "all", "--monthly-layout", "pivoted"]).unwrap();
+        let Some(Commands::All(args)) = cli.command else {
+            panic!("expected the all subcommand");
+        };
+        assert_eq!(args.monthly.monthly_layout, MonthlyLayout::Pivoted);
+
+        assert!(Cli::try_parse_from(["ushcn", "monthly", "--monthly-layout", "tall"]).is_err());
+    }
+
+    #[test]
+    fn should_reject_partitioned_pivoted_layout() {
+        let parse = |args: &[&str]| Cli::try_parse_from(args).unwrap().validate();
+
+        assert!(parse(&["ushcn", "monthly", "--monthly-layout", "long", "--partition-by", "state"])
+            .is_ok());
+        assert!(parse(&["ushcn", "daily", "--partition-by", "decade"]).is_ok());
+        assert!(parse(&["ushcn", "monthly", "--monthly-layout", "pivoted"]).is_ok());
+        let error = parse(&[
+            "ushcn",
+            "--partition-by",
+            "state",
+            "all",
+            "--monthly-layout",
+            "pivoted",
+        ])
+        .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn should_reject_daily_partitioned_by_dataset() {
+        let parse = |args: &[&str]| Cli::try_parse_from(args).unwrap().validate();
+
+        assert!(parse(&["ushcn", "monthly", "--partition-by", "dataset,element"]).is_ok());
+        for args in [
+            &["ushcn", "daily", "--partition-by", "dataset"][..],
+            &["ushcn", "all", "--partition-by", "element,dataset"],
+            &["ushcn", "--partition-by", "dataset"],
+        ] {
+            assert_eq!(parse(args).unwrap_err().kind(), ErrorKind::ArgumentConflict);
+        }
+    }
+
+    #[test]
+    fn should_parse_cache_mode() {
+        let cli = Cli::try_parse_from(["ushcn", "--cache"]).unwrap();
+        assert_eq!(cli.cache_mode(), CacheMode::Revalidate);
+
+        let cli = Cli::try_parse_from(["ushcn", "daily", "--cache", "--refresh"]).unwrap();
+        assert_eq!(cli.cache_mode(), CacheMode::Refresh);
+
+        let cli = Cli::try_parse_from(["ushcn", "monthly", "--cache", "--offline"]).unwrap();
+        assert_eq!(cli.cache_mode(), CacheMode::Offline);
+
+        assert!(Cli::try_parse_from(["ushcn", "--refresh", "--offline"]).is_err());
+
+        let cli = Cli::try_parse_from([
+            "ushcn",
+            "daily",
+            "--from-archive",
+            "ghcnd_hcn.tar.gz",
+            "--from-archive",
+            "ghcnd-stations.txt",
+        ])
+        .unwrap();
+        assert_eq!(cli.from_archive.len(), 2);
+        assert_eq!(cli.cache_mode(), CacheMode::Offline);
+
+        let cli = Cli::try_parse_from(["ushcn", "monthly", "--from-dir", "/transfer"]).unwrap();
+        assert_eq!(cli.from_dir, Some(PathBuf::from("/transfer")));
+        assert_eq!(cli.cache_mode(), CacheMode::Offline);
+    }
+
+    #[test]
+    fn should_parse_cache_commands() {
+        let cli = Cli::try_parse_from(["ushcn", "cache", "verify"]).unwrap();
+        assert_eq!(
+            cli.command,
+            Some(Commands::Cache(CacheArgs {
+                command: CacheCommands::Verify
+            }))
+        );
+
+        let cli = Cli::try_parse_from(["ushcn", "cache", "clean", "ghcnd_hcn", "v2.5"]).unwrap();
+        assert_eq!(
+            cli.command,
+            Some(Commands::Cache(CacheArgs {
+                command: CacheCommands::Clean(CleanArgs {
+                    urls: vec!["ghcnd_hcn".to_string(), "v2.5".to_string()],
+                    all: false,
+                })
+            }))
+        );
+
+        assert!(Cli::try_parse_from(["ushcn", "cache"]).is_err());
+        assert!(Cli::try_parse_from(["ushcn", "cache", "clean"]).is_err());
+        assert!(Cli::try_parse_from(["ushcn", "cache", "clean", "--all", "v2.5"]).is_err());
+    }
+
+    #[test]
+    fn should_parse_retry_policy() {
+        let cli = Cli::try_parse_from(["ushcn"]).unwrap();
+        assert_eq!(cli.retry_policy(), RetryPolicy::default());
+
+        let cli =
+            Cli::try_parse_from(["ushcn", "daily", "--retries", "5", "--timeout", "10"]).unwrap();
+        let policy = cli.retry_policy();
+        assert_eq!(policy.retries, 5);
+        assert_eq!(policy.timeout, Duration::from_secs(10));
+    }
+
+    #[test]
+    fn should_parse_output_options() {
+        let cli = Cli::try_parse_from([
+            "ushcn",
+            "daily",
+            "--output-dir",
+            "/data/lake",
+            "--name-template",
+            "{period}/{date}.parquet",
+        ])
+        .unwrap();
+
+        assert_eq!(cli.output_dir, Some(PathBuf::from("/data/lake")));
+        assert_eq!(cli.name_template.as_deref(), Some("{period}/{date}.parquet"));
+        assert_eq!(cli.units, UnitSystem::Metric);
+        assert!(cli.partition_by.is_empty());
+
+        let cli = Cli::try_parse_from(["ushcn", "monthly", "--units", "imperial"]).unwrap();
+        assert_eq!(cli.units, UnitSystem::Imperial);
+
+        let cli = Cli::try_parse_from(["ushcn", "--partition-by", "dataset,decade"]).unwrap();
+        assert_eq!(cli.partition_by, [PartitionKey::Dataset, PartitionKey::Decade]);
+        assert!(Cli::try_parse_from(["ushcn", "--partition-by", "county"]).is_err());
+        assert!(!cli.parse_config().stream_archives);
+
+        assert_eq!(cli.parse_config().jobs, ParseConfig::default().jobs);
+
+        let cli = Cli::try_parse_from(["ushcn", "daily", "--no-extract", "-j", "2"]).unwrap();
+        assert!(cli.parse_config().stream_archives);
+        assert_eq!(cli.parse_config().jobs, 2);
+
+        assert!(Cli::try_parse_from(["ushcn", "--jobs", "0"]).is_err());
+    }
+
+    #[test]
+    fn should_parse_period() {
+        let cli = Cli::try_parse_from(["ushcn", "daily", "--start", "1991", "--end", "2020-06"])
+            .unwrap();
+        let parse = cli.parse_config();
+        assert_eq!(parse.start.unwrap().to_string(), "1991");
+        assert_eq!(parse.end.unwrap().to_string(), "2020-06");
+        assert!(parse.validate().is_ok());
+
+        let cli = Cli::try_parse_from(["ushcn", "--start", "2021", "--end", "2020"]).unwrap();
+        let error = cli.parse_config().validate().unwrap_err();
+        assert_eq!(error.to_string(), "--start 2021 is after --end 2020");
+
+        assert!(Cli::try_parse_from(["ushcn", "--start", "1991-13"]).is_err());
+    }
+
+    #[test]
+    fn should_parse_station_filters() {
+        let cli = Cli::try_parse_from(["ushcn", "daily"]).unwrap();
+        assert!(cli.station_filter().unwrap().is_empty());
+
+        let cli = Cli::try_parse_from([
+            "ushcn",
+            "daily",
+            "--stations",
+            "USC00011084,011690",
+            "--state",
+            "al,GA",
+            "--bbox",
+            "-88.5,30,-84.9,35",
+        ])
+        .unwrap();
+        let filter = cli.station_filter().unwrap();
+        assert_eq!(
+            filter.ids,
+            Some(vec!["USC00011084".to_string(), "011690".to_string()])
+        );
+        assert_eq!(filter.states, Some(vec!["AL".to_string(), "GA".to_string()]));
+        assert_eq!(filter.bbox.unwrap().min_lon, -88.5);
+
+        assert!(Cli::try_parse_from(["ushcn", "--bbox", "-84.9,30,-88.5,35"]).is_err());
+        assert!(Cli::try_parse_from(["ushcn", "--bbox", "-88.5,30"]).is_err());
+    }
+
+    #[test]
+    fn should_parse_error_policy() {
+        let cli = Cli::try_parse_from(["ushcn", "monthly"]).unwrap();
+        assert_eq!(cli.parse_config().policy, ErrorPolicy::Lenient);
+
+        let cli = Cli::try_parse_from(["ushcn", "monthly", "--lenient"]).unwrap();
+        assert_eq!(cli.parse_config().policy, ErrorPolicy::Lenient);
+
+        let cli = Cli::try_parse_from(["ushcn", "monthly", "--strict"]).unwrap();
+        assert_eq!(cli.parse_config().policy, ErrorPolicy::Strict);
+
+        assert!(Cli::try_parse_from(["ushcn", "--strict", "--lenient"]).is_err());
+    }
+
+    #[test]
+    fn should_parse_source_options() {
+        let cli = Cli::try_parse_from([
+            "ushcn",
+            "monthly",
+            "--config",
+            "/etc/ushcn.toml",
+            "--ushcn-url",
+            "https://mirror.example.com/ushcn/v2.5",
+            "--ghcn-daily-url",
+            "file:///data/ghcnd",
+        ])
+        .unwrap();
+
+        assert_eq!(cli.config, Some(PathBuf::from("/etc/ushcn.toml")));
+        assert_eq!(
+            cli.ushcn_url.as_deref(),
+            Some("https://mirror.example.com/ushcn/v2.5")
+        );
+        assert_eq!(cli.ghcn_daily_url.as_deref(), Some("file:///data/ghcnd"));
+    }
+}
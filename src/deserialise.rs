@@ -1,79 +1,617 @@
-//! Deserialises a folder of archive files to a Vec of Readings.
+//! Deserialises a folder of archive files, or the archives themselves, to a stream of Readings.
 //!
 //! The deserialise function is generic over the Reading trait, which is implemented by the Reading struct.
+//! Files are parsed a few at a time as the stream is consumed, so however many files there are
+//! only the readings of those few are held in memory. Lines that fail to parse either fail the
+//! run or are skipped and collected for a rejected-lines report, depending on the ErrorPolicy.
 
-use std::{
-    path::{Path, PathBuf},
-    sync::{Arc, Mutex},
-};
 use std::fs::File;
-use std::io::{self, BufRead};
+use std::io::{self, BufRead, Read, Write};
+use std::path::{Path, PathBuf};
 
-use anyhow::Result;
-use futures::future::join_all;
+use anyhow::{anyhow, bail, Context, Result};
+use flate2::read::GzDecoder;
+use futures::{stream, FutureExt, Stream, StreamExt};
 use indicatif::ProgressBar;
+use serde::Serialize;
+use tar::Archive;
+use tokio::{
+    sync::mpsc,
+    task::{self, JoinError},
+};
+
+use crate::reading::{Reading, ReadingFilter};
+
+/// What to do with a line that fails to parse.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// fail the run, naming the file and line
+    Strict,
+    /// skip the line and record it as rejected
+    #[default]
+    Lenient,
+}
+
+/// A line skipped by the lenient error policy.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RejectedLine {
+    /// name of the file the line is in
+    pub file: String,
+    /// line number, counting from 1
+    pub line_number: usize,
+    /// why the line failed to parse
+    pub reason: String,
+    /// the raw text of the line
+    pub text: String,
+}
+
+/// A file skipped as a whole by the lenient error policy, e.g. because it couldn't be read.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FailedFile {
+    /// name of the file, or of the archive if the archive itself couldn't be read
+    pub file: String,
+    /// why the file failed
+    pub reason: String,
+}
+
+/// The Readings parsed from a file and the lines rejected, or why the whole file failed.
+#[derive(Debug)]
+pub struct ParsedFile<R> {
+    pub readings: Vec<R>,
+    pub rejected: Vec<RejectedLine>,
+    pub failed: Option<FailedFile>,
+}
+
+/// Lists the files in directories of extracted archive files, leaving out the files of stations
+/// the filter doesn't keep so they are never opened.
+pub fn list_files(extraction_dirs: &[PathBuf], filter: &ReadingFilter) -> Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = Vec::new();
+    for extraction_dir in extraction_dirs {
+        let dir_files = extraction_dir
+            .read_dir()?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<Vec<_>, io::Error>>()?;
+        files.extend(dir_files.into_iter().filter(|file| {
+            let file_name = file.file_name().and_then(|name| name.to_str());
+            file_name.is_none_or(|name| filter.accepts_file(name))
+        }));
+    }
+
+    Ok(files)
+}
+
+/// Deserialises the files to a stream of each file's Readings accepted by the filter, in file
+/// order. Up to `jobs` files are parsed at once on the blocking thread pool, and parsing only runs
+/// ahead of the consumer by those files, so a slow consumer holds it back.
+/// The progress bar is advanced as each file is consumed. Under the strict policy the stream
+/// ends with an error at the first line or file that fails; under the lenient policy a file that
+/// fails as a whole yields a ParsedFile without readings that records the failure.
+pub fn deserialise<R: Reading + Send + 'static>(
+    files: Vec<PathBuf>,
+    filter: &ReadingFilter,
+    jobs: usize,
+    policy: ErrorPolicy,
+    progress_bar: ProgressBar,
+) -> impl Stream<Item = Result<ParsedFile<R>>> {
+    let filter = filter.clone();
+
+    let parsed = stream::iter(files)
+        .map(move |file| {
+            let filter = filter.clone();
+            let name = file_name(&file);
+            task::spawn_blocking(move || process_file::<R>(&file, &filter, policy))
+                .map(move |result| (name, result))
+        })
+        .buffered(jobs);
+
+    report_progress(parsed, policy, progress_bar)
+}
+
+/// Deserialises the files inside tar.gz archives to a stream of each file's Readings accepted by
+/// the filter, in archive order, without extracting them to disk. The archives are decompressed on
+/// a blocking thread that only runs ahead of the consumer by a few files, and up to `jobs` of the
+/// decompressed files are parsed at once on the blocking thread pool.
+/// The progress bar is advanced as each file is consumed. Under the strict policy the stream
+/// ends with an error at the first line, file or archive that fails; under the lenient policy a
+/// file or archive that fails as a whole yields a ParsedFile without readings that records it.
+pub fn deserialise_archives<R: Reading + Send + 'static>(
+    archives: Vec<PathBuf>,
+    filter: &ReadingFilter,
+    jobs: usize,
+    policy: ErrorPolicy,
+    progress_bar: ProgressBar,
+) -> impl Stream<Item = Result<ParsedFile<R>>> {
+    let filter = filter.clone();
+    let (sender, receiver) = mpsc::channel(jobs);
+
+    let archive_filter = filter.clone();
+    task::spawn_blocking(move || {
+        for archive in archives {
+            if let Err(e) = read_archive(&archive, &archive_filter, &sender) {
+                // the archive itself is unreadable; stop if the stream was dropped too
+                if sender.blocking_send((file_name(&archive), Err(e))).is_err() {
+                    return;
+                }
+            }
+        }
+    });
 
-use crate::cli::create_progress_bar;
-use crate::reading::Reading;
-
-/// Read a directory of archive files and deserialise to a vec of Readings.
-pub async fn deserialise<R: Reading + Send + 'static>(extraction_dir: &Path) -> Result<Vec<R>> {
-    let files: Vec<PathBuf> = extraction_dir
-        .read_dir()?
-        .map(|entry| entry.map(|e| e.path()))
-        .collect::<Result<Vec<_>, io::Error>>()?;
-
-    let pb = create_progress_bar(files.len() as u64, "Processing files".to_string());
-    let progress_bar = Arc::new(Mutex::new(pb));
-
-    let tasks: Vec<_> = files
-        .iter()
-        .map(|file| {
-            let file = file.clone();
-            let pb = Arc::clone(&progress_bar);
-            tokio::spawn(async move { process_file::<R>(&file, pb).await })
+    let parsed = stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|file| (file, receiver))
+    })
+    .map(move |(name, file): (String, Result<ArchiveFile>)| {
+        let filter = filter.clone();
+        task::spawn_blocking(move || {
+            let file = file?;
+            parse_lines(file.contents.as_slice(), &file.name, &filter, policy)
+                .with_context(|| format!("Failed to parse {}", file.path.display()))
         })
-        .collect();
+        .map(move |result| (name, result))
+    })
+    .buffered(jobs);
+
+    report_progress(parsed, policy, progress_bar)
+}
+
+/// Writes rejected lines to a CSV report with the columns file, line, reason and text.
+pub fn write_rejected_lines(file_path: &Path, rejected: &[RejectedLine]) -> Result<()> {
+    let file = File::create(file_path)
+        .with_context(|| format!("Failed to create {}", file_path.display()))?;
+    let mut writer = io::BufWriter::new(file);
+
+    writeln!(writer, "file,line,reason,text")?;
+    for line in rejected {
+        writeln!(
+            writer,
+            "{},{},{},{}",
+            csv_field(&line.file),
+            line.line_number,
+            csv_field(&line.reason),
+            csv_field(&line.text)
+        )?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Quotes a CSV field if it contains a separator, quote or line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
 
-    let mut readings = Vec::new();
-    for result in join_all(tasks).await {
+/// Advances the progress bar as each parsed file, named by the first of the pair, is consumed.
+/// Under the lenient policy files that failed as a whole are reported and recorded as failed;
+/// under the strict policy they are passed on.
+fn report_progress<R>(
+    parsed: impl Stream<Item = (String, Result<Result<ParsedFile<R>>, JoinError>)>,
+    policy: ErrorPolicy,
+    progress_bar: ProgressBar,
+) -> impl Stream<Item = Result<ParsedFile<R>>> {
+    parsed.map(move |(name, result)| {
+        progress_bar.inc(1);
+        let result = result.unwrap_or_else(|e| Err(anyhow!("Task join error: {:?}", e)));
         match result {
-            Ok(Ok(file_readings)) => readings.extend(file_readings),
-            Ok(Err(e)) => eprintln!("Error processing file: {:?}", e),
-            Err(e) => eprintln!("Task join error: {:?}", e),
+            Err(e) if policy == ErrorPolicy::Lenient => {
+                eprintln!("Error processing file {}: {:#}", name, e);
+                Ok(ParsedFile {
+                    readings: Vec::new(),
+                    rejected: Vec::new(),
+                    failed: Some(FailedFile {
+                        file: name,
+                        reason: format!("{:#}", e),
+                    }),
+                })
+            }
+            result => result,
+        }
+    })
+}
+
+/// Returns the name of a file, or its whole path if it has none.
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map_or_else(|| path.display().to_string(), |name| name.to_string_lossy().into_owned())
+}
+
+/// A file decompressed from an archive, waiting to be parsed.
+struct ArchiveFile {
+    path: PathBuf,
+    name: String,
+    contents: Vec<u8>,
+}
+
+/// Decompresses each file in a tar.gz archive of a station the filter keeps and sends it to be
+/// parsed. The files of other stations are skipped over without being decompressed into memory.
+fn read_archive(
+    archive_path: &Path,
+    filter: &ReadingFilter,
+    sender: &mpsc::Sender<(String, Result<ArchiveFile>)>,
+) -> Result<()> {
+    let file = File::open(archive_path)
+        .with_context(|| format!("Failed to open {}", archive_path.display()))?;
+    let mut archive = Archive::new(GzDecoder::new(file));
+
+    let entries = archive
+        .entries()
+        .with_context(|| format!("Failed to read {}", archive_path.display()))?;
+    for entry in entries {
+        let mut entry =
+            entry.with_context(|| format!("Failed to read {}", archive_path.display()))?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        let path = entry.path()?.into_owned();
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        if !filter.accepts_file(name) {
+            continue;
+        }
+        let name = name.to_string();
+        let mut contents = Vec::with_capacity(entry.size() as usize);
+        entry
+            .read_to_end(&mut contents)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+
+        if sender
+            .blocking_send((
+                name.clone(),
+                Ok(ArchiveFile {
+                    path,
+                    name,
+                    contents,
+                }),
+            ))
+            .is_err()
+        {
+            // the stream was dropped, so stop decompressing
+            break;
         }
     }
-    progress_bar
-        .lock()
-        .unwrap()
-        .finish_with_message("Processing complete");
 
-    Ok(readings)
+    Ok(())
 }
 
-/// Processes a single file and return a vec of Readings.
-async fn process_file<R: Reading>(
+/// Processes a single file and return its Readings and rejected lines.
+fn process_file<R: Reading>(
     file_path: &Path,
-    progress_bar: Arc<Mutex<ProgressBar>>,
-) -> Result<Vec<R>> {
-    let mut readings = Vec::new();
-
+    filter: &ReadingFilter,
+    policy: ErrorPolicy,
+) -> Result<ParsedFile<R>> {
     let file = File::open(file_path)?;
     let file_name = file_path.file_name().unwrap().to_str().unwrap();
-    let reader = io::BufReader::new(file);
 
-    for line in reader.lines() {
-        let line = line?;
-        let reading = R::from_line(&line, file_name)?;
-        if reading.is_valid() {
-            readings.push(reading);
+    parse_lines(io::BufReader::new(file), file_name, filter, policy)
+        .with_context(|| format!("Failed to parse {}", file_path.display()))
+}
+
+/// Parses the lines of a file and return its Readings and, under the lenient policy, the lines
+/// that failed to parse. Under the strict policy the first line that fails is an error.
+fn parse_lines<R: Reading>(
+    mut reader: impl BufRead,
+    file_name: &str,
+    filter: &ReadingFilter,
+    policy: ErrorPolicy,
+) -> Result<ParsedFile<R>> {
+    let mut parsed = ParsedFile {
+        readings: Vec::new(),
+        rejected: Vec::new(),
+        failed: None,
+    };
+    let mut buffer = Vec::new();
+    let mut line_number = 0;
+
+    while reader.read_until(b'\n', &mut buffer)? > 0 {
+        line_number += 1;
+        let line = buffer.strip_suffix(b"\n").unwrap_or(&buffer);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+        let result = std::str::from_utf8(line)
+            .map_err(|_| anyhow!("Line is not valid UTF-8"))
+            .and_then(|line| {
+                // Lines outside the period are skipped before their values are parsed
+                let (year, month) = R::line_date(line)?;
+                if !filter.accepts_date(year, month) {
+                    return Ok(None);
+                }
+                R::from_line(line, file_name).map(Some)
+            });
+        match result {
+            Ok(Some(mut reading)) => {
+                if reading.is_valid() && filter.accepts(&reading) {
+                    reading.clear_outside_period(filter);
+                    parsed.readings.push(reading);
+                }
+            }
+            Ok(None) => {}
+            Err(e) if policy == ErrorPolicy::Strict => bail!("line {}: {}", line_number, e),
+            Err(e) => parsed.rejected.push(RejectedLine {
+                file: file_name.to_string(),
+                line_number,
+                reason: e.to_string(),
+                text: String::from_utf8_lossy(line).into_owned(),
+            }),
+        }
+        buffer.clear();
+    }
+
+    Ok(parsed)
+}
+
+// -- Tests -------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, fs};
+
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::reading::{DailyReading, ElementSelection, MonthlyReading};
+
+    #[tokio::test]
+    async fn should_stream_readings_in_file_order() {
+        let tmp_dir = TempDir::new().unwrap();
+        let values = "   10  6   20  6".repeat(16);
+        for (station, element) in [("USC00011084", "TMAX"), ("USC00011085", "PRCP")] {
+            let line = format!("{}189501{}{}", station, element, values);
+            fs::write(tmp_dir.path().join(format!("{}.dly", station)), line).unwrap();
         }
+        fs::write(
+            tmp_dir.path().join("USC00011086.dly"),
+            "USC00011086YEAR01TMAX",
+        )
+        .unwrap();
+
+        let filter = ReadingFilter::new(ElementSelection::All);
+        let mut files = list_files(&[tmp_dir.path().to_path_buf()], &filter).unwrap();
+        files.sort();
+        assert_eq!(files.len(), 3);
+
+        let progress_bar = ProgressBar::hidden();
+        let parsed: Vec<Result<ParsedFile<DailyReading>>> = deserialise(
+            files,
+            &filter,
+            2,
+            ErrorPolicy::Lenient,
+            progress_bar.clone(),
+        )
+        .collect()
+        .await;
+        let parsed: Vec<_> = parsed.into_iter().map(Result::unwrap).collect();
+
+        // the line that fails to parse is rejected
+        assert_eq!(parsed.len(), 3);
+        assert_eq!(parsed[0].readings[0].id, "USC00011084");
+        assert_eq!(parsed[1].readings[0].id, "USC00011085");
+        assert!(parsed[2].readings.is_empty());
+        assert_eq!(parsed[2].rejected[0].file, "USC00011086.dly");
+        assert_eq!(progress_bar.position(), 3);
+    }
+
+    #[tokio::test]
+    async fn should_reject_bad_lines_leniently() {
+        let tmp_dir = TempDir::new().unwrap();
+        let file_path = tmp_dir.path().join("USC00011084.dly");
+        let line = format!("USC00011084189501TMAX{}", "   10  6".repeat(31));
+        let mut contents = format!("{}\nUSC00011084\r\n", line).into_bytes();
+        contents.extend_from_slice(b"USC00011084189503TMAX  \xff\n");
+        contents.extend_from_slice(format!("{}\n", line).as_bytes());
+        fs::write(&file_path, contents).unwrap();
+
+        let filter = ReadingFilter::new(ElementSelection::All);
+        let parsed: ParsedFile<DailyReading> =
+            process_file(&file_path, &filter, ErrorPolicy::Lenient).unwrap();
+
+        assert_eq!(parsed.readings.len(), 2);
+        assert_eq!(
+            parsed.rejected,
+            vec![
+                RejectedLine {
+                    file: "USC00011084.dly".to_string(),
+                    line_number: 2,
+                    reason: "Line is too short: expected at least 21 characters, got 11"
+                        .to_string(),
+                    text: "USC00011084".to_string(),
+                },
+                RejectedLine {
+                    file: "USC00011084.dly".to_string(),
+                    line_number: 3,
+                    reason: "Line is not valid UTF-8".to_string(),
+                    text: "USC00011084189503TMAX  \u{FFFD}".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn should_skip_lines_outside_period_before_parsing_values() {
+        let tmp_dir = TempDir::new().unwrap();
+        let file_path = tmp_dir.path().join("USH00011084.raw.tmax");
+        let values = "  100    ".repeat(12);
+        let contents = format!(
+            "USH0001108411890   100\nUSH0001108411991 {}\nUSH0001108412021 {}\n",
+            values, values
+        );
+        fs::write(&file_path, contents).unwrap();
+
+        let filter = ReadingFilter::new(ElementSelection::All)
+            .with_period("1991-06".parse().ok(), "2020".parse().ok());
+        let parsed: ParsedFile<MonthlyReading> =
+            process_file(&file_path, &filter, ErrorPolicy::Strict).unwrap();
+
+        // the 1890 line is too short for its values, but is skipped before they are parsed
+        assert_eq!(parsed.readings.len(), 1);
+        assert!(parsed.rejected.is_empty());
+        let reading = &parsed.readings[0];
+        assert_eq!(reading.year, 1991);
+        assert_eq!(reading.values[4], None);
+        assert_eq!(reading.values[5], Some(1.0));
+    }
+
+    #[tokio::test]
+    async fn should_fail_strictly_with_file_and_line() {
+        let tmp_dir = TempDir::new().unwrap();
+        let line = format!("USC00011084189501TMAX{}", "   10  6".repeat(31));
+        fs::write(
+            tmp_dir.path().join("USC00011084.dly"),
+            format!("{}\n", line),
+        )
+        .unwrap();
+        fs::write(
+            tmp_dir.path().join("USC00011085.dly"),
+            format!("{}\nUSC00011085189502", line),
+        )
+        .unwrap();
+
+        let filter = ReadingFilter::new(ElementSelection::All);
+        let mut files = list_files(&[tmp_dir.path().to_path_buf()], &filter).unwrap();
+        files.sort();
+        let parsed: Vec<Result<ParsedFile<DailyReading>>> = deserialise(
+            files,
+            &filter,
+            2,
+            ErrorPolicy::Strict,
+            ProgressBar::hidden(),
+        )
+        .collect()
+        .await;
+
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].as_ref().unwrap().readings.len(), 1);
+        let error = format!("{:#}", parsed[1].as_ref().unwrap_err());
+        assert!(error.starts_with("Failed to parse "), "{}", error);
+        assert!(
+            error.ends_with("USC00011085.dly: line 2: Line is too short: expected at least 21 characters, got 17"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn should_write_rejected_lines_report() {
+        let tmp_dir = TempDir::new().unwrap();
+        let report_path = tmp_dir.path().join("rejected.csv");
+        let rejected = vec![RejectedLine {
+            file: "USH00011084.raw.tmax".to_string(),
+            line_number: 7,
+            reason: "invalid digit found in string".to_string(),
+            text: "USH00011084 189\"5, 736".to_string(),
+        }];
+
+        write_rejected_lines(&report_path, &rejected).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&report_path).unwrap(),
+            "file,line,reason,text\n\
+             USH00011084.raw.tmax,7,invalid digit found in string,\"USH00011084 189\"\"5, 736\"\n"
+        );
     }
 
-    {
-        let pb = progress_bar.lock().unwrap();
-        pb.inc(1);
+    #[tokio::test]
+    async fn should_stream_readings_from_archive_without_extracting() {
+        let tmp_dir = TempDir::new().unwrap();
+        let archive_path = tmp_dir.path().join("ghcnd_hcn.tar.gz");
+        let values = "   10  6   20  6".repeat(16);
+
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+            File::create(&archive_path).unwrap(),
+            flate2::Compression::default(),
+        ));
+        builder.append_dir("ghcnd_hcn", tmp_dir.path()).unwrap();
+        for (station, element) in [("USC00011084", "TMAX"), ("USC00011085", "PRCP")] {
+            let line = format!("{}189501{}{}\n", station, element, values);
+            let mut header = tar::Header::new_gnu();
+            header.set_size(line.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(
+                    &mut header,
+                    format!("ghcnd_hcn/{}.dly", station),
+                    line.as_bytes(),
+                )
+                .unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+
+        let filter = ReadingFilter::new(ElementSelection::All);
+        let progress_bar = ProgressBar::hidden();
+        let readings: Vec<Vec<DailyReading>> = deserialise_archives(
+            vec![archive_path],
+            &filter,
+            2,
+            ErrorPolicy::Strict,
+            progress_bar.clone(),
+        )
+        .map(|parsed| parsed.unwrap().readings)
+        .collect()
+        .await;
+
+        // the directory entry is skipped and nothing is written next to the archive
+        assert_eq!(readings.len(), 2);
+        assert_eq!(readings[0][0].id, "USC00011084");
+        assert_eq!(readings[1][0].id, "USC00011085");
+        assert_eq!(progress_bar.position(), 2);
+        assert_eq!(fs::read_dir(tmp_dir.path()).unwrap().count(), 1);
+
+        // files of stations that aren't selected are skipped without being parsed
+        let stations = HashSet::from(["USC00011085".to_string()]);
+        let filter = ReadingFilter::new(ElementSelection::All).with_stations(Some(stations));
+        let readings: Vec<Vec<DailyReading>> = deserialise_archives(
+            vec![tmp_dir.path().join("ghcnd_hcn.tar.gz")],
+            &filter,
+            2,
+            ErrorPolicy::Strict,
+            ProgressBar::hidden(),
+        )
+        .map(|parsed| parsed.unwrap().readings)
+        .collect()
+        .await;
+
+        assert_eq!(readings.len(), 1);
+        assert_eq!(readings[0][0].id, "USC00011085");
     }
 
-    Ok(readings)
+    #[tokio::test]
+    async fn should_report_unreadable_archive() {
+        let tmp_dir = TempDir::new().unwrap();
+        let archive_path = tmp_dir.path().join("ghcnd_hcn.tar.gz");
+        fs::write(&archive_path, "not a gzip file").unwrap();
+
+        let filter = ReadingFilter::new(ElementSelection::All);
+        let progress_bar = ProgressBar::hidden();
+        let lenient: Vec<Result<ParsedFile<DailyReading>>> = deserialise_archives(
+            vec![archive_path.clone()],
+            &filter,
+            2,
+            ErrorPolicy::Lenient,
+            progress_bar.clone(),
+        )
+        .collect()
+        .await;
+        let strict: Vec<Result<ParsedFile<DailyReading>>> = deserialise_archives(
+            vec![archive_path],
+            &filter,
+            2,
+            ErrorPolicy::Strict,
+            progress_bar.clone(),
+        )
+        .collect()
+        .await;
+
+        // the lenient policy records the archive as failed rather than dropping it
+        assert_eq!(lenient.len(), 1);
+        let failed = lenient[0].as_ref().unwrap().failed.as_ref().unwrap();
+        assert_eq!(failed.file, "ghcnd_hcn.tar.gz");
+        assert!(failed.reason.starts_with("Failed to read "), "{}", failed.reason);
+        assert!(strict[0].is_err());
+        assert_eq!(progress_bar.position(), 2);
+    }
 }
@@ -1,51 +1,312 @@
 //! Downloads and extracts the latest version of the specified dataset.
 
 use std::{
-    fs::{self, File},
-    io::{copy, Cursor, Write},
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind, Write},
     path::{Path, PathBuf},
+    time::Duration,
 };
 
 use anyhow::{Error, Result};
 use flate2::read::GzDecoder;
-use tar::Archive;
-use indicatif::ProgressBar;
 use futures::StreamExt;
+use indicatif::{ProgressBar, ProgressStyle};
+use reqwest::{
+    header::{
+        CONTENT_RANGE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE, LAST_MODIFIED, RANGE,
+    },
+    StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use tar::Archive;
 
-/// Downloads the tarball from the specified URL and saves it to the specified file path.
-pub async fn download_tar(url: &str, file_path: PathBuf) -> Result<(), Error> {
-    let response = reqwest::get(url).await.expect("Failed to download file");
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+/// Response headers identifying the downloaded version of a file, sent back to the server to
+/// check whether it has changed.
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
 
-    if response.status().is_success() {
-        // Create a file to save the downloaded content
+impl Validators {
+    /// Returns the validator for an `If-Range` header, preferring the ETag.
+    fn if_range(&self) -> Option<&String> {
+        self.etag.as_ref().or(self.last_modified.as_ref())
+    }
+}
 
-        let mut file = File::create(file_path)?;
+#[derive(Debug, PartialEq)]
+/// The outcome of a download.
+pub enum Download {
+    /// The server reported the file unchanged since the validators were issued.
+    NotModified,
+    /// The file was downloaded, with the validators of the new version.
+    Complete(Validators),
+}
 
-        let mut content = Cursor::new(response.bytes().await?);
+#[derive(Debug, Clone, PartialEq)]
+/// How often and how patiently a download is attempted.
+pub struct RetryPolicy {
+    /// attempts after the first one
+    pub retries: u32,
+    /// wait before the first retry, doubled for each further retry
+    pub initial_backoff: Duration,
+    /// longest wait for a connection, the response headers or the next part of the body
+    pub timeout: Duration,
+}
 
-        // Write the content to the file
-        copy(&mut content, &mut file)?;
-    } else {
-        println!("Failed to download file: {}", response.status());
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            retries: 3,
+            initial_backoff: Duration::from_secs(1),
+            timeout: Duration::from_secs(60),
+        }
     }
+}
 
-    Ok(())
+impl RetryPolicy {
+    /// Returns the wait before the given retry, counting from zero.
+    fn backoff(&self, retry: u32) -> Duration {
+        self.initial_backoff * 2u32.saturating_pow(retry)
+    }
+}
+
+#[derive(Debug)]
+/// Why a download failed.
+pub enum DownloadError {
+    /// The request could not be sent, e.g. the host was unreachable.
+    Request { url: String, source: reqwest::Error },
+    /// The server responded with an error status.
+    Status { url: String, status: StatusCode },
+    /// The server didn't respond, or stopped sending, within the timeout.
+    Timeout { url: String, timeout: Duration },
+    /// The response body broke off part way.
+    Interrupted { url: String, source: reqwest::Error },
+    /// The server sent part of the file that doesn't continue the partial download.
+    UnexpectedRange {
+        url: String,
+        content_range: Option<String>,
+    },
+    /// The download couldn't be written to disk.
+    Io { path: PathBuf, source: io::Error },
+}
+
+impl DownloadError {
+    /// Returns true if trying again may succeed.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            DownloadError::Request { .. }
+            | DownloadError::Timeout { .. }
+            | DownloadError::Interrupted { .. }
+            | DownloadError::UnexpectedRange { .. } => true,
+            DownloadError::Status { status, .. } => {
+                status.is_server_error()
+                    || *status == StatusCode::REQUEST_TIMEOUT
+                    || *status == StatusCode::TOO_MANY_REQUESTS
+                    || *status == StatusCode::RANGE_NOT_SATISFIABLE
+            }
+            DownloadError::Io { .. } => false,
+        }
+    }
 }
 
-/// Downloads with progress bar based on content length
-pub async fn download_tar_with_progress(url: &str, file_path: PathBuf, progress_bar: ProgressBar) -> Result<(), Error> {
-    let response = reqwest::get(url).await.map_err(|e| Error::msg(format!("Failed to download file: {}", e)))?;
+impl fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DownloadError::Request { url, source } => {
+                write!(f, "Failed to download {}: {}", url, source)
+            }
+            DownloadError::Status { url, status } => {
+                write!(f, "Failed to download {}: server responded {}", url, status)
+            }
+            DownloadError::Timeout { url, timeout } => {
+                write!(f, "Failed to download {}: no response for {:?}", url, timeout)
+            }
+            DownloadError::Interrupted { url, source } => {
+                write!(f, "Download of {} was interrupted: {}", url, source)
+            }
+            DownloadError::UnexpectedRange { url, content_range } => write!(
+                f,
+                "Failed to resume download of {}: server sent range {}",
+                url,
+                content_range.as_deref().unwrap_or("none")
+            ),
+            DownloadError::Io { path, source } => {
+                write!(f, "Failed to write download to {}: {}", path.display(), source)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DownloadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DownloadError::Request { source, .. } | DownloadError::Interrupted { source, .. } => {
+                Some(source)
+            }
+            DownloadError::Io { source, .. } => Some(source),
+            DownloadError::Status { .. }
+            | DownloadError::Timeout { .. }
+            | DownloadError::UnexpectedRange { .. } => None,
+        }
+    }
+}
+
+/// Downloads the file at the URL to the file path, unless the server reports it unchanged since
+/// the validators were issued. Progress is reported on the bar, if given.
+///
+/// The download is written to a `.part` file next to the file path, which replaces the file only
+/// once complete. An interrupted download is resumed from the `.part` file with a `Range`
+/// request if the server still has the same version. Failures that may be temporary are retried
+/// according to the policy.
+pub async fn download(
+    url: &str,
+    file_path: &Path,
+    validators: &Validators,
+    progress_bar: Option<&ProgressBar>,
+    policy: &RetryPolicy,
+) -> Result<Download, DownloadError> {
+    let client = reqwest::Client::builder()
+        .connect_timeout(policy.timeout)
+        .build()
+        .map_err(|source| DownloadError::Request {
+            url: url.to_string(),
+            source,
+        })?;
+
+    let mut retry = 0;
+    loop {
+        match download_once(&client, url, file_path, validators, progress_bar, policy).await {
+            Err(e) if e.is_retryable() && retry < policy.retries => {
+                let backoff = policy.backoff(retry);
+                retry += 1;
+                eprintln!(
+                    "{}, retrying in {:?} ({}/{})",
+                    e, backoff, retry, policy.retries
+                );
+                tokio::time::sleep(backoff).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+async fn download_once(
+    client: &reqwest::Client,
+    url: &str,
+    file_path: &Path,
+    validators: &Validators,
+    progress_bar: Option<&ProgressBar>,
+    policy: &RetryPolicy,
+) -> Result<Download, DownloadError> {
+    let [part_path, part_validators_path] = partial_download_paths(file_path);
+    let timeout = || DownloadError::Timeout {
+        url: url.to_string(),
+        timeout: policy.timeout,
+    };
+
+    // Resume only if we know which version the partial download belongs to
+    let resume_from = fs::metadata(&part_path).map(|m| m.len()).unwrap_or(0);
+    let part_validators = fs::read_to_string(&part_validators_path)
+        .ok()
+        .and_then(|json| serde_json::from_str::<Validators>(&json).ok())
+        .filter(|part_validators| part_validators.if_range().is_some() && resume_from > 0);
+
+    let mut request = client.get(url);
+    if let Some(etag) = &validators.etag {
+        request = request.header(IF_NONE_MATCH, etag);
+    }
+    if let Some(last_modified) = &validators.last_modified {
+        request = request.header(IF_MODIFIED_SINCE, last_modified);
+    }
+    if let Some(if_range) = part_validators.as_ref().and_then(Validators::if_range) {
+        request = request
+            .header(RANGE, format!("bytes={}-", resume_from))
+            .header(IF_RANGE, if_range);
+    }
+
+    let response = tokio::time::timeout(policy.timeout, request.send())
+        .await
+        .map_err(|_| timeout())?
+        .map_err(|source| DownloadError::Request {
+            url: url.to_string(),
+            source,
+        })?;
+
+    let status = response.status();
+    if status == StatusCode::NOT_MODIFIED {
+        remove_if_exists(&part_path)?;
+        remove_if_exists(&part_validators_path)?;
+        return Ok(Download::NotModified);
+    }
+    if status == StatusCode::RANGE_NOT_SATISFIABLE {
+        // The partial download doesn't fit the file on the server, start again
+        remove_if_exists(&part_path)?;
+        remove_if_exists(&part_validators_path)?;
+    }
+    if !status.is_success() {
+        return Err(DownloadError::Status {
+            url: url.to_string(),
+            status,
+        });
+    }
+
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from)
+    };
 
-    if !response.status().is_success() {
-        return Err(Error::msg(format!("Failed to download file: {}", response.status())));
+    // A 206 continues the partial download, anything else replaces it. A 206 from any other offset
+    // than the one asked for would corrupt the file, so the partial download is dropped and the
+    // retry asks for the whole file
+    let resumed = status == StatusCode::PARTIAL_CONTENT;
+    let content_range = header(CONTENT_RANGE);
+    if resumed
+        && (part_validators.is_none()
+            || !content_range
+                .as_ref()
+                .is_some_and(|range| range.starts_with(&format!("bytes {}-", resume_from))))
+    {
+        remove_if_exists(&part_path)?;
+        remove_if_exists(&part_validators_path)?;
+        return Err(DownloadError::UnexpectedRange {
+            url: url.to_string(),
+            content_range,
+        });
     }
+    let write_error = |path: &Path| {
+        let path = path.to_path_buf();
+        move |source| DownloadError::Io { path, source }
+    };
+    let (validators, mut file, mut downloaded) = if resumed {
+        let part_validators = part_validators.unwrap_or_default();
+        let file = OpenOptions::new()
+            .append(true)
+            .open(&part_path)
+            .map_err(write_error(&part_path))?;
+        (part_validators, file, resume_from)
+    } else {
+        let validators = Validators {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        };
+        let json = serde_json::to_string(&validators).map_err(io::Error::from);
+        json.and_then(|json| fs::write(&part_validators_path, json))
+            .map_err(write_error(&part_validators_path))?;
+        let file = File::create(&part_path).map_err(write_error(&part_path))?;
+        (validators, file, 0)
+    };
 
     // Get content length and convert spinner to progress bar if we have size info
     let total_size = response.content_length().unwrap_or(0);
-    if total_size > 0 {
-        // Convert to proper progress bar with known size
-        use indicatif::ProgressStyle;
-        progress_bar.set_length(total_size);
+    if let Some(progress_bar) = progress_bar.filter(|_| total_size > 0) {
+        progress_bar.set_length(downloaded + total_size);
+        progress_bar.set_position(downloaded);
         progress_bar.set_style(
             ProgressStyle::with_template("{msg} [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({percent}%) {eta}")
                 .unwrap()
@@ -53,18 +314,51 @@ pub async fn download_tar_with_progress(url: &str, file_path: PathBuf, progress_
         );
     }
 
-    let mut file = File::create(file_path)?;
-    let mut downloaded = 0u64;
     let mut stream = response.bytes_stream();
 
-    while let Some(chunk_result) = stream.next().await {
-        let chunk = chunk_result.map_err(|e| Error::msg(format!("Error reading chunk: {}", e)))?;
-        file.write_all(&chunk)?;
+    while let Some(chunk_result) = tokio::time::timeout(policy.timeout, stream.next())
+        .await
+        .map_err(|_| timeout())?
+    {
+        let chunk = chunk_result.map_err(|source| DownloadError::Interrupted {
+            url: url.to_string(),
+            source,
+        })?;
+        file.write_all(&chunk).map_err(write_error(&part_path))?;
         downloaded += chunk.len() as u64;
-        progress_bar.set_position(downloaded);
+        if let Some(progress_bar) = progress_bar {
+            progress_bar.set_position(downloaded);
+        }
     }
 
-    Ok(())
+    // Replace the file in one step so an interrupted download never looks complete
+    file.sync_all().map_err(write_error(&part_path))?;
+    fs::rename(&part_path, file_path).map_err(write_error(file_path))?;
+    remove_if_exists(&part_validators_path)?;
+
+    Ok(Download::Complete(validators))
+}
+
+/// Returns the paths an unfinished download of the file and its validators are kept at.
+pub fn partial_download_paths(file_path: &Path) -> [PathBuf; 2] {
+    [suffixed(file_path, ".part"), suffixed(file_path, ".part.json")]
+}
+
+// Appends the suffix to the file name, e.g. `ghcnd_hcn.tar.gz.part`.
+fn suffixed(file_path: &Path, suffix: &str) -> PathBuf {
+    let mut file_name = file_path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(suffix);
+    file_path.with_file_name(file_name)
+}
+
+fn remove_if_exists(path: &Path) -> Result<(), DownloadError> {
+    match fs::remove_file(path) {
+        Err(source) if source.kind() != ErrorKind::NotFound => Err(DownloadError::Io {
+            path: path.to_path_buf(),
+            source,
+        }),
+        _ => Ok(()),
+    }
 }
 
 /// Extracts the tarball at the specified path to the specified working directory.
@@ -84,41 +378,31 @@ pub async fn extract_tar(tar_gz_path: &PathBuf, working_dir: &Path) -> Result<()
     Ok(())
 }
 
-/// Extracts the tarball with progress tracking by counting files
+/// Extracts the tarball with progress tracking by the compressed bytes read, in a single pass
 pub async fn extract_tar_with_progress(tar_gz_path: &PathBuf, working_dir: &Path, progress_bar: ProgressBar) -> Result<(), Error> {
-    // First pass: count total files
     let tar_gz = File::open(tar_gz_path)?;
-    let tar = GzDecoder::new(tar_gz);
-    let mut archive = Archive::new(tar);
-    
-    let total_files = archive.entries()?.count() as u64;
-    
+
     // Convert spinner to progress bar now that we know the total
-    progress_bar.set_length(total_files);
+    progress_bar.set_length(tar_gz.metadata()?.len());
     progress_bar.set_style(
-        indicatif::ProgressStyle::with_template("{msg} [{bar:40.cyan/blue}] {pos}/{len} files ({percent}%) {eta}")
+        ProgressStyle::with_template("{msg} [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({percent}%) {eta}")
             .unwrap()
             .progress_chars("=> "),
     );
-    
-    // Second pass: extract with progress
-    let tar_gz = File::open(tar_gz_path)?;
-    let tar = GzDecoder::new(tar_gz);
+
+    let tar = GzDecoder::new(progress_bar.wrap_read(tar_gz));
     let mut archive = Archive::new(tar);
-    
-    let mut count = 0u64;
+
     for entry in archive.entries()? {
         let mut entry = entry?;
         entry.unpack_in(working_dir)?;
-        count += 1;
-        progress_bar.set_position(count);
     }
 
     Ok(())
 }
 
-/// Returns the path of the extracted folder.
-pub fn get_extraction_folder(working_dir: &Path) -> Result<PathBuf> {
+/// Returns the paths of the extracted folders, e.g. one per NOAA version the tarball contains.
+pub fn get_extraction_folders(working_dir: &Path) -> Result<Vec<PathBuf>> {
     let mut extracted_folders = Vec::new();
     for entry in fs::read_dir(working_dir)? {
         let entry = entry?;
@@ -130,12 +414,32 @@ pub fn get_extraction_folder(working_dir: &Path) -> Result<PathBuf> {
         }
     }
 
-    // Return the first extracted directory
-    if let Some(folder_path) = extracted_folders.first() {
-        Ok(folder_path.clone())
-    } else {
-        Err(Error::msg("No extracted folder found"))
+    if extracted_folders.is_empty() {
+        return Err(Error::msg("No extracted folder found"));
     }
+    extracted_folders.sort();
+
+    Ok(extracted_folders)
+}
+
+/// Returns the top level directory of the first entry in a tarball, which names the NOAA version,
+/// only decompressing as far as that entry.
+pub fn get_tar_root_dir(tar_gz_path: &Path) -> Result<Option<String>> {
+    let tar_gz = File::open(tar_gz_path)?;
+    let mut archive = Archive::new(GzDecoder::new(tar_gz));
+
+    let Some(entry) = archive.entries()?.next() else {
+        return Ok(None);
+    };
+    let entry = entry?;
+    let path = entry.path()?;
+    let is_in_dir = entry.header().entry_type().is_dir() || path.components().count() > 1;
+
+    Ok(path
+        .components()
+        .next()
+        .filter(|_| is_in_dir)
+        .map(|root| root.as_os_str().to_string_lossy().to_string()))
 }
 
 // -- Tests -------------------------------------------------------------------
@@ -144,7 +448,27 @@ pub fn get_extraction_folder(working_dir: &Path) -> Result<PathBuf> {
 mod tests {
     use super::*;
     use tempfile::TempDir;
-    use indicatif::ProgressStyle;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use tokio::net::TcpListener;
+
+    use crate::test_server::{serve, Response};
+
+    fn no_retries() -> RetryPolicy {
+        RetryPolicy {
+            retries: 0,
+            ..RetryPolicy::default()
+        }
+    }
+
+    fn quick_retries(retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            retries,
+            initial_backoff: Duration::from_millis(1),
+            timeout: Duration::from_millis(200),
+        }
+    }
 
     #[test]
     fn should_create_progress_bar_for_download() {
@@ -208,4 +532,308 @@ mod tests {
         
         pb.finish_with_message("✓ Conversion test completed");
     }
+
+    #[tokio::test]
+    async fn should_download_with_validators() {
+        let (base_url, _) = serve(|_| {
+            Response::ok(b"stations")
+                .with_header("ETag", "\"v1\"")
+                .with_header("Last-Modified", "Tue, 16 Jul 2024 10:00:00 GMT")
+        })
+        .await;
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("stations.txt");
+
+        let url = format!("{}/stations.txt", base_url);
+
+        let result = download(&url, &file_path, &Validators::default(), None, &no_retries())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            result,
+            Download::Complete(Validators {
+                etag: Some("\"v1\"".to_string()),
+                last_modified: Some("Tue, 16 Jul 2024 10:00:00 GMT".to_string()),
+            })
+        );
+        assert_eq!(fs::read(&file_path).unwrap(), b"stations");
+    }
+
+    #[tokio::test]
+    async fn should_send_conditional_request() {
+        let (base_url, requests) = serve(|request| match request.header("If-None-Match") {
+            Some("\"v1\"") => Response::status(304),
+            _ => Response::ok(b"changed"),
+        })
+        .await;
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("stations.txt");
+        fs::write(&file_path, "cached").unwrap();
+        let validators = Validators {
+            etag: Some("\"v1\"".to_string()),
+            last_modified: Some("Tue, 16 Jul 2024 10:00:00 GMT".to_string()),
+        };
+
+        let url = format!("{}/stations.txt", base_url);
+
+        let result = download(&url, &file_path, &validators, None, &no_retries()).await.unwrap();
+
+        assert_eq!(result, Download::NotModified);
+        assert_eq!(fs::read(&file_path).unwrap(), b"cached");
+        let requests = requests.lock().unwrap();
+        assert_eq!(
+            requests[0].header("If-Modified-Since"),
+            Some("Tue, 16 Jul 2024 10:00:00 GMT")
+        );
+    }
+
+    #[tokio::test]
+    async fn should_fail_on_error_status() {
+        let (base_url, _) = serve(|_| Response::status(404)).await;
+        let temp_dir = TempDir::new().unwrap();
+        let url = format!("{}/missing.tar.gz", base_url);
+
+        let file_path = temp_dir.path().join("missing.tar.gz");
+
+        let result = download(&url, &file_path, &Validators::default(), None, &no_retries()).await;
+
+        let message = result.unwrap_err().to_string();
+        assert!(message.contains(&url));
+        assert!(message.contains("404"));
+    }
+
+    #[tokio::test]
+    async fn should_not_retry_client_error() {
+        let (base_url, requests) = serve(|_| Response::status(404)).await;
+        let temp_dir = TempDir::new().unwrap();
+        let url = format!("{}/missing.tar.gz", base_url);
+        let file_path = temp_dir.path().join("missing.tar.gz");
+
+        let policy = quick_retries(3);
+        let result = download(&url, &file_path, &Validators::default(), None, &policy).await;
+
+        assert!(matches!(
+            result,
+            Err(DownloadError::Status { status: StatusCode::NOT_FOUND, .. })
+        ));
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn should_retry_server_error() {
+        let attempts = AtomicUsize::new(0);
+        let (base_url, requests) = serve(move |_| match attempts.fetch_add(1, Ordering::SeqCst) {
+            0 | 1 => Response::status(503),
+            _ => Response::ok(b"stations"),
+        })
+        .await;
+        let temp_dir = TempDir::new().unwrap();
+        let url = format!("{}/stations.txt", base_url);
+        let file_path = temp_dir.path().join("stations.txt");
+
+        let policy = quick_retries(2);
+        let result = download(&url, &file_path, &Validators::default(), None, &policy).await;
+
+        assert!(matches!(result, Ok(Download::Complete(_))));
+        assert_eq!(fs::read(&file_path).unwrap(), b"stations");
+        assert_eq!(requests.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn should_give_up_after_retries() {
+        let (base_url, requests) = serve(|_| Response::status(502)).await;
+        let temp_dir = TempDir::new().unwrap();
+        let url = format!("{}/stations.txt", base_url);
+        let file_path = temp_dir.path().join("stations.txt");
+
+        let policy = quick_retries(2);
+        let result = download(&url, &file_path, &Validators::default(), None, &policy).await;
+
+        let message = result.unwrap_err().to_string();
+        assert!(message.contains(&url));
+        assert!(message.contains("502"));
+        assert_eq!(requests.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn should_time_out_unresponsive_server() {
+        // accepts connections but never responds
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/stations.txt", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let mut connections = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                connections.push(stream);
+            }
+        });
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("stations.txt");
+
+        let policy = quick_retries(0);
+        let result = download(&url, &file_path, &Validators::default(), None, &policy).await;
+
+        assert!(matches!(result, Err(DownloadError::Timeout { .. })));
+        assert!(result.unwrap_err().to_string().contains(&url));
+    }
+
+    #[tokio::test]
+    async fn should_resume_interrupted_download() {
+        let body = b"0123456789";
+        let (base_url, requests) = serve(move |request| {
+            let response = match (request.header("Range"), request.header("If-Range")) {
+                (Some("bytes=4-"), Some("\"v1\"")) => Response {
+                    status: 206,
+                    body: body[4..].to_vec(),
+                    ..Response::default()
+                }
+                .with_header("Content-Range", "bytes 4-9/10"),
+                // the first attempt is cut off after four bytes
+                (None, _) => Response::ok(body).closing_after(4),
+                _ => Response::ok(body),
+            };
+            response.with_header("ETag", "\"v1\"")
+        })
+        .await;
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("ghcnd_hcn.tar.gz");
+        let part_path = temp_dir.path().join("ghcnd_hcn.tar.gz.part");
+        let url = format!("{}/ghcnd_hcn.tar.gz", base_url);
+
+        let result = download(&url, &file_path, &Validators::default(), None, &no_retries()).await;
+        assert!(result.is_err());
+        assert!(!file_path.exists());
+        assert_eq!(fs::read(&part_path).unwrap(), b"0123");
+
+        let result = download(&url, &file_path, &Validators::default(), None, &no_retries()).await;
+        assert!(matches!(result, Ok(Download::Complete(_))));
+        assert_eq!(fs::read(&file_path).unwrap(), body);
+        assert!(!part_path.exists());
+        assert!(!temp_dir.path().join("ghcnd_hcn.tar.gz.part.json").exists());
+        assert_eq!(requests.lock().unwrap()[1].header("Range"), Some("bytes=4-"));
+    }
+
+    #[tokio::test]
+    async fn should_resume_interrupted_download_on_retry() {
+        let body = b"0123456789";
+        let (base_url, _) = serve(move |request| {
+            let response = match request.header("Range") {
+                Some("bytes=4-") => Response {
+                    status: 206,
+                    body: body[4..].to_vec(),
+                    ..Response::default()
+                }
+                .with_header("Content-Range", "bytes 4-9/10"),
+                _ => Response::ok(body).closing_after(4),
+            };
+            response.with_header("ETag", "\"v1\"")
+        })
+        .await;
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("ghcnd_hcn.tar.gz");
+        let url = format!("{}/ghcnd_hcn.tar.gz", base_url);
+
+        let policy = quick_retries(1);
+        let result = download(&url, &file_path, &Validators::default(), None, &policy).await;
+
+        assert!(matches!(result, Ok(Download::Complete(_))));
+        assert_eq!(fs::read(&file_path).unwrap(), body);
+    }
+
+    #[tokio::test]
+    async fn should_restart_download_on_mismatched_range() {
+        let body = b"0123456789";
+        let (base_url, requests) = serve(move |request| match request.header("Range") {
+            // the server answers from the wrong offset
+            Some(_) => Response {
+                status: 206,
+                body: body[2..].to_vec(),
+                ..Response::default()
+            }
+            .with_header("Content-Range", "bytes 2-9/10")
+            .with_header("ETag", "\"v1\""),
+            None => Response::ok(body).with_header("ETag", "\"v1\""),
+        })
+        .await;
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("ghcnd_hcn.tar.gz");
+        let part_path = temp_dir.path().join("ghcnd_hcn.tar.gz.part");
+        let part_validators_path = temp_dir.path().join("ghcnd_hcn.tar.gz.part.json");
+        fs::write(&part_path, "0123").unwrap();
+        fs::write(&part_validators_path, r#"{"etag":"\"v1\"","last_modified":null}"#).unwrap();
+        let url = format!("{}/ghcnd_hcn.tar.gz", base_url);
+
+        let result = download(&url, &file_path, &Validators::default(), None, &no_retries()).await;
+        assert!(matches!(result, Err(DownloadError::UnexpectedRange { .. })));
+        assert!(!file_path.exists());
+        assert!(!part_path.exists());
+        assert!(!part_validators_path.exists());
+
+        fs::write(&part_path, "0123").unwrap();
+        fs::write(&part_validators_path, r#"{"etag":"\"v1\"","last_modified":null}"#).unwrap();
+        let policy = quick_retries(1);
+        let result = download(&url, &file_path, &Validators::default(), None, &policy).await;
+        assert!(matches!(result, Ok(Download::Complete(_))));
+        assert_eq!(fs::read(&file_path).unwrap(), body);
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests[1].header("Range"), Some("bytes=4-"));
+        assert_eq!(requests[2].header("Range"), None);
+    }
+
+    #[tokio::test]
+    async fn should_restart_download_of_changed_file() {
+        // the server ignores the range because the file changed since the partial download
+        let (base_url, _) =
+            serve(|_| Response::ok(b"new version").with_header("ETag", "\"v2\"")).await;
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("ghcnd_hcn.tar.gz");
+        fs::write(temp_dir.path().join("ghcnd_hcn.tar.gz.part"), "old ver").unwrap();
+        fs::write(
+            temp_dir.path().join("ghcnd_hcn.tar.gz.part.json"),
+            r#"{"etag":"\"v1\"","last_modified":null}"#,
+        )
+        .unwrap();
+        let url = format!("{}/ghcnd_hcn.tar.gz", base_url);
+
+        download(&url, &file_path, &Validators::default(), None, &no_retries())
+            .await
+            .unwrap();
+
+        assert_eq!(fs::read(&file_path).unwrap(), b"new version");
+    }
+
+    #[tokio::test]
+    async fn should_find_every_extracted_version() {
+        let temp_dir = TempDir::new().unwrap();
+        let archive_path = temp_dir.path().join("ushcn.tmax.latest.raw.tar.gz");
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+            File::create(&archive_path).unwrap(),
+            flate2::Compression::default(),
+        ));
+        for version in ["ushcn.v2.5.5.20240718", "ushcn.v2.5.5.20240716"] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(3);
+            header.set_mode(0o644);
+            header.set_cksum();
+            let path = format!("{}/USH00011084.raw.tmax", version);
+            builder.append_data(&mut header, path, &b"..."[..]).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+
+        let root_dir = get_tar_root_dir(&archive_path).unwrap();
+        assert_eq!(root_dir.as_deref(), Some("ushcn.v2.5.5.20240718"));
+
+        let working_dir = temp_dir.path().join("extracted");
+        fs::create_dir(&working_dir).unwrap();
+        extract_tar(&archive_path, &working_dir).await.unwrap();
+        let folders = get_extraction_folders(&working_dir).unwrap();
+
+        assert_eq!(
+            folders,
+            vec![
+                working_dir.join("ushcn.v2.5.5.20240716"),
+                working_dir.join("ushcn.v2.5.5.20240718"),
+            ]
+        );
+    }
 }
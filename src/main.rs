@@ -10,6 +10,9 @@
 //! in a compressed format ([parquet](https://docs.rs/parquet/52.1.0/parquet/))
 //! that is easy to work with.
 //!
+//! Each dataset can be processed on its own with a subcommand (`daily`, `monthly`, `stations`,
+//! `ghcn-stations`); running without a subcommand, or with `all`, processes everything.
+//!
 //! # Example
 //!
 //! ```rust,no_run
@@ -29,59 +32,181 @@
 use anyhow::{Error, Result};
 use clap::Parser;
 
-use cli::{Cli, command};
+use cache::Cache;
+use report::Report;
+use sources::Sources;
+use cli::{
+    command::{self, OutputConfig, ParseConfig},
+    AllArgs, CacheCommands, Cli, Commands,
+};
 
+mod cache;
 mod cli;
 mod deserialise;
 mod download;
 mod parquet;
 mod reading;
+mod report;
+mod sources;
+#[cfg(test)]
+mod test_server;
 
 #[tokio::main]
 /// The command line utility.
 async fn main() -> Result<(), Error> {
     let cli = Cli::parse();
+    cli.validate().unwrap_or_else(|e| e.exit());
+    let mut output = OutputConfig::new(cli.output_dir.clone(), cli.name_template.clone(), cli.units)?;
+    output.partition_by = cli.partition_by.clone();
+    let parse = ParseConfig {
+        stations: cli.station_filter()?,
+        ..cli.parse_config()
+    };
+    parse.validate()?;
+    // The cache subcommands always work on the persistent cache
+    let persistent = cli.cache || matches!(cli.command, Some(Commands::Cache(_)));
+    let cache = Cache::new(persistent, cli.cache_mode())?.with_retry_policy(cli.retry_policy());
+    let mut sources = Sources::load(
+        cli.config.as_deref(),
+        cli.ushcn_url.clone(),
+        cli.ghcn_daily_url.clone(),
+    )?
+    .import_files(&cli.from_archive)?;
+    if let Some(dir) = &cli.from_dir {
+        sources = sources.import_dir(dir)?;
+    }
+
+    let command = cli.command.clone().unwrap_or_default();
+    let report = Report::new();
+    let result = run(command.clone(), &cache, &sources, &output, &parse, &report).await;
+
+    // The report is written whether or not the run failed
+    if let Some(report_path) = &cli.report {
+        if let Err(e) = &result {
+            report.add_error(command.name(), e);
+        }
+        report.write(report_path)?;
+    }
+
+    result
+}
+
+/// Runs the command.
+async fn run(
+    command: Commands,
+    cache: &Cache,
+    sources: &Sources,
+    output: &OutputConfig,
+    parse: &ParseConfig,
+    report: &Report,
+) -> Result<(), Error> {
+    match command {
+        Commands::All(args) => all(cache, sources, output, parse, &args, report).await,
+        Commands::Daily(args) => {
+            println!("Downloading GHCN stations data...");
+            let ghcn_stations = command::load_ghcn_stations(cache, sources).await?;
+
+            println!("Processing daily data...");
+            let result = command::daily(cache, sources, output, parse, &args, &ghcn_stations, report).await?;
+            println!("Daily: {}", result);
+            Ok(())
+        }
+        Commands::Monthly(args) => {
+            println!("Downloading USHCN stations data...");
+            let ushcn_stations = command::load_ushcn_stations(cache, sources).await?;
 
+            println!("Processing monthly data...");
+            let result =
+                command::monthly(cache, sources, output, parse, &args, &ushcn_stations, report)
+                    .await?;
+            println!("Monthly: {}", result);
+            Ok(())
+        }
+        Commands::Stations => {
+            println!("Downloading USHCN stations data...");
+            let (result, _) = command::stations(cache, sources, output, report).await?;
+            println!("USHCN Stations: {}", result);
+            Ok(())
+        }
+        Commands::GhcnStations => {
+            println!("Downloading GHCN stations data...");
+            let (result, _) = command::ghcn_stations(cache, sources, output, report).await?;
+            println!("GHCN Stations: {}", result);
+            Ok(())
+        }
+        Commands::Cache(args) => {
+            let result = match args.command {
+                CacheCommands::Verify => command::cache::verify(cache)?,
+                CacheCommands::List => command::cache::list(cache)?,
+                CacheCommands::Size => command::cache::size(cache)?,
+                CacheCommands::Clean(args) => command::cache::clean(cache, &args)?,
+                CacheCommands::Path => command::cache::path(cache)?,
+            };
+            println!("{}", result);
+            Ok(())
+        }
+    }
+}
+
+/// Runs every dataset in sequence, reporting errors without aborting the remaining steps.
+async fn all(
+    cache: &Cache,
+    sources: &Sources,
+    output: &OutputConfig,
+    parse: &ParseConfig,
+    args: &AllArgs,
+    report: &Report,
+) -> Result<(), Error> {
     println!("Downloading and processing US Historical Climate Network data...\n");
 
     // Download USHCN stations data for monthly coordinate injection
     println!("Downloading USHCN stations data...");
-    let ushcn_stations = match command::stations(cli.cache).await {
+    let ushcn_stations = match command::stations(cache, sources, output, report).await {
         Ok((result, stations_data)) => {
             println!("USHCN Stations: {}\n", result);
             stations_data
         },
         Err(e) => {
             eprintln!("USHCN Stations error: {}\n", e);
+            report.add_error("ushcn-stations", &e);
             return Ok(());
         }
     };
 
     // Download GHCN stations data for daily coordinate injection
     println!("Downloading GHCN stations data...");
-    let ghcn_stations = match command::ghcn_stations(cli.cache).await {
+    let ghcn_stations = match command::ghcn_stations(cache, sources, output, report).await {
         Ok((result, stations_data)) => {
             println!("GHCN Stations: {}\n", result);
             stations_data
         },
         Err(e) => {
             eprintln!("GHCN Stations error: {}\n", e);
+            report.add_error("ghcn-stations", &e);
             return Ok(());
         }
     };
 
     // Generate daily data with GHCN stations for coordinate injection
     println!("Processing daily data...");
-    match command::daily(cli.cache, &ghcn_stations).await {
+    match command::daily(cache, sources, output, parse, &args.daily, &ghcn_stations, report).await {
         Ok(result) => println!("Daily: {}\n", result),
-        Err(e) => eprintln!("Daily error: {}\n", e),
+        Err(e) => {
+            eprintln!("Daily error: {}\n", e);
+            report.add_error("daily", &e);
+        }
     }
 
     // Generate monthly data with USHCN stations for coordinate injection
     println!("Processing monthly data...");
-    match command::monthly(cli.cache, &ushcn_stations).await {
+    match command::monthly(cache, sources, output, parse, &args.monthly, &ushcn_stations, report)
+        .await
+    {
         Ok(result) => println!("Monthly: {}\n", result),
-        Err(e) => eprintln!("Monthly error: {}\n", e),
+        Err(e) => {
+            eprintln!("Monthly error: {}\n", e);
+            report.add_error("monthly", &e);
+        }
     }
 
     Ok(())
@@ -1,6 +1,6 @@
 //! Save the daily readings to a parquet file.
 
-use std::{fs::File, path::PathBuf, sync::Arc};
+use std::{fs::File, path::Path, sync::Arc};
 
 use anyhow::Result;
 use arrow::{
@@ -11,168 +11,241 @@ use arrow::{
 use chrono::{Datelike, NaiveDate};
 use parquet::{arrow::ArrowWriter, file::properties::WriterProperties};
 
-use crate::{
-    cli::create_progress_bar,
-    reading::{DailyReading, Element},
-};
+use super::partition::{append_parquet_file, file_columns, PartitionKey, PARTITION_CHUNK_SIZE};
+use crate::reading::{DailyFlags, DailyReading, UnitSystem};
+
+/// Rows buffered before they are written as a batch.
+const CHUNK_SIZE: usize = 100000;
+
+/// Writes daily readings to a parquet file one batch at a time, so memory use doesn't grow with
+/// the number of readings.
+pub struct DailyWriter {
+    writer: ArrowWriter<File>,
+    schema: Arc<Schema>,
+    /// columns of the schema written to the file, if not all of them
+    file_columns: Option<Vec<usize>>,
+    builders: DailyBuilders,
+    batch_rows: usize,
+    chunk_size: usize,
+    rows: u64,
+    drop_qc_failed: bool,
+    units: UnitSystem,
+    epoch_offset: i32,
+}
 
-pub fn save_daily(readings: &[DailyReading], file_path: &PathBuf) -> Result<()> {
-    let chunk_size = 100000;
-    
-    // Calculate total actual rows (only for days with values)
-    let mut total_actual_rows = 0;
-    for reading in readings {
-        for value in &reading.values {
-            if value.is_some() {
-                total_actual_rows += 1;
-            }
-        }
+impl DailyWriter {
+    /// Creates the parquet file. Values that failed NOAA quality control are dropped if
+    /// `drop_qc_failed` is set, and values are converted to the unit system. The file of a
+    /// partition leaves out the columns in `partition_by`.
+    pub fn create(
+        file_path: &Path,
+        drop_qc_failed: bool,
+        units: UnitSystem,
+        partition_by: &[PartitionKey],
+    ) -> Result<Self> {
+        let file = File::create(file_path)?;
+
+        // Optimized schema with better compression for Python processing
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Utf8, false),
+            Field::new("date", DataType::Date32, false),
+            Field::new("element", DataType::Utf8, false),
+            Field::new("dataset", DataType::Utf8, false),
+            Field::new("value", DataType::Float32, false),
+            Field::new("unit", DataType::Utf8, false),
+            Field::new("mflag", DataType::Utf8, true),
+            Field::new("qflag", DataType::Utf8, true),
+            Field::new("sflag", DataType::Utf8, true),
+            Field::new("lat", DataType::Float32, true),
+            Field::new("lon", DataType::Float32, true),
+        ]));
+
+        let props = WriterProperties::builder()
+            .set_compression(parquet::basic::Compression::ZSTD(parquet::basic::ZstdLevel::default()))  // Better compression for Python
+            .set_dictionary_enabled(true)  // Enable dictionary encoding for repeated strings
+            .build();
+
+        let file_columns = file_columns(&schema, partition_by);
+        let file_schema = match &file_columns {
+            Some(columns) => Arc::new(schema.project(columns)?),
+            None => schema.clone(),
+        };
+        let writer = ArrowWriter::try_new(file, file_schema, Some(props))?;
+
+        // Partition files are many and small, so they buffer fewer rows
+        let chunk_size = match partition_by {
+            [] => CHUNK_SIZE,
+            _ => PARTITION_CHUNK_SIZE,
+        };
+
+        Ok(DailyWriter {
+            writer,
+            schema,
+            file_columns,
+            // Pre-allocate builders for better performance
+            builders: DailyBuilders::with_capacity(chunk_size),
+            batch_rows: 0,
+            chunk_size,
+            rows: 0,
+            drop_qc_failed,
+            units,
+            // Pre-calculate epoch offset
+            epoch_offset: NaiveDate::from_ymd_opt(1970, 1, 1).unwrap().num_days_from_ce(),
+        })
     }
 
-    let file = File::create(file_path)?;
-
-    // Optimized schema with better compression for Python processing
-    let schema = Arc::new(Schema::new(vec![
-        Field::new("id", DataType::Utf8, false),
-        Field::new("date", DataType::Date32, false),
-        Field::new("element", DataType::Utf8, false),
-        Field::new("dataset", DataType::Utf8, false),
-        Field::new("value", DataType::Float32, false),
-        Field::new("lat", DataType::Float32, true),
-        Field::new("lon", DataType::Float32, true),
-    ]));
-
-    let props = WriterProperties::builder()
-        .set_compression(parquet::basic::Compression::ZSTD(parquet::basic::ZstdLevel::default()))  // Better compression for Python
-        .set_dictionary_enabled(true)  // Enable dictionary encoding for repeated strings
-        .build();
-
-    let mut writer = ArrowWriter::try_new(file, schema.clone(), Some(props))?;
-    let pb = create_progress_bar(total_actual_rows as u64, "Writing parquet file".to_string());
-
-    // Pre-allocate builders for better performance
-    let mut id_builder = StringBuilder::with_capacity(chunk_size, chunk_size * 12);
-    let mut date_builder = Date32Builder::with_capacity(chunk_size);
-    let mut element_builder = StringBuilder::with_capacity(chunk_size, chunk_size * 4);
-    let mut dataset_builder = StringBuilder::with_capacity(chunk_size, chunk_size * 6);
-    let mut value_builder = Float32Builder::with_capacity(chunk_size);
-    let mut lat_builder = Float32Builder::with_capacity(chunk_size);
-    let mut lon_builder = Float32Builder::with_capacity(chunk_size);
-
-    // Pre-calculate epoch offset
-    let epoch_offset = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap().num_days_from_ce();
-
-    let mut current_batch_rows = 0;
-    let mut progress_counter = 0;
-
-    for reading in readings {
+    /// Appends a row for each value of the reading, writing a batch whenever one is full.
+    pub fn write(&mut self, reading: &DailyReading) -> Result<()> {
         let year = reading.year;
         let month = reading.month.unwrap();
-        
-        // Pre-calculate strings once per reading  
-        let element_str = element_to_string(&reading.properties.element);
-        let dataset_str = dataset_to_string(&reading.properties.dataset);
+        let no_flags = DailyFlags::default();
+
+        // Pre-calculate strings once per reading
+        let element_str = reading.properties.element.code();
+        let dataset_str = reading.properties.dataset.as_str();
+        let unit = reading.properties.element.unit();
+        let unit_str = unit.in_system(self.units).symbol();
 
         for (day_index, value_opt) in reading.values.iter().enumerate() {
             if let Some(value) = value_opt {
+                let flags = reading.flags.get(day_index).unwrap_or(&no_flags);
+                if self.drop_qc_failed && flags.failed_qc() {
+                    continue;
+                }
+
                 let day = (day_index + 1) as u32;
-                
+
                 // Create date - optimized calculation
                 if let Some(valid_date) = NaiveDate::from_ymd_opt(year as i32, month as u32, day) {
-                    let date32 = valid_date.num_days_from_ce() - epoch_offset;
-
-                    // Use builders for better performance
-                    id_builder.append_value(&reading.id);
-                    date_builder.append_value(date32);
-                    element_builder.append_value(&element_str);
-                    dataset_builder.append_value(&dataset_str);
-                    value_builder.append_value(*value);
-                    lat_builder.append_option(reading.lat);
-                    lon_builder.append_option(reading.lon);
-
-                    current_batch_rows += 1;
-                    progress_counter += 1;
-
-                    // Batch progress updates for better performance
-                    if progress_counter % 10000 == 0 {
-                        pb.set_position(progress_counter as u64);
-                    }
+                    let date32 = valid_date.num_days_from_ce() - self.epoch_offset;
+
+                    let value = unit.convert(*value, self.units);
+                    let labels = [element_str.as_str(), dataset_str, unit_str];
+                    self.builders.append(reading, date32, labels, value, flags);
+                    self.batch_rows += 1;
 
                     // Write batch when full
-                    if current_batch_rows >= chunk_size {
-                        write_batch_optimized(&mut writer, &schema, &mut id_builder, &mut date_builder, 
-                                            &mut element_builder, &mut dataset_builder, &mut value_builder, 
-                                            &mut lat_builder, &mut lon_builder)?;
-                        current_batch_rows = 0;
+                    if self.batch_rows >= self.chunk_size {
+                        self.write_batch()?;
                     }
                 }
             }
         }
+
+        Ok(())
     }
 
-    // Write remaining data
-    if current_batch_rows > 0 {
-        write_batch_optimized(&mut writer, &schema, &mut id_builder, &mut date_builder, 
-                            &mut element_builder, &mut dataset_builder, &mut value_builder, 
-                            &mut lat_builder, &mut lon_builder)?;
+    /// Appends the rows of a parquet file written by a DailyWriter with the same partition
+    /// columns, e.g. another part file of the same partition.
+    pub fn append_file(&mut self, file_path: &Path) -> Result<()> {
+        self.rows += append_parquet_file(&mut self.writer, file_path)?;
+
+        Ok(())
     }
 
-    pb.finish_with_message("Finished writing Parquet file");
-    writer.close()?;
-    Ok(())
+    /// Writes the remaining rows and closes the file. Returns the number of rows written.
+    pub fn close(mut self) -> Result<u64> {
+        if self.batch_rows > 0 {
+            self.write_batch()?;
+        }
+        self.writer.close()?;
+
+        Ok(self.rows)
+    }
+
+    fn write_batch(&mut self) -> Result<()> {
+        let batch = self.builders.finish(&self.schema)?;
+        match &self.file_columns {
+            Some(columns) => self.writer.write(&batch.project(columns)?)?,
+            None => self.writer.write(&batch)?,
+        }
+        self.rows += self.batch_rows as u64;
+        self.batch_rows = 0;
+
+        Ok(())
+    }
 }
 
-fn write_batch_optimized(
-    writer: &mut ArrowWriter<File>,
-    schema: &Arc<Schema>,
-    id_builder: &mut StringBuilder,
-    date_builder: &mut Date32Builder,
-    element_builder: &mut StringBuilder,
-    dataset_builder: &mut StringBuilder,
-    value_builder: &mut Float32Builder,
-    lat_builder: &mut Float32Builder,
-    lon_builder: &mut Float32Builder,
-) -> Result<()> {
-    let ids_array = id_builder.finish();
-    let dates_array = date_builder.finish();
-    let elements_array = element_builder.finish();
-    let datasets_array = dataset_builder.finish();
-    let values_array = value_builder.finish();
-    let lats_array = lat_builder.finish();
-    let lons_array = lon_builder.finish();
-
-    let batch = RecordBatch::try_new(
-        schema.clone(),
-        vec![
-            Arc::new(ids_array),
-            Arc::new(dates_array),
-            Arc::new(elements_array),
-            Arc::new(datasets_array),
-            Arc::new(values_array),
-            Arc::new(lats_array),
-            Arc::new(lons_array),
-        ],
-    )?;
-
-    writer.write(&batch)?;
-    Ok(())
+/// Column builders for one batch of daily rows.
+struct DailyBuilders {
+    id: StringBuilder,
+    date: Date32Builder,
+    element: StringBuilder,
+    dataset: StringBuilder,
+    value: Float32Builder,
+    unit: StringBuilder,
+    mflag: StringBuilder,
+    qflag: StringBuilder,
+    sflag: StringBuilder,
+    lat: Float32Builder,
+    lon: Float32Builder,
 }
 
-fn element_to_string(element: &Element) -> String {
-    match element {
-        Element::Tmax => "TMAX".to_string(),
-        Element::Tmin => "TMIN".to_string(),
-        Element::Prcp => "PRCP".to_string(),
-        _ => "UNKNOWN".to_string(),
+impl DailyBuilders {
+    fn with_capacity(chunk_size: usize) -> Self {
+        DailyBuilders {
+            id: StringBuilder::with_capacity(chunk_size, chunk_size * 12),
+            date: Date32Builder::with_capacity(chunk_size),
+            element: StringBuilder::with_capacity(chunk_size, chunk_size * 4),
+            dataset: StringBuilder::with_capacity(chunk_size, chunk_size * 6),
+            value: Float32Builder::with_capacity(chunk_size),
+            unit: StringBuilder::with_capacity(chunk_size, chunk_size * 4),
+            mflag: StringBuilder::with_capacity(chunk_size, chunk_size),
+            qflag: StringBuilder::with_capacity(chunk_size, chunk_size),
+            sflag: StringBuilder::with_capacity(chunk_size, chunk_size),
+            lat: Float32Builder::with_capacity(chunk_size),
+            lon: Float32Builder::with_capacity(chunk_size),
+        }
+    }
+
+    fn append(
+        &mut self,
+        reading: &DailyReading,
+        date32: i32,
+        [element, dataset, unit]: [&str; 3],
+        value: f32,
+        flags: &DailyFlags,
+    ) {
+        self.id.append_value(&reading.id);
+        self.date.append_value(date32);
+        self.element.append_value(element);
+        self.dataset.append_value(dataset);
+        self.value.append_value(value);
+        self.unit.append_value(unit);
+        append_flag(&mut self.mflag, flags.mflag);
+        append_flag(&mut self.qflag, flags.qflag);
+        append_flag(&mut self.sflag, flags.sflag);
+        self.lat.append_option(reading.lat);
+        self.lon.append_option(reading.lon);
+    }
+
+    /// Builds a RecordBatch from the appended rows and resets the builders.
+    fn finish(&mut self, schema: &Arc<Schema>) -> Result<RecordBatch> {
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(self.id.finish()),
+                Arc::new(self.date.finish()),
+                Arc::new(self.element.finish()),
+                Arc::new(self.dataset.finish()),
+                Arc::new(self.value.finish()),
+                Arc::new(self.unit.finish()),
+                Arc::new(self.mflag.finish()),
+                Arc::new(self.qflag.finish()),
+                Arc::new(self.sflag.finish()),
+                Arc::new(self.lat.finish()),
+                Arc::new(self.lon.finish()),
+            ],
+        )?;
+
+        Ok(batch)
     }
 }
 
-fn dataset_to_string(dataset: &crate::reading::Dataset) -> String {
-    match dataset {
-        crate::reading::Dataset::Raw => "RAW".to_string(),
-        crate::reading::Dataset::Tob => "TOB".to_string(),
-        crate::reading::Dataset::Fls52 => "FLS52".to_string(),
-        _ => "UNKNOWN".to_string(),
+pub(super) fn append_flag(builder: &mut StringBuilder, flag: Option<char>) {
+    match flag {
+        Some(c) => builder.append_value(c.encode_utf8(&mut [0; 4])),
+        None => builder.append_null(),
     }
 }
 
@@ -180,15 +253,28 @@ fn dataset_to_string(dataset: &crate::reading::Dataset) -> String {
 
 #[cfg(test)]
 mod test {
-    use std::fs;
-    use arrow::array::{Array, StringArray};
+    use std::{fs, path::PathBuf};
+    use arrow::array::{Array, Float32Array, StringArray};
     use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
     use tempfile::NamedTempFile;
 
-    use crate::reading::{Dataset, FileProperties};
+    use crate::reading::{Dataset, Element, FileProperties};
 
     use super::*;
 
+    fn save_daily(
+        readings: &[DailyReading],
+        file_path: &Path,
+        drop_qc_failed: bool,
+        units: UnitSystem,
+    ) -> Result<u64> {
+        let mut writer = DailyWriter::create(file_path, drop_qc_failed, units, &[])?;
+        for reading in readings {
+            writer.write(reading)?;
+        }
+        writer.close()
+    }
+
     #[test]
     fn should_round_trip_multi_column() {
         // arrange
@@ -197,7 +283,11 @@ mod test {
         assert_eq!(readings[0].values[30], Some(40.0));
 
         // act
-        save_daily(&readings, &PathBuf::from("test")).unwrap();
+        let temp_file = NamedTempFile::new().unwrap();
+        let rows = save_daily(&readings, temp_file.path(), false, UnitSystem::Metric).unwrap();
+
+        // assert
+        assert!(rows > 0);
     }
 
     #[test]
@@ -210,7 +300,7 @@ mod test {
         let temp_path = temp_file.path().to_path_buf();
         
         // Save to parquet
-        save_daily(&readings, &temp_path).unwrap();
+        save_daily(&readings, &temp_path, false, UnitSystem::Metric).unwrap();
         
         // Read back and validate
         let file = fs::File::open(&temp_path).unwrap();
@@ -230,14 +320,18 @@ mod test {
             
             // Validate new long format schema
             let schema = batch.schema();
-            assert_eq!(schema.fields().len(), 7);
+            assert_eq!(schema.fields().len(), 11);
             assert_eq!(schema.field(0).name(), "id");
             assert_eq!(schema.field(1).name(), "date");
             assert_eq!(schema.field(2).name(), "element");
             assert_eq!(schema.field(3).name(), "dataset");
             assert_eq!(schema.field(4).name(), "value");
-            assert_eq!(schema.field(5).name(), "lat");
-            assert_eq!(schema.field(6).name(), "lon");
+            assert_eq!(schema.field(5).name(), "unit");
+            assert_eq!(schema.field(6).name(), "mflag");
+            assert_eq!(schema.field(7).name(), "qflag");
+            assert_eq!(schema.field(8).name(), "sflag");
+            assert_eq!(schema.field(9).name(), "lat");
+            assert_eq!(schema.field(10).name(), "lon");
             
             // Count element types
             let element_array = batch.column(2);
@@ -268,6 +362,77 @@ mod test {
         assert_eq!(prcp_count, 31);
     }
 
+    #[test]
+    fn should_write_flags_and_drop_qc_failed_values() {
+        let mut readings = readings_fixture();
+        readings[0].flags[0] = DailyFlags { mflag: Some('T'), qflag: None, sflag: Some('6') };
+        readings[0].flags[1] = DailyFlags { mflag: None, qflag: Some('X'), sflag: Some('6') };
+
+        let temp_file = NamedTempFile::new().unwrap();
+        let temp_path = temp_file.path().to_path_buf();
+
+        // all values are kept by default, with their flags
+        save_daily(&readings, &temp_path, false, UnitSystem::Metric).unwrap();
+        let batch = read_first_batch(&temp_path);
+        let mflags = batch.column(6).as_any().downcast_ref::<StringArray>().unwrap();
+        let qflags = batch.column(7).as_any().downcast_ref::<StringArray>().unwrap();
+        let sflags = batch.column(8).as_any().downcast_ref::<StringArray>().unwrap();
+
+        // January has 31 days and February 2020 has 29
+        assert_eq!(batch.num_rows(), 60);
+        assert_eq!(mflags.value(0), "T");
+        assert_eq!(sflags.value(0), "6");
+        assert_eq!(qflags.value(1), "X");
+        assert!(qflags.is_null(0));
+        assert_eq!(qflags.null_count(), 59);
+
+        // the QC-failed value is dropped on request
+        save_daily(&readings, &temp_path, true, UnitSystem::Metric).unwrap();
+        let batch = read_first_batch(&temp_path);
+        let qflags = batch.column(7).as_any().downcast_ref::<StringArray>().unwrap();
+
+        assert_eq!(batch.num_rows(), 59);
+        assert_eq!(qflags.null_count(), 59);
+    }
+
+    #[test]
+    fn should_write_units_and_convert_to_imperial() {
+        let mut readings = readings_fixture();
+        readings[1].properties.element = Element::Prcp;
+
+        let temp_file = NamedTempFile::new().unwrap();
+        let temp_path = temp_file.path().to_path_buf();
+
+        save_daily(&readings, &temp_path, false, UnitSystem::Metric).unwrap();
+        let batch = read_first_batch(&temp_path);
+        let values = batch.column(4).as_any().downcast_ref::<Float32Array>().unwrap();
+        let units = batch.column(5).as_any().downcast_ref::<StringArray>().unwrap();
+
+        assert_eq!(values.value(0), 10.0);
+        assert_eq!(units.value(0), "degC");
+        assert_eq!(units.value(31), "mm");
+
+        save_daily(&readings, &temp_path, false, UnitSystem::Imperial).unwrap();
+        let batch = read_first_batch(&temp_path);
+        let values = batch.column(4).as_any().downcast_ref::<Float32Array>().unwrap();
+        let units = batch.column(5).as_any().downcast_ref::<StringArray>().unwrap();
+
+        assert_eq!(values.value(0), 50.0);
+        assert_eq!(units.value(0), "degF");
+        assert_eq!(values.value(31), 10.0 / 25.4);
+        assert_eq!(units.value(31), "in");
+    }
+
+    fn read_first_batch(path: &PathBuf) -> RecordBatch {
+        let file = fs::File::open(path).unwrap();
+        let mut reader = ParquetRecordBatchReaderBuilder::try_new(file)
+            .unwrap()
+            .build()
+            .unwrap();
+
+        reader.next().unwrap().unwrap()
+    }
+
     #[test] 
     fn should_demonstrate_efficiency_improvement() {
         // Create test data with realistic sparsity
@@ -278,7 +443,7 @@ mod test {
         let new_path = new_file.path().to_path_buf();
         
         // Save with new long format
-        save_daily(&readings, &new_path).unwrap();
+        save_daily(&readings, &new_path, false, UnitSystem::Metric).unwrap();
         
         // Check file size and row count
         let new_file_size = fs::metadata(&new_path).unwrap().len();
@@ -338,6 +503,7 @@ mod test {
                     element: Element::Tmax,
                 },
                 values,
+                flags: vec![DailyFlags::default(); 31],
             });
         }
         
@@ -431,6 +597,7 @@ mod test {
                     element: Element::Tmax,
                 },
                 values: values.clone(),
+                flags: vec![DailyFlags::default(); 31],
             },
             // TMIN reading  
             DailyReading {
@@ -444,6 +611,7 @@ mod test {
                     element: Element::Tmin,
                 },
                 values: values.iter().map(|v| v.map(|x| x - 5.0)).collect(),
+                flags: vec![DailyFlags::default(); 31],
             },
             // PRCP reading
             DailyReading {
@@ -457,6 +625,7 @@ mod test {
                     element: Element::Prcp,
                 },
                 values: values.iter().map(|v| v.map(|x| x * 0.1)).collect(),
+                flags: vec![DailyFlags::default(); 31],
             },
         ]
     }
@@ -480,6 +649,7 @@ mod test {
                 month: Some(1),
                 properties: properties.clone(),
                 values: values.clone(),
+                flags: vec![DailyFlags::default(); 31],
             },
             DailyReading {
                 id: "USW00094729".to_string(),
@@ -489,6 +659,7 @@ mod test {
                 month: Some(2),
                 properties: properties.clone(),
                 values,
+                flags: vec![DailyFlags::default(); 31],
             },
         ]
     }
@@ -2,8 +2,10 @@
 
 pub mod daily;
 pub mod monthly;
+pub mod partition;
 pub mod stations;
 
-pub use daily::save_daily;
-pub use monthly::save_monthly;
+pub use daily::DailyWriter;
+pub use monthly::{MonthlyLayout, MonthlyWriter};
+pub use partition::{OutputWriter, Partition, PartitionKey, PartitionedWriter};
 pub use stations::save_stations;
@@ -2,330 +2,826 @@
 
 use anyhow::Result;
 use arrow::{
-    array::{ArrayRef, Date32Array, Float32Array, RecordBatch, StringArray},
+    array::{
+        ArrayRef, Date32Array, Date32Builder, Float32Array, Float32Builder, RecordBatch,
+        StringArray, StringBuilder,
+    },
     datatypes::{DataType, Field, Schema},
 };
 use chrono::{Datelike, NaiveDate};
+use clap::ValueEnum;
 use parquet::{arrow::ArrowWriter, file::properties::WriterProperties};
-use std::{fs::File, path::PathBuf, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    path::Path,
+    sync::Arc,
+};
 
-use crate::{
-    cli::create_progress_bar,
-    reading::{Dataset, Element, MonthlyReading},
+use super::{
+    daily::append_flag,
+    partition::{append_parquet_file, file_columns, PartitionKey, PARTITION_CHUNK_SIZE},
 };
+use crate::reading::{Dataset, Element, MonthlyFlags, MonthlyReading, UnitSystem};
+
+/// Rows buffered before they are written as a batch.
+const CHUNK_SIZE: usize = 100000;
+
+const MONTHS_PER_YEAR: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+/// How monthly readings are laid out in the parquet files.
+pub enum MonthlyLayout {
+    /// One file with a row per station, month, element and dataset, like the daily file
+    Long,
+    /// A file per dataset with a column per element and dataset, of which each row fills one
+    #[default]
+    Wide,
+    /// One file with a row per station and month, joining every element and dataset
+    Pivoted,
+}
+
+/// Writes monthly readings to a parquet file one batch at a time, so memory use doesn't grow with
+/// the number of readings. The pivoted layout is the exception: it holds every reading until the
+/// file is closed, to join the readings of each station and month.
+pub struct MonthlyWriter {
+    writer: ArrowWriter<File>,
+    schema: Arc<Schema>,
+    /// columns of the schema written to the file, if not all of them
+    file_columns: Option<Vec<usize>>,
+    value_columns: Vec<(String, Element, Dataset)>,
+    rows: MonthlyRows,
+    batch_rows: usize,
+    chunk_size: usize,
+    written_rows: u64,
+    units: UnitSystem,
+    epoch_offset: i32,
+}
+
+/// The rows buffered for the next batch, by layout.
+enum MonthlyRows {
+    Long(Box<LongBuilders>),
+    Wide(MonthlyColumns),
+    Pivoted(BTreeMap<(String, u16), PivotedYear>),
+}
+
+impl MonthlyWriter {
+    /// Creates the parquet file, with values converted to the unit system. The file of a partition
+    /// leaves out the columns in `partition_by`.
+    pub fn create(
+        file_path: &Path,
+        layout: MonthlyLayout,
+        units: UnitSystem,
+        partition_by: &[PartitionKey],
+    ) -> Result<Self> {
+        let value_columns = value_columns();
+
+        // Initialize the Parquet writer
+        let file = File::create(file_path)?;
+
+        let schema = match layout {
+            MonthlyLayout::Long => long_schema(),
+            MonthlyLayout::Wide | MonthlyLayout::Pivoted => wide_schema(&value_columns, units),
+        };
+
+        let props = WriterProperties::builder()
+            .set_compression(parquet::basic::Compression::SNAPPY)
+            .build();
+
+        let file_columns = file_columns(&schema, partition_by);
+        let file_schema = match &file_columns {
+            Some(columns) => Arc::new(schema.project(columns)?),
+            None => schema.clone(),
+        };
+        let writer = ArrowWriter::try_new(file, file_schema, Some(props))?;
+
+        // Prepare vectors to hold column data, fewer rows for the many small partition files
+        let chunk_size = match partition_by {
+            [] => CHUNK_SIZE,
+            _ => PARTITION_CHUNK_SIZE,
+        };
+        let rows = match layout {
+            MonthlyLayout::Long => {
+                MonthlyRows::Long(Box::new(LongBuilders::with_capacity(chunk_size)))
+            }
+            MonthlyLayout::Wide => MonthlyRows::Wide(MonthlyColumns::with_capacity(
+                chunk_size,
+                value_columns.len(),
+            )),
+            MonthlyLayout::Pivoted => MonthlyRows::Pivoted(BTreeMap::new()),
+        };
+
+        let epoch_offset = NaiveDate::from_ymd_opt(1970, 1, 1)
+            .unwrap()
+            .num_days_from_ce();
+
+        Ok(MonthlyWriter {
+            writer,
+            schema,
+            file_columns,
+            value_columns,
+            rows,
+            batch_rows: 0,
+            chunk_size,
+            written_rows: 0,
+            units,
+            epoch_offset,
+        })
+    }
+
+    /// Appends the rows of the reading, writing a batch whenever one is full: a row for each month
+    /// in the wide layout, and a row for each month with a value in the long layout. In the
+    /// pivoted layout the values are joined to those of the same station and year.
+    pub fn write(&mut self, r: &MonthlyReading) -> Result<()> {
+        // Each reading fills the one column matching its element and dataset
+        let column = self.value_columns.iter().position(|(_, element, dataset)| {
+            *element == r.properties.element && *dataset == r.properties.dataset
+        });
+        let unit = r.properties.element.unit();
+        let units = self.units;
+        let no_flags = MonthlyFlags::default();
+        let month_value = |month: usize| {
+            let value = r.values.get(month).copied().flatten();
+            let flags = r.flags.get(month).unwrap_or(&no_flags);
+            (value.map(|v| unit.convert(v, units)), flags)
+        };
+
+        if let MonthlyRows::Pivoted(years) = &mut self.rows {
+            let Some(column) = column else {
+                return Ok(());
+            };
+            let year = years
+                .entry((r.id.clone(), r.year))
+                .or_insert_with(|| PivotedYear {
+                    lat: r.lat,
+                    lon: r.lon,
+                    columns: vec![None; self.value_columns.len()],
+                });
+            let cells = std::array::from_fn(|month| {
+                let (value, flags) = month_value(month);
+                PivotedCell::new(value, flags)
+            });
+            year.columns[column] = Some(Box::new(cells));
+
+            return Ok(());
+        }
+
+        let element = r.properties.element.code();
+        let dataset = r.properties.dataset.as_str();
+        let unit_symbol = unit.in_system(units).symbol();
+        for month in 0..MONTHS_PER_YEAR {
+            let date32 = date32(r.year, month, self.epoch_offset);
+            let (value, flags) = month_value(month);
+            match &mut self.rows {
+                MonthlyRows::Long(builders) => {
+                    let Some(value) = value else {
+                        continue;
+                    };
+                    builders.append(r, date32, [&element, dataset, unit_symbol], value, flags);
+                }
+                MonthlyRows::Wide(columns) => {
+                    columns.push_row(&r.id, date32, r.lat, r.lon, |index| {
+                        (Some(index) == column).then(|| (value, flags.clone()))
+                    });
+                }
+                MonthlyRows::Pivoted(_) => unreachable!("pivoted readings are joined above"),
+            }
+            self.batch_rows += 1;
+            if self.batch_rows == self.chunk_size {
+                self.write_batch()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Appends the rows of a parquet file written by a MonthlyWriter with the same layout and
+    /// partition columns, e.g. another part file of the same partition.
+    pub fn append_file(&mut self, file_path: &Path) -> Result<()> {
+        self.written_rows += append_parquet_file(&mut self.writer, file_path)?;
+
+        Ok(())
+    }
 
-pub fn save_monthly(readings: &[MonthlyReading], file_path: &PathBuf) -> Result<()> {
-    let months_per_year = 12;
-    let chunk_size = 100000;
-    let total_rows = readings.len() * months_per_year;
+    /// Writes the remaining rows and closes the file. Returns the number of rows written.
+    pub fn close(mut self) -> Result<u64> {
+        if let MonthlyRows::Pivoted(years) = &mut self.rows {
+            let years = std::mem::take(years);
+            self.rows = MonthlyRows::Wide(MonthlyColumns::with_capacity(
+                self.chunk_size,
+                self.value_columns.len(),
+            ));
+            self.write_pivoted(years)?;
+        }
+
+        if self.batch_rows > 0 {
+            self.write_batch()?;
+        }
+        self.writer.close()?;
+
+        Ok(self.written_rows)
+    }
+
+    /// Writes a row for each month of each station and year, in station and date order.
+    fn write_pivoted(&mut self, years: BTreeMap<(String, u16), PivotedYear>) -> Result<()> {
+        for ((id, year), pivoted) in years {
+            for month in 0..MONTHS_PER_YEAR {
+                let date32 = date32(year, month, self.epoch_offset);
+                let MonthlyRows::Wide(columns) = &mut self.rows else {
+                    unreachable!("pivoted rows are written through the wide columns");
+                };
+                columns.push_row(&id, date32, pivoted.lat, pivoted.lon, |index| {
+                    pivoted.columns[index]
+                        .as_ref()
+                        .map(|cells| cells[month].get())
+                });
+                self.batch_rows += 1;
+                if self.batch_rows == self.chunk_size {
+                    self.write_batch()?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_batch(&mut self) -> Result<()> {
+        let batch = match &mut self.rows {
+            MonthlyRows::Long(builders) => builders.finish(&self.schema)?,
+            MonthlyRows::Wide(columns) => columns.take_batch(&self.schema)?,
+            MonthlyRows::Pivoted(_) => return Ok(()),
+        };
+        match &self.file_columns {
+            Some(columns) => self.writer.write(&batch.project(columns)?)?,
+            None => self.writer.write(&batch)?,
+        }
+        self.written_rows += self.batch_rows as u64;
+        self.batch_rows = 0;
+
+        Ok(())
+    }
+}
+
+/// Returns the days since the epoch of the first of a month, counting months from 0.
+fn date32(year: u16, month: usize, epoch_offset: i32) -> Option<i32> {
+    NaiveDate::from_ymd_opt(year as i32, month as u32 + 1, 1)
+        .map(|valid_date| valid_date.num_days_from_ce() - epoch_offset)
+}
+
+/// Suffixes of the flag columns that follow each value column.
+const FLAG_NAMES: [&str; 3] = ["dmflag", "qcflag", "dsflag"];
+
+/// Returns the monthly value columns in schema order, with the element and dataset that fill them.
+fn value_columns() -> Vec<(String, Element, Dataset)> {
+    let elements = [
+        ("max", Element::Max),
+        ("min", Element::Min),
+        ("avg", Element::Avg),
+        ("prcp", Element::Prcp),
+    ];
+    let datasets = [
+        ("raw", Dataset::Raw),
+        ("tob", Dataset::Tob),
+        ("fls52", Dataset::Fls52),
+    ];
 
-    // Initialize the Parquet writer
-    let file = File::create(file_path)?;
+    let mut columns = Vec::new();
+    for (element_name, element) in &elements {
+        for (dataset_name, dataset) in &datasets {
+            columns.push((
+                format!("{}_{}", element_name, dataset_name),
+                element.clone(),
+                dataset.clone(),
+            ));
+        }
+    }
 
-    // Define the schema for the RecordBatch
-    let schema = Arc::new(Schema::new(vec![
+    columns
+}
+
+/// Returns the schema of the wide and pivoted layouts, with the three flags following each value
+/// and the unit of each value in its field metadata.
+fn wide_schema(value_columns: &[(String, Element, Dataset)], units: UnitSystem) -> Arc<Schema> {
+    let mut fields = vec![
         Field::new("id", DataType::Utf8, false),
         Field::new("date", DataType::Date32, true),
-        Field::new("max_raw", DataType::Float32, true),
-        Field::new("max_tob", DataType::Float32, true),
-        Field::new("max_fls52", DataType::Float32, true),
-        Field::new("min_raw", DataType::Float32, true),
-        Field::new("min_tob", DataType::Float32, true),
-        Field::new("min_fls52", DataType::Float32, true),
-        Field::new("avg_raw", DataType::Float32, true),
-        Field::new("avg_tob", DataType::Float32, true),
-        Field::new("avg_fls52", DataType::Float32, true),
+    ];
+    for (name, element, _) in value_columns {
+        let unit = element.unit().in_system(units).symbol();
+        fields.push(
+            Field::new(name, DataType::Float32, true)
+                .with_metadata(HashMap::from([("unit".to_string(), unit.to_string())])),
+        );
+        for flag_name in FLAG_NAMES {
+            fields.push(Field::new(
+                format!("{}_{}", name, flag_name),
+                DataType::Utf8,
+                true,
+            ));
+        }
+    }
+    fields.push(Field::new("lat", DataType::Float32, true));
+    fields.push(Field::new("lon", DataType::Float32, true));
+
+    Arc::new(Schema::new(fields))
+}
+
+/// Returns the schema of the long layout, matching the daily file.
+fn long_schema() -> Arc<Schema> {
+    Arc::new(Schema::new(vec![
+        Field::new("id", DataType::Utf8, false),
+        Field::new("date", DataType::Date32, true),
+        Field::new("element", DataType::Utf8, false),
+        Field::new("dataset", DataType::Utf8, false),
+        Field::new("value", DataType::Float32, false),
+        Field::new("unit", DataType::Utf8, false),
+        Field::new("dmflag", DataType::Utf8, true),
+        Field::new("qcflag", DataType::Utf8, true),
+        Field::new("dsflag", DataType::Utf8, true),
         Field::new("lat", DataType::Float32, true),
         Field::new("lon", DataType::Float32, true),
-    ]));
-
-    let props = WriterProperties::builder()
-        .set_compression(parquet::basic::Compression::SNAPPY)
-        .build();
-
-    let mut writer = ArrowWriter::try_new(file, schema.clone(), Some(props))?;
-
-    let mut rows_processed = 0;
-
-    // Prepare vectors to hold column data
-    let pb = create_progress_bar(total_rows as u64, "Writing parquet file chunks".to_string());
-
-    while rows_processed < total_rows {
-        let remaining_rows = total_rows - rows_processed;
-        let batch_size = chunk_size.min(remaining_rows);
-
-        let mut ids = Vec::with_capacity(batch_size);
-        let mut date32s = Vec::with_capacity(batch_size);
-        let mut max_raws = Vec::with_capacity(batch_size);
-        let mut max_tobs = Vec::with_capacity(batch_size);
-        let mut max_fls52s = Vec::with_capacity(batch_size);
-        let mut min_raws = Vec::with_capacity(batch_size);
-        let mut min_tobs = Vec::with_capacity(batch_size);
-        let mut min_fls52s = Vec::with_capacity(batch_size);
-        let mut avg_raws = Vec::with_capacity(batch_size);
-        let mut avg_tobs = Vec::with_capacity(batch_size);
-        let mut avg_fls52s = Vec::with_capacity(batch_size);
-        let mut lats = Vec::with_capacity(batch_size);
-        let mut lons = Vec::with_capacity(batch_size);
-
-        let mut rows_in_batch = 0;
-
-        for r in &readings[rows_processed / months_per_year..] {
-            let id = r.id.as_str();
-            let year = r.year;
-
-            for month in 1..=months_per_year {
-                ids.push(id);
-
-                // Convert year, month, and day to a NaiveDate
-                let date = NaiveDate::from_ymd_opt(year as i32, month as u32, 1);
-                match date {
-                    Some(valid_date) => {
-                        let date32 = valid_date.num_days_from_ce()
-                            - NaiveDate::from_ymd_opt(1970, 1, 1)
-                                .unwrap()
-                                .num_days_from_ce();
-                        date32s.push(Some(date32));
-                    }
-                    None => {
-                        date32s.push(None);
-                    }
-                }
+    ]))
+}
 
-                let idx = month - 1;
-                
-                // Add lat/lon for each row
-                lats.push(r.lat);
-                lons.push(r.lon);
-                
-                match r.properties.element {
-                    Element::Max => match r.properties.dataset {
-                        Dataset::Raw => {
-                            push_value_or_none(&r.values, idx, &mut max_raws);
-                            max_tobs.push(None);
-                            max_fls52s.push(None);
-                            min_raws.push(None);
-                            min_tobs.push(None);
-                            min_fls52s.push(None);
-                            avg_raws.push(None);
-                            avg_tobs.push(None);
-                            avg_fls52s.push(None);
-                        }
-                        Dataset::Tob => {
-                            push_value_or_none(&r.values, idx, &mut max_tobs);
-                            max_raws.push(None);
-                            max_fls52s.push(None);
-                            min_raws.push(None);
-                            min_tobs.push(None);
-                            min_fls52s.push(None);
-                            avg_raws.push(None);
-                            avg_tobs.push(None);
-                            avg_fls52s.push(None);
-                        }
-                        Dataset::Fls52 => {
-                            push_value_or_none(&r.values, idx, &mut max_fls52s);
-                            max_raws.push(None);
-                            max_tobs.push(None);
-                            min_raws.push(None);
-                            min_tobs.push(None);
-                            min_fls52s.push(None);
-                            avg_raws.push(None);
-                            avg_tobs.push(None);
-                            avg_fls52s.push(None);
-                        }
-                        _ => {
-                            max_raws.push(None);
-                            max_tobs.push(None);
-                            max_fls52s.push(None);
-                            min_raws.push(None);
-                            min_tobs.push(None);
-                            min_fls52s.push(None);
-                            avg_raws.push(None);
-                            avg_tobs.push(None);
-                            avg_fls52s.push(None);
-                        }
-                    },
-                    Element::Min => match r.properties.dataset {
-                        Dataset::Raw => {
-                            push_value_or_none(&r.values, idx, &mut min_raws);
-                            max_raws.push(None);
-                            max_tobs.push(None);
-                            max_fls52s.push(None);
-                            min_tobs.push(None);
-                            min_fls52s.push(None);
-                            avg_raws.push(None);
-                            avg_tobs.push(None);
-                            avg_fls52s.push(None);
-                        }
-                        Dataset::Tob => {
-                            push_value_or_none(&r.values, idx, &mut min_tobs);
-                            max_raws.push(None);
-                            max_tobs.push(None);
-                            max_fls52s.push(None);
-                            min_raws.push(None);
-                            min_fls52s.push(None);
-                            avg_raws.push(None);
-                            avg_tobs.push(None);
-                            avg_fls52s.push(None);
-                        }
-                        Dataset::Fls52 => {
-                            push_value_or_none(&r.values, idx, &mut min_fls52s);
-                            max_raws.push(None);
-                            max_tobs.push(None);
-                            max_fls52s.push(None);
-                            min_raws.push(None);
-                            min_tobs.push(None);
-                            avg_raws.push(None);
-                            avg_tobs.push(None);
-                            avg_fls52s.push(None);
-                        }
-                        _ => {
-                            min_raws.push(None);
-                            max_raws.push(None);
-                            max_tobs.push(None);
-                            max_fls52s.push(None);
-                            min_tobs.push(None);
-                            min_fls52s.push(None);
-                            avg_raws.push(None);
-                            avg_tobs.push(None);
-                            avg_fls52s.push(None);
-                        }
-                    },
-                    Element::Avg => match r.properties.dataset {
-                        Dataset::Raw => {
-                            push_value_or_none(&r.values, idx, &mut avg_raws);
-                            max_raws.push(None);
-                            max_tobs.push(None);
-                            max_fls52s.push(None);
-                            min_raws.push(None);
-                            min_tobs.push(None);
-                            min_fls52s.push(None);
-                            avg_tobs.push(None);
-                            avg_fls52s.push(None);
-                        }
-                        Dataset::Tob => {
-                            push_value_or_none(&r.values, idx, &mut avg_tobs);
-                            max_raws.push(None);
-                            max_tobs.push(None);
-                            max_fls52s.push(None);
-                            min_raws.push(None);
-                            min_tobs.push(None);
-                            min_fls52s.push(None);
-                            avg_raws.push(None);
-                            avg_fls52s.push(None);
-                        }
-                        Dataset::Fls52 => {
-                            push_value_or_none(&r.values, idx, &mut avg_fls52s);
-                            max_raws.push(None);
-                            max_tobs.push(None);
-                            max_fls52s.push(None);
-                            min_raws.push(None);
-                            min_tobs.push(None);
-                            min_fls52s.push(None);
-                            avg_raws.push(None);
-                            avg_tobs.push(None);
-                        }
-                        _ => {
-                            max_raws.push(None);
-                            max_tobs.push(None);
-                            max_fls52s.push(None);
-                            min_raws.push(None);
-                            min_tobs.push(None);
-                            min_fls52s.push(None);
-                            avg_raws.push(None);
-                            avg_tobs.push(None);
-                            avg_fls52s.push(None);
-                        }
-                    },
-                    _ => {
-                        max_raws.push(None);
-                        max_tobs.push(None);
-                        max_fls52s.push(None);
-                        min_raws.push(None);
-                        min_tobs.push(None);
-                        min_fls52s.push(None);
-                        avg_raws.push(None);
-                        avg_tobs.push(None);
-                        avg_fls52s.push(None);
-                    }
-                }
+/// Buffered column data for one batch of wide or pivoted monthly rows.
+struct MonthlyColumns {
+    ids: Vec<String>,
+    date32s: Vec<Option<i32>>,
+    values: Vec<Vec<Option<f32>>>,
+    flags: Vec<[Vec<Option<char>>; 3]>,
+    lats: Vec<Option<f32>>,
+    lons: Vec<Option<f32>>,
+}
 
-                rows_processed += 1;
-                rows_in_batch += 1;
-                pb.inc(1);
+impl MonthlyColumns {
+    fn with_capacity(chunk_size: usize, value_columns: usize) -> Self {
+        let flags = std::array::from_fn(|_| Vec::with_capacity(chunk_size));
 
-                if rows_in_batch == batch_size {
-                    break;
-                }
-            }
+        MonthlyColumns {
+            ids: Vec::with_capacity(chunk_size),
+            date32s: Vec::with_capacity(chunk_size),
+            values: vec![Vec::with_capacity(chunk_size); value_columns],
+            flags: vec![flags; value_columns],
+            lats: Vec::with_capacity(chunk_size),
+            lons: Vec::with_capacity(chunk_size),
+        }
+    }
 
-            if rows_in_batch == batch_size {
-                break;
-            }
+    /// Appends a row, taking the value and flags of each value column from `cell`, or nulls if it
+    /// returns None.
+    fn push_row(
+        &mut self,
+        id: &str,
+        date32: Option<i32>,
+        lat: Option<f32>,
+        lon: Option<f32>,
+        cell: impl Fn(usize) -> Option<(Option<f32>, MonthlyFlags)>,
+    ) {
+        self.ids.push(id.to_string());
+        self.date32s.push(date32);
+        for (index, (values, column_flags)) in self
+            .values
+            .iter_mut()
+            .zip(self.flags.iter_mut())
+            .enumerate()
+        {
+            let (value, flags) = cell(index).unwrap_or_default();
+            values.push(value);
+            column_flags[0].push(flags.dmflag);
+            column_flags[1].push(flags.qcflag);
+            column_flags[2].push(flags.dsflag);
         }
+        self.lats.push(lat);
+        self.lons.push(lon);
+    }
 
-        // Create Arrow arrays from vectors
-        let ids_array = StringArray::from(ids);
-        let date32s_array = Date32Array::from(date32s);
-        let max_raws_array = Float32Array::from(max_raws);
-        let max_tobs_array = Float32Array::from(max_tobs);
-        let max_fls52s_array = Float32Array::from(max_fls52s);
-        let min_raws_array = Float32Array::from(min_raws);
-        let min_tobs_array = Float32Array::from(min_tobs);
-        let min_fls52s_array = Float32Array::from(min_fls52s);
-        let avg_raws_array = Float32Array::from(avg_raws);
-        let avg_tobs_array = Float32Array::from(avg_tobs);
-        let avg_fls52s_array = Float32Array::from(avg_fls52s);
-        let lats_array = Float32Array::from(lats);
-        let lons_array = Float32Array::from(lons);
-
-        // Create a vector for the RecordBatch
-        let columns: Vec<(&str, ArrayRef)> = vec![
-            ("id", Arc::new(ids_array) as ArrayRef),
-            ("date", Arc::new(date32s_array) as ArrayRef),
-            ("max_raw", Arc::new(max_raws_array) as ArrayRef),
-            ("max_tob", Arc::new(max_tobs_array) as ArrayRef),
-            ("max_fls52", Arc::new(max_fls52s_array) as ArrayRef),
-            ("min_raw", Arc::new(min_raws_array) as ArrayRef),
-            ("min_tob", Arc::new(min_tobs_array) as ArrayRef),
-            ("min_fls52", Arc::new(min_fls52s_array) as ArrayRef),
-            ("avg_raw", Arc::new(avg_raws_array) as ArrayRef),
-            ("avg_tob", Arc::new(avg_tobs_array) as ArrayRef),
-            ("avg_fls52", Arc::new(avg_fls52s_array) as ArrayRef),
-            ("lat", Arc::new(lats_array) as ArrayRef),
-            ("lon", Arc::new(lons_array) as ArrayRef),
+    /// Builds a RecordBatch from the buffered rows and clears the buffers.
+    fn take_batch(&mut self, schema: &Arc<Schema>) -> Result<RecordBatch> {
+        let mut columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from(std::mem::take(&mut self.ids))),
+            Arc::new(Date32Array::from(std::mem::take(&mut self.date32s))),
         ];
+        for (values, flags) in self.values.iter_mut().zip(self.flags.iter_mut()) {
+            columns.push(Arc::new(Float32Array::from(std::mem::take(values))));
+            for flag in flags.iter_mut() {
+                let flag_strings = flag.drain(..).map(|f| f.map(String::from));
+                columns.push(Arc::new(StringArray::from_iter(flag_strings)));
+            }
+        }
+        columns.push(Arc::new(Float32Array::from(std::mem::take(&mut self.lats))));
+        columns.push(Arc::new(Float32Array::from(std::mem::take(&mut self.lons))));
 
-        // Ensure all columns have the same number of rows
-        let num_rows = columns.first().map(|(_, col)| col.len()).unwrap_or(0);
-        for (name, array) in &columns {
-            assert_eq!(
-                array.len(),
-                num_rows,
-                "Column '{}' has incorrect number of rows: expected {}, got {}",
-                name,
-                num_rows,
-                array.len()
-            );
+        Ok(RecordBatch::try_new(schema.clone(), columns)?)
+    }
+}
+
+/// Column builders for one batch of long monthly rows.
+struct LongBuilders {
+    id: StringBuilder,
+    date: Date32Builder,
+    element: StringBuilder,
+    dataset: StringBuilder,
+    value: Float32Builder,
+    unit: StringBuilder,
+    dmflag: StringBuilder,
+    qcflag: StringBuilder,
+    dsflag: StringBuilder,
+    lat: Float32Builder,
+    lon: Float32Builder,
+}
+
+impl LongBuilders {
+    fn with_capacity(chunk_size: usize) -> Self {
+        LongBuilders {
+            id: StringBuilder::with_capacity(chunk_size, chunk_size * 12),
+            date: Date32Builder::with_capacity(chunk_size),
+            element: StringBuilder::with_capacity(chunk_size, chunk_size * 4),
+            dataset: StringBuilder::with_capacity(chunk_size, chunk_size * 6),
+            value: Float32Builder::with_capacity(chunk_size),
+            unit: StringBuilder::with_capacity(chunk_size, chunk_size * 4),
+            dmflag: StringBuilder::with_capacity(chunk_size, chunk_size),
+            qcflag: StringBuilder::with_capacity(chunk_size, chunk_size),
+            dsflag: StringBuilder::with_capacity(chunk_size, chunk_size),
+            lat: Float32Builder::with_capacity(chunk_size),
+            lon: Float32Builder::with_capacity(chunk_size),
         }
+    }
+
+    fn append(
+        &mut self,
+        reading: &MonthlyReading,
+        date32: Option<i32>,
+        [element, dataset, unit]: [&str; 3],
+        value: f32,
+        flags: &MonthlyFlags,
+    ) {
+        self.id.append_value(&reading.id);
+        self.date.append_option(date32);
+        self.element.append_value(element);
+        self.dataset.append_value(dataset);
+        self.value.append_value(value);
+        self.unit.append_value(unit);
+        append_flag(&mut self.dmflag, flags.dmflag);
+        append_flag(&mut self.qcflag, flags.qcflag);
+        append_flag(&mut self.dsflag, flags.dsflag);
+        self.lat.append_option(reading.lat);
+        self.lon.append_option(reading.lon);
+    }
 
-        // Create RecordBatch
-        let batch = RecordBatch::try_from_iter(columns).expect("Failed to create record batch");
+    /// Builds a RecordBatch from the appended rows and resets the builders.
+    fn finish(&mut self, schema: &Arc<Schema>) -> Result<RecordBatch> {
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(self.id.finish()),
+                Arc::new(self.date.finish()),
+                Arc::new(self.element.finish()),
+                Arc::new(self.dataset.finish()),
+                Arc::new(self.value.finish()),
+                Arc::new(self.unit.finish()),
+                Arc::new(self.dmflag.finish()),
+                Arc::new(self.qcflag.finish()),
+                Arc::new(self.dsflag.finish()),
+                Arc::new(self.lat.finish()),
+                Arc::new(self.lon.finish()),
+            ],
+        )?;
 
-        writer.write(&batch)?;
+        Ok(batch)
     }
+}
 
-    pb.finish_with_message("Finished writing Parquet file");
+/// The values of a station in a year, by value column, waiting to be joined into pivoted rows.
+struct PivotedYear {
+    lat: Option<f32>,
+    lon: Option<f32>,
+    columns: Vec<Option<Box<[PivotedCell; MONTHS_PER_YEAR]>>>,
+}
 
-    writer.close()?;
+/// A compact monthly value and its flags, as the pivoted layout holds every value in memory.
+#[derive(Debug, Clone, Copy)]
+struct PivotedCell {
+    /// NaN when missing
+    value: f32,
+    /// the ASCII flag characters, 0 when blank
+    flags: [u8; 3],
+}
 
-    Ok(())
+impl PivotedCell {
+    fn new(value: Option<f32>, flags: &MonthlyFlags) -> Self {
+        let flag = |flag: Option<char>| flag.filter(char::is_ascii).map_or(0, |c| c as u8);
+        PivotedCell {
+            value: value.unwrap_or(f32::NAN),
+            flags: [flag(flags.dmflag), flag(flags.qcflag), flag(flags.dsflag)],
+        }
+    }
+
+    fn get(&self) -> (Option<f32>, MonthlyFlags) {
+        let flag = |flag: u8| (flag != 0).then_some(flag as char);
+        let value = (!self.value.is_nan()).then_some(self.value);
+        let flags = MonthlyFlags {
+            dmflag: flag(self.flags[0]),
+            qcflag: flag(self.flags[1]),
+            dsflag: flag(self.flags[2]),
+        };
+
+        (value, flags)
+    }
 }
 
-fn push_value_or_none(values: &[Option<f32>], index: usize, target_vec: &mut Vec<Option<f32>>) {
-    if let Some(value) = values.get(index) {
-        target_vec.push(*value);
-    } else {
-        target_vec.push(None);
+// -- Tests -------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use arrow::array::Array;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use tempfile::NamedTempFile;
+
+    use crate::reading::FileProperties;
+
+    use super::*;
+
+    fn save_monthly(readings: &[MonthlyReading], file_path: &Path, units: UnitSystem) -> Result<u64> {
+        save_monthly_as(readings, file_path, MonthlyLayout::Wide, units)
+    }
+
+    fn save_monthly_as(
+        readings: &[MonthlyReading],
+        file_path: &Path,
+        layout: MonthlyLayout,
+        units: UnitSystem,
+    ) -> Result<u64> {
+        let mut writer = MonthlyWriter::create(file_path, layout, units, &[])?;
+        for reading in readings {
+            writer.write(reading)?;
+        }
+        writer.close()
+    }
+
+    #[test]
+    fn should_write_value_to_matching_column() {
+        let readings = vec![
+            reading_fixture(Element::Max, Dataset::Raw),
+            reading_fixture(Element::Prcp, Dataset::Fls52),
+        ];
+        let temp_file = NamedTempFile::new().unwrap();
+        let temp_path = temp_file.path().to_path_buf();
+
+        save_monthly(&readings, &temp_path, UnitSystem::Metric).unwrap();
+
+        let file = File::open(&temp_path).unwrap();
+        let reader = ParquetRecordBatchReaderBuilder::try_new(file)
+            .unwrap()
+            .build()
+            .unwrap();
+        let batch = reader.into_iter().next().unwrap().unwrap();
+        let schema = batch.schema();
+
+        assert_eq!(batch.num_rows(), 24);
+        assert_eq!(schema.fields().len(), 52);
+        assert_eq!(schema.field(2).name(), "max_raw");
+        assert_eq!(schema.field(3).name(), "max_raw_dmflag");
+        assert_eq!(schema.field(5).name(), "max_raw_dsflag");
+        assert_eq!(schema.field(46).name(), "prcp_fls52");
+        assert_eq!(schema.field(51).name(), "lon");
+
+        let max_raw = batch
+            .column(2)
+            .as_any()
+            .downcast_ref::<Float32Array>()
+            .unwrap();
+        let prcp_fls52 = batch
+            .column(46)
+            .as_any()
+            .downcast_ref::<Float32Array>()
+            .unwrap();
+
+        // first reading fills max_raw only, second fills prcp_fls52 only
+        assert_eq!(max_raw.value(0), 1.0);
+        assert_eq!(max_raw.null_count(), 13);
+        assert_eq!(prcp_fls52.value(23), 12.0);
+        assert_eq!(prcp_fls52.null_count(), 13);
+    }
+
+    #[test]
+    fn should_write_flags_next_to_values() {
+        let mut reading = reading_fixture(Element::Avg, Dataset::Tob);
+        reading.flags[0] = MonthlyFlags {
+            dmflag: Some('E'),
+            qcflag: None,
+            dsflag: None,
+        };
+        reading.flags[1] = MonthlyFlags {
+            dmflag: Some('a'),
+            qcflag: Some('M'),
+            dsflag: Some('3'),
+        };
+        let temp_file = NamedTempFile::new().unwrap();
+        let temp_path = temp_file.path().to_path_buf();
+
+        save_monthly(&[reading], &temp_path, UnitSystem::Metric).unwrap();
+
+        let file = File::open(&temp_path).unwrap();
+        let reader = ParquetRecordBatchReaderBuilder::try_new(file)
+            .unwrap()
+            .build()
+            .unwrap();
+        let batch = reader.into_iter().next().unwrap().unwrap();
+        let flag_column = |name: &str| {
+            let index = batch.schema().index_of(name).unwrap();
+            batch
+                .column(index)
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .unwrap()
+                .clone()
+        };
+
+        let dmflags = flag_column("avg_tob_dmflag");
+        let qcflags = flag_column("avg_tob_qcflag");
+        let dsflags = flag_column("avg_tob_dsflag");
+
+        assert_eq!(dmflags.value(0), "E");
+        assert_eq!(dmflags.value(1), "a");
+        assert_eq!(qcflags.value(1), "M");
+        assert_eq!(dsflags.value(1), "3");
+        assert!(qcflags.is_null(0));
+        assert_eq!(dmflags.null_count(), 10);
+        assert_eq!(flag_column("avg_raw_dmflag").null_count(), 12);
+    }
+
+    #[test]
+    fn should_write_unit_metadata_and_convert_to_imperial() {
+        let readings = vec![
+            reading_fixture(Element::Max, Dataset::Raw),
+            reading_fixture(Element::Prcp, Dataset::Raw),
+        ];
+        let temp_file = NamedTempFile::new().unwrap();
+        let temp_path = temp_file.path().to_path_buf();
+
+        save_monthly(&readings, &temp_path, UnitSystem::Imperial).unwrap();
+
+        let file = File::open(&temp_path).unwrap();
+        let reader = ParquetRecordBatchReaderBuilder::try_new(file)
+            .unwrap()
+            .build()
+            .unwrap();
+        let batch = reader.into_iter().next().unwrap().unwrap();
+        let schema = batch.schema();
+        let unit = |name: &str| schema.field_with_name(name).unwrap().metadata()["unit"].clone();
+        let value = |name: &str, row: usize| {
+            let index = schema.index_of(name).unwrap();
+            batch
+                .column(index)
+                .as_any()
+                .downcast_ref::<Float32Array>()
+                .unwrap()
+                .value(row)
+        };
+
+        assert_eq!(unit("max_raw"), "degF");
+        assert_eq!(unit("prcp_fls52"), "in");
+        assert_eq!(value("max_raw", 0), 33.8);
+        assert_eq!(value("prcp_raw", 12), 1.0 / 25.4);
+    }
+
+    #[test]
+    fn should_write_long_layout_like_daily() {
+        let mut reading = reading_fixture(Element::Max, Dataset::Tob);
+        reading.flags[1].qcflag = Some('M');
+        let readings = vec![reading, reading_fixture(Element::Prcp, Dataset::Raw)];
+        let temp_file = NamedTempFile::new().unwrap();
+        let temp_path = temp_file.path().to_path_buf();
+
+        let rows = save_monthly_as(
+            &readings,
+            &temp_path,
+            MonthlyLayout::Long,
+            UnitSystem::Metric,
+        )
+        .unwrap();
+
+        let file = File::open(&temp_path).unwrap();
+        let reader = ParquetRecordBatchReaderBuilder::try_new(file)
+            .unwrap()
+            .build()
+            .unwrap();
+        let batch = reader.into_iter().next().unwrap().unwrap();
+        let schema = batch.schema();
+        let names: Vec<&str> = schema.fields().iter().map(|f| f.name().as_str()).collect();
+        let strings = |name: &str| {
+            let index = schema.index_of(name).unwrap();
+            batch
+                .column(index)
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .unwrap()
+                .clone()
+        };
+        let values = batch
+            .column(schema.index_of("value").unwrap())
+            .as_any()
+            .downcast_ref::<Float32Array>()
+            .unwrap();
+
+        // a row for each month with a value, the sixth month of each reading is missing
+        assert_eq!(rows, 22);
+        assert_eq!(batch.num_rows(), 22);
+        assert_eq!(
+            names,
+            [
+                "id", "date", "element", "dataset", "value", "unit", "dmflag", "qcflag", "dsflag",
+                "lat", "lon"
+            ]
+        );
+        assert_eq!(strings("element").value(0), "TMAX");
+        assert_eq!(strings("dataset").value(0), "TOB");
+        assert_eq!(strings("unit").value(0), "degC");
+        assert_eq!(strings("qcflag").value(1), "M");
+        assert_eq!(strings("qcflag").null_count(), 21);
+        assert_eq!(values.value(5), 7.0);
+        assert_eq!(strings("element").value(11), "PRCP");
+        assert_eq!(strings("dataset").value(11), "RAW");
+        assert_eq!(strings("unit").value(11), "mm");
+    }
+
+    #[test]
+    fn should_join_pivoted_station_months() {
+        let mut tob = reading_fixture(Element::Max, Dataset::Tob);
+        tob.values[0] = Some(2.5);
+        tob.flags[0].dmflag = Some('E');
+        let mut other_station = reading_fixture(Element::Max, Dataset::Raw);
+        other_station.id = "USH00011083".to_string();
+        let readings = vec![
+            reading_fixture(Element::Max, Dataset::Raw),
+            other_station,
+            tob,
+            reading_fixture(Element::Avg, Dataset::Fls52),
+        ];
+        let temp_file = NamedTempFile::new().unwrap();
+        let temp_path = temp_file.path().to_path_buf();
+
+        let rows = save_monthly_as(
+            &readings,
+            &temp_path,
+            MonthlyLayout::Pivoted,
+            UnitSystem::Metric,
+        )
+        .unwrap();
+
+        let file = File::open(&temp_path).unwrap();
+        let reader = ParquetRecordBatchReaderBuilder::try_new(file)
+            .unwrap()
+            .build()
+            .unwrap();
+        let batch = reader.into_iter().next().unwrap().unwrap();
+        let schema = batch.schema();
+        let column = |name: &str| batch.column(schema.index_of(name).unwrap()).clone();
+        let floats = |name: &str| {
+            column(name)
+                .as_any()
+                .downcast_ref::<Float32Array>()
+                .unwrap()
+                .clone()
+        };
+        let strings = |name: &str| {
+            column(name)
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .unwrap()
+                .clone()
+        };
+
+        // a row for each month of each station, sorted by station
+        assert_eq!(rows, 24);
+        assert_eq!(schema.fields().len(), 52);
+        assert_eq!(strings("id").value(0), "USH00011083");
+        assert_eq!(floats("max_tob").null_count(), 13);
+
+        // the readings of the same station and month share a row
+        assert_eq!(strings("id").value(12), "USH00011084");
+        assert_eq!(floats("max_raw").value(12), 1.0);
+        assert_eq!(floats("max_tob").value(12), 2.5);
+        assert_eq!(floats("avg_fls52").value(12), 1.0);
+        assert_eq!(strings("max_tob_dmflag").value(12), "E");
+        assert!(strings("max_raw_dmflag").is_null(12));
+        assert!(floats("max_raw").is_null(17));
+        assert!(floats("min_raw").is_null(12));
+    }
+
+    fn reading_fixture(element: Element, dataset: Dataset) -> MonthlyReading {
+        let mut values: Vec<Option<f32>> = (1..=12).map(|v| Some(v as f32)).collect();
+        values[5] = None;
+
+        MonthlyReading {
+            id: "USH00011084".to_string(),
+            year: 1895,
+            month: None,
+            properties: FileProperties { dataset, element },
+            values,
+            flags: vec![MonthlyFlags::default(); 12],
+            lat: Some(31.0581),
+            lon: Some(-87.0547),
+        }
     }
 }
@@ -0,0 +1,505 @@
+//! Save readings to a Hive-partitioned directory of parquet files, e.g.
+//! `dataset=raw/element=tmax/decade=1890/state=AL/part-0.parquet`.
+
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use arrow::datatypes::Schema;
+use clap::ValueEnum;
+use parquet::arrow::{arrow_reader::ParquetRecordBatchReaderBuilder, ArrowWriter};
+
+use super::{DailyWriter, MonthlyWriter};
+use crate::reading::{DailyReading, Dataset, Element, MonthlyReading};
+
+/// Rows buffered before they are written as a batch to a partition file, fewer than for a single
+/// file as many partition files are open at once.
+pub const PARTITION_CHUNK_SIZE: usize = 10000;
+
+/// Partition files open at once. When a reading belongs to another partition, the least recently
+/// written file is closed and the partition continues in a new part file if it gets more readings.
+/// The part files of each partition are merged into one when the output is closed.
+pub const MAX_OPEN_PARTITIONS: usize = 64;
+
+/// Hive's name for the partition of a missing value.
+const DEFAULT_PARTITION: &str = "__HIVE_DEFAULT_PARTITION__";
+
+/// File marking a directory as written by this tool, so a later run may replace it. Hive readers
+/// skip files starting with `_`.
+const MARKER_FILE: &str = "_ushcn";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+/// A column the output files can be partitioned by, as a directory level.
+pub enum PartitionKey {
+    /// `raw`, `tob` or `fls52` for monthly readings
+    Dataset,
+    /// Element code, e.g. `tmax`
+    Element,
+    /// First year of the decade, e.g. `1890`
+    Decade,
+    /// Two letter code of the station's state, e.g. `AL`
+    State,
+}
+
+/// Returns the indices of the columns of a partition file, leaving out the columns it is
+/// partitioned by as their value is in its directory, or None to write every column.
+pub fn file_columns(schema: &Schema, partition_by: &[PartitionKey]) -> Option<Vec<usize>> {
+    let fields = schema.fields();
+    let columns: Vec<usize> = (0..fields.len())
+        .filter(|&index| {
+            !partition_by
+                .iter()
+                .any(|key| key.name() == fields[index].name())
+        })
+        .collect();
+
+    (columns.len() < fields.len()).then_some(columns)
+}
+
+/// Writes the record batches of a parquet file to a writer with the same schema. Returns the number
+/// of rows written.
+pub fn append_parquet_file(writer: &mut ArrowWriter<File>, file_path: &Path) -> Result<u64> {
+    let file = File::open(file_path)
+        .with_context(|| format!("Failed to open {}", file_path.display()))?;
+    let mut rows = 0;
+    for batch in ParquetRecordBatchReaderBuilder::try_new(file)?.build()? {
+        let batch = batch?;
+        writer.write(&batch)?;
+        rows += batch.num_rows() as u64;
+    }
+
+    Ok(rows)
+}
+
+/// A parquet file that readings are streamed into.
+pub trait ReadingWriter {
+    type Reading;
+
+    fn write(&mut self, reading: &Self::Reading) -> Result<()>;
+
+    /// Appends the rows of a parquet file written by a writer of the same kind.
+    fn append_file(&mut self, file_path: &Path) -> Result<()>;
+
+    /// Writes the remaining rows and closes the file. Returns the number of rows written.
+    fn close(self) -> Result<u64>;
+}
+
+impl ReadingWriter for DailyWriter {
+    type Reading = DailyReading;
+
+    fn write(&mut self, reading: &DailyReading) -> Result<()> {
+        DailyWriter::write(self, reading)
+    }
+
+    fn append_file(&mut self, file_path: &Path) -> Result<()> {
+        DailyWriter::append_file(self, file_path)
+    }
+
+    fn close(self) -> Result<u64> {
+        DailyWriter::close(self)
+    }
+}
+
+impl ReadingWriter for MonthlyWriter {
+    type Reading = MonthlyReading;
+
+    fn write(&mut self, reading: &MonthlyReading) -> Result<()> {
+        MonthlyWriter::write(self, reading)
+    }
+
+    fn append_file(&mut self, file_path: &Path) -> Result<()> {
+        MonthlyWriter::append_file(self, file_path)
+    }
+
+    fn close(self) -> Result<u64> {
+        MonthlyWriter::close(self)
+    }
+}
+
+/// The values a reading is partitioned by.
+#[derive(Debug, Clone, Copy)]
+pub struct Partition<'a> {
+    pub dataset: &'a Dataset,
+    pub element: &'a Element,
+    pub year: u16,
+    pub state: Option<&'a str>,
+}
+
+impl Partition<'_> {
+    /// Returns the partition directory, relative to the output directory, with a level per key.
+    pub fn dir(&self, keys: &[PartitionKey]) -> PathBuf {
+        keys.iter()
+            .map(|key| {
+                let value = match key {
+                    PartitionKey::Dataset => self.dataset.as_str().to_lowercase(),
+                    PartitionKey::Element => self.element.code().to_lowercase(),
+                    PartitionKey::Decade => (self.year / 10 * 10).to_string(),
+                    PartitionKey::State => self.state.unwrap_or(DEFAULT_PARTITION).to_string(),
+                };
+                format!("{}={}", key.name(), value)
+            })
+            .collect()
+    }
+}
+
+impl PartitionKey {
+    /// Returns the name of the directory level.
+    pub fn name(&self) -> &'static str {
+        match self {
+            PartitionKey::Dataset => "dataset",
+            PartitionKey::Element => "element",
+            PartitionKey::Decade => "decade",
+            PartitionKey::State => "state",
+        }
+    }
+}
+
+/// Opens a parquet file at a path.
+type CreateWriter<W> = Box<dyn Fn(&Path) -> Result<W>>;
+
+/// Writes readings to a parquet file per partition under a directory.
+pub struct PartitionedWriter<W> {
+    root: PathBuf,
+    keys: Vec<PartitionKey>,
+    create: CreateWriter<W>,
+    max_open: usize,
+    /// open files by partition directory, with when each was last written
+    open: HashMap<PathBuf, (W, u64)>,
+    /// part files created for each partition
+    parts: HashMap<PathBuf, usize>,
+    writes: u64,
+    rows: u64,
+}
+
+impl<W: ReadingWriter> PartitionedWriter<W> {
+    /// Creates the directory, replacing the output of an earlier run like a single file would be,
+    /// and opens partition files with `create` as readings arrive. Fails rather than replace a
+    /// directory this tool didn't write, unless it is empty.
+    pub fn create(
+        root: &Path,
+        keys: &[PartitionKey],
+        max_open: usize,
+        create: impl Fn(&Path) -> Result<W> + 'static,
+    ) -> Result<Self> {
+        if root.join(MARKER_FILE).is_file() {
+            fs::remove_dir_all(root)
+                .with_context(|| format!("Failed to replace {}", root.display()))?;
+        } else if root.exists() && !is_empty_dir(root) {
+            bail!(
+                "{} exists and wasn't written by ushcn, refusing to replace it",
+                root.display()
+            );
+        }
+        fs::create_dir_all(root)?;
+        File::create(root.join(MARKER_FILE))?;
+
+        Ok(PartitionedWriter {
+            root: root.to_path_buf(),
+            keys: keys.to_vec(),
+            create: Box::new(create),
+            max_open: max_open.max(1),
+            open: HashMap::new(),
+            parts: HashMap::new(),
+            writes: 0,
+            rows: 0,
+        })
+    }
+
+    /// Writes the reading to the file of its partition.
+    pub fn write(&mut self, reading: &W::Reading, partition: &Partition) -> Result<()> {
+        let dir = partition.dir(&self.keys);
+        self.writes += 1;
+
+        if let Some((writer, last_write)) = self.open.get_mut(&dir) {
+            *last_write = self.writes;
+            return writer.write(reading);
+        }
+
+        if self.open.len() >= self.max_open {
+            self.close_least_recent()?;
+        }
+
+        let part = self.parts.entry(dir.clone()).or_default();
+        let part_dir = self.root.join(&dir);
+        fs::create_dir_all(&part_dir)?;
+        let mut writer = (self.create)(&part_dir.join(part_file_name(*part)))?;
+        *part += 1;
+
+        writer.write(reading)?;
+        self.open.insert(dir, (writer, self.writes));
+
+        Ok(())
+    }
+
+    /// Closes every partition file, then merges the part files of each partition whose file was
+    /// closed early, so that every partition is a single file. Returns the number of rows written.
+    pub fn close(mut self) -> Result<u64> {
+        for (_, (writer, _)) in self.open.drain() {
+            self.rows += writer.close()?;
+        }
+
+        for (dir, &parts) in &self.parts {
+            if parts > 1 {
+                self.merge_parts(&self.root.join(dir), parts)?;
+            }
+        }
+
+        Ok(self.rows)
+    }
+
+    // Copies the part files of a partition into a new file that replaces them as `part-0.parquet`.
+    // The rows were counted as the part files were closed.
+    fn merge_parts(&self, part_dir: &Path, parts: usize) -> Result<()> {
+        let part_paths: Vec<PathBuf> = (0..parts)
+            .map(|part| part_dir.join(part_file_name(part)))
+            .collect();
+        let merged_path = part_dir.join("merged.parquet.tmp");
+
+        let mut merged = (self.create)(&merged_path)?;
+        for part_path in &part_paths {
+            merged.append_file(part_path)?;
+        }
+        merged.close()?;
+
+        for part_path in &part_paths {
+            fs::remove_file(part_path)?;
+        }
+        fs::rename(&merged_path, part_dir.join(part_file_name(0)))
+            .with_context(|| format!("Failed to merge the part files in {}", part_dir.display()))?;
+
+        Ok(())
+    }
+
+    fn close_least_recent(&mut self) -> Result<()> {
+        let least_recent = self
+            .open
+            .iter()
+            .min_by_key(|(_, (_, last_write))| *last_write)
+            .map(|(dir, _)| dir.clone());
+        if let Some((writer, _)) = least_recent.and_then(|dir| self.open.remove(&dir)) {
+            self.rows += writer.close()?;
+        }
+
+        Ok(())
+    }
+}
+
+fn part_file_name(part: usize) -> String {
+    format!("part-{}.parquet", part)
+}
+
+fn is_empty_dir(path: &Path) -> bool {
+    fs::read_dir(path).is_ok_and(|mut entries| entries.next().is_none())
+}
+
+/// Writes readings to a single parquet file, or a partitioned directory of them.
+pub enum OutputWriter<W> {
+    File(W),
+    Partitioned(PartitionedWriter<W>),
+}
+
+impl<W: ReadingWriter> OutputWriter<W> {
+    /// Writes the reading, to the file of its partition if the output is partitioned.
+    pub fn write(&mut self, reading: &W::Reading, partition: &Partition) -> Result<()> {
+        match self {
+            OutputWriter::File(writer) => writer.write(reading),
+            OutputWriter::Partitioned(writer) => writer.write(reading, partition),
+        }
+    }
+
+    /// Closes the output. Returns the number of rows written.
+    pub fn close(self) -> Result<u64> {
+        match self {
+            OutputWriter::File(writer) => writer.close(),
+            OutputWriter::Partitioned(writer) => writer.close(),
+        }
+    }
+}
+
+// -- Tests -------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{
+        parquet::MonthlyLayout,
+        reading::{FileProperties, MonthlyFlags, UnitSystem},
+    };
+
+    #[test]
+    fn should_make_hive_partition_dir() {
+        let partition = Partition {
+            dataset: &Dataset::Fls52,
+            element: &Element::Max,
+            year: 1897,
+            state: Some("AL"),
+        };
+        let keys = [
+            PartitionKey::Dataset,
+            PartitionKey::Element,
+            PartitionKey::Decade,
+            PartitionKey::State,
+        ];
+
+        assert_eq!(
+            partition.dir(&keys),
+            PathBuf::from("dataset=fls52/element=tmax/decade=1890/state=AL")
+        );
+        assert_eq!(
+            Partition {
+                state: None,
+                ..partition
+            }
+            .dir(&[PartitionKey::State]),
+            PathBuf::from("state=__HIVE_DEFAULT_PARTITION__")
+        );
+    }
+
+    #[test]
+    fn should_write_a_part_file_per_partition() {
+        let tmp_dir = TempDir::new().unwrap();
+        let root = tmp_dir.path().join("ushcn-monthly");
+        fs::create_dir_all(root.join("stale")).unwrap();
+        File::create(root.join(MARKER_FILE)).unwrap();
+
+        // one open file at a time, so returning to a partition starts a new part file that is
+        // merged into the first on close
+        let mut writer =
+            PartitionedWriter::create(&root, &[PartitionKey::Decade], 1, |path: &Path| {
+                MonthlyWriter::create(path, MonthlyLayout::Wide, UnitSystem::Metric, &[])
+            })
+            .unwrap();
+        for year in [1895, 1901, 1896] {
+            let reading = reading_fixture(year);
+            let partition = Partition {
+                dataset: &reading.properties.dataset,
+                element: &reading.properties.element,
+                year,
+                state: Some("AL"),
+            };
+            writer.write(&reading, &partition).unwrap();
+        }
+        let rows = writer.close().unwrap();
+
+        assert_eq!(rows, 36);
+        assert!(!root.join("stale").exists());
+        assert_eq!(part_files(&root.join("decade=1890")), vec!["part-0.parquet"]);
+        assert_eq!(part_files(&root.join("decade=1900")), vec!["part-0.parquet"]);
+        assert_eq!(count_rows(&root.join("decade=1890/part-0.parquet")), 24);
+    }
+
+    #[test]
+    fn should_write_one_file_per_partition_beyond_open_limit() {
+        let tmp_dir = TempDir::new().unwrap();
+        let root = tmp_dir.path().join("ushcn-monthly");
+        let decades = MAX_OPEN_PARTITIONS as u16 + 6;
+
+        let mut writer = PartitionedWriter::create(
+            &root,
+            &[PartitionKey::Decade],
+            MAX_OPEN_PARTITIONS,
+            |path: &Path| MonthlyWriter::create(path, MonthlyLayout::Long, UnitSystem::Metric, &[]),
+        )
+        .unwrap();
+        // station by station, each station cycling through every decade
+        for _station in 0..3 {
+            for decade in 0..decades {
+                let reading = reading_fixture(1000 + decade * 10);
+                let partition = Partition {
+                    dataset: &reading.properties.dataset,
+                    element: &reading.properties.element,
+                    year: reading.year,
+                    state: Some("AL"),
+                };
+                writer.write(&reading, &partition).unwrap();
+            }
+        }
+        let rows = writer.close().unwrap();
+
+        assert_eq!(rows, 3 * 12 * decades as u64);
+        let dirs: Vec<_> = fs::read_dir(&root)
+            .unwrap()
+            .map(|dir| dir.unwrap().path())
+            .filter(|path| path.is_dir())
+            .collect();
+        assert_eq!(dirs.len(), decades as usize);
+        for dir in dirs {
+            assert_eq!(part_files(&dir), vec!["part-0.parquet"]);
+            assert_eq!(count_rows(&dir.join("part-0.parquet")), 3 * 12);
+        }
+    }
+
+    #[test]
+    fn should_leave_partition_columns_out_of_files() {
+        let tmp_dir = TempDir::new().unwrap();
+        let file_path = tmp_dir.path().join("part-0.parquet");
+        let keys = [PartitionKey::Element, PartitionKey::Decade];
+
+        let mut writer =
+            MonthlyWriter::create(&file_path, MonthlyLayout::Long, UnitSystem::Metric, &keys)
+                .unwrap();
+        writer.write(&reading_fixture(1895)).unwrap();
+        writer.close().unwrap();
+
+        let file = fs::File::open(&file_path).unwrap();
+        let reader = ParquetRecordBatchReaderBuilder::try_new(file).unwrap();
+        let schema = reader.schema();
+        assert!(schema.field_with_name("element").is_err());
+        assert!(schema.field_with_name("dataset").is_ok());
+        assert!(schema.field_with_name("value").is_ok());
+    }
+
+    #[test]
+    fn should_not_replace_foreign_directory() {
+        let tmp_dir = TempDir::new().unwrap();
+        let root = tmp_dir.path().join("ushcn-monthly");
+        fs::create_dir_all(root.join("notes")).unwrap();
+        let create = |path: &Path| {
+            MonthlyWriter::create(path, MonthlyLayout::Long, UnitSystem::Metric, &[])
+        };
+
+        assert!(PartitionedWriter::create(&root, &[PartitionKey::Decade], 1, create).is_err());
+        assert!(root.join("notes").exists());
+
+        // an empty directory is used as is
+        fs::remove_dir(root.join("notes")).unwrap();
+        assert!(PartitionedWriter::create(&root, &[PartitionKey::Decade], 1, create).is_ok());
+        assert!(root.join(MARKER_FILE).is_file());
+    }
+
+    fn part_files(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    fn count_rows(file_path: &Path) -> usize {
+        let file = fs::File::open(file_path).unwrap();
+        let reader = ParquetRecordBatchReaderBuilder::try_new(file).unwrap().build().unwrap();
+        reader.map(|batch| batch.unwrap().num_rows()).sum()
+    }
+
+    fn reading_fixture(year: u16) -> MonthlyReading {
+        MonthlyReading {
+            id: "USH00011084".to_string(),
+            year,
+            month: None,
+            properties: FileProperties {
+                dataset: Dataset::Raw,
+                element: Element::Max,
+            },
+            values: vec![Some(1.0); 12],
+            flags: vec![MonthlyFlags::default(); 12],
+            lat: Some(31.0581),
+            lon: Some(-87.0547),
+        }
+    }
+}
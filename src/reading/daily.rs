@@ -2,7 +2,7 @@
 
 use anyhow::Result;
 
-use super::{Element, FileProperties, Reading};
+use super::{check_line, Element, FileProperties, Reading};
 
 #[derive(Debug, Clone)]
 /// Represents a daily reading.
@@ -21,18 +21,50 @@ pub struct DailyReading {
     pub properties: FileProperties,
     /// reading values
     pub values: Vec<Option<f32>>,
+    /// measurement, quality and source flags for each value
+    pub flags: Vec<DailyFlags>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+/// The flags attached to a daily value. See "GHCN Daily Readme.txt Section III".
+pub struct DailyFlags {
+    /// measurement flag, e.g. `T` for a trace of precipitation
+    pub mflag: Option<char>,
+    /// quality flag, set when the value failed a NOAA quality assurance check
+    pub qflag: Option<char>,
+    /// source flag, e.g. `0` for U.S. Cooperative Summary of the Day
+    pub sflag: Option<char>,
+}
+
+impl DailyFlags {
+    /// Returns true if the value failed a NOAA quality assurance check.
+    pub fn failed_qc(&self) -> bool {
+        self.qflag.is_some()
+    }
+}
+
+impl DailyReading {
+    /// Returns true if the reading has a value to write, not counting values that failed a NOAA
+    /// quality assurance check if those are dropped.
+    pub fn has_values(&self, drop_qc_failed: bool) -> bool {
+        let no_flags = DailyFlags::default();
+        self.values.iter().enumerate().any(|(day_index, value)| {
+            let flags = self.flags.get(day_index).unwrap_or(&no_flags);
+            value.is_some() && !(drop_qc_failed && flags.failed_qc())
+        })
+    }
 }
 
 impl Reading for DailyReading {
     fn from_line(line: &str, _file_name: &str) -> Result<Self> {
+        check_line(line, 21)?;
         let id = line[0..11].to_string();
         let lat = None; // FIXME
         let lon = None; // FIXME
-        let year = line[11..15].parse()?;
-        let month = Some(line[15..17].parse()?);
+        let (year, month) = Self::line_date(line)?;
         let element = line[17..21].to_string();
         let properties = FileProperties::from_element(&element)?;
-        let values = parse_daily_values(line);
+        let (values, flags) = parse_daily_values(line, properties.element.daily_divisor());
 
         Ok(DailyReading {
             id,
@@ -42,46 +74,66 @@ impl Reading for DailyReading {
             month,
             properties,
             values,
+            flags,
         })
     }
 
+    fn line_date(line: &str) -> Result<(u16, Option<u16>)> {
+        check_line(line, 21)?;
+        Ok((line[11..15].parse()?, Some(line[15..17].parse()?)))
+    }
+
     fn is_valid(&self) -> bool {
-        [Element::Prcp, Element::Tmax, Element::Tmin].contains(&self.properties.element)
+        self.properties.element != Element::Unknown
+    }
+
+    fn element(&self) -> &Element {
+        &self.properties.element
     }
 }
 
-/// Parses the daily values from a line.
-fn parse_daily_values(line: &str) -> Vec<Option<f32>> {
+/// Parses the daily values and their flags from a line, dividing values by the element's scale.
+fn parse_daily_values(line: &str, divisor: f32) -> (Vec<Option<f32>>, Vec<DailyFlags>) {
     let start_pos = 21;
     let chunk_length = 8;
     let num_chunks = 31;
 
-    let values: Vec<Option<f32>> = (0..num_chunks)
+    (0..num_chunks)
         .map(|i| {
             let start_index = start_pos + i * chunk_length;
             let end_index = start_pos + (i + 1) * chunk_length;
             
             // Check bounds to prevent panic
             if end_index > line.len() {
-                return None;
+                return (None, DailyFlags::default());
             }
             
             let chunk = &line[start_index..end_index];
             
             // Additional check for chunk length
             if chunk.len() < 5 {
-                return None;
+                return (None, DailyFlags::default());
             }
             
             let first_five = &chunk[..5].trim();
-            match first_five.parse::<i32>() {
-                Ok(v) if v != -9999 => Some((v as f32) / 10.0),
+            let value = match first_five.parse::<i32>() {
+                Ok(v) if v != -9999 => Some((v as f32) / divisor),
                 _ => None,
-            }
+            };
+            let flags = DailyFlags {
+                mflag: parse_flag(chunk, 5),
+                qflag: parse_flag(chunk, 6),
+                sflag: parse_flag(chunk, 7),
+            };
+
+            (value, flags)
         })
-        .collect();
+        .unzip()
+}
 
-    values
+/// Parses a single flag character, treating blank as no flag.
+fn parse_flag(chunk: &str, index: usize) -> Option<char> {
+    chunk[index..].chars().next().filter(|c| !c.is_whitespace())
 }
 
 // -- Tests ----------------------------------------------------------------------------
@@ -100,9 +152,61 @@ mod tests {
         assert_eq!(reading.id, "USC00011084");
         assert_eq!(reading.year, 1926);
         assert_eq!(reading.month, Some(1));
-        assert_eq!(reading.properties.element, Element::Unknown);
+        assert_eq!(reading.properties.element, Element::Tobs);
         assert_eq!(reading.values.len(), 31);
         assert_eq!(reading.values[0], None);
         assert_eq!(reading.values[30], Some(18.9));
     }
+
+    #[test]
+    fn should_parse_flags() {
+        let line = "USC00011084192602PRCP    0  6  381  6    0T 6   25 X6-9999   -9999   -9999   -9999   -9999   -9999   -9999   -9999   -9999   -9999   -9999   -9999   -9999   -9999   -9999   -9999   -9999   -9999   -9999   -9999   -9999   -9999   -9999   -9999   -9999   -9999   -9999   ";
+        let reading = DailyReading::from_line(line, "").unwrap();
+
+        assert_eq!(reading.flags.len(), 31);
+        assert_eq!(reading.flags[0], DailyFlags { mflag: None, qflag: None, sflag: Some('6') });
+        assert_eq!(reading.flags[2].mflag, Some('T'));
+        assert_eq!(reading.flags[3].qflag, Some('X'));
+        assert!(reading.flags[3].failed_qc());
+        assert!(!reading.flags[2].failed_qc());
+        assert_eq!(reading.flags[4], DailyFlags::default());
+    }
+
+    #[test]
+    fn should_not_count_dropped_values() {
+        let line = "USC00011084192602PRCP   25 X6-9999   ";
+        let reading = DailyReading::from_line(line, "").unwrap();
+
+        assert!(reading.has_values(false));
+        assert!(!reading.has_values(true));
+    }
+
+    #[test]
+    fn should_reject_truncated_or_non_ascii_line() {
+        let error = DailyReading::from_line("USC000110841926", "").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Line is too short: expected at least 21 characters, got 15"
+        );
+
+        let line = "USC00011084192602PRCP    0  6  3é1  6";
+        let error = DailyReading::from_line(line, "").unwrap_err();
+        assert_eq!(error.to_string(), "Line contains non-ASCII characters");
+    }
+
+    #[test]
+    fn should_scale_values_by_element() {
+        let line = "USC00011084192602SNOW   51  6   25  6-9999   ";
+        let reading = DailyReading::from_line(line, "").unwrap();
+
+        assert_eq!(reading.properties.element, Element::Snow);
+        assert_eq!(reading.values[0], Some(51.0));
+        assert_eq!(reading.values[1], Some(25.0));
+
+        let line = "USC00011084192602WT03    1  6-9999   ";
+        let reading = DailyReading::from_line(line, "").unwrap();
+
+        assert_eq!(reading.properties.element, Element::WeatherType(3));
+        assert_eq!(reading.values[0], Some(1.0));
+    }
 }
@@ -0,0 +1,522 @@
+//! Measurement elements and element selection.
+//!
+//! See "GHCN Daily Readme.txt Section III" for the daily element codes and their units.
+
+use std::{fmt, str::FromStr};
+
+use super::Unit;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+/// Represents the type of measurement. See the [NOAA documentation](https://www1.ncdc.noaa.gov/pub/data/cdo/documentation/gsom-gsoy_documentation.pdf)
+/// for more information.
+pub enum Element {
+    // USHCN monthly elements
+    Max,
+    Min,
+    Avg,
+
+    // GHCN-Daily core elements (PRCP is shared with USHCN monthly)
+    Prcp,
+    Snow,
+    Snwd,
+    Tmax,
+    Tmin,
+
+    // GHCN-Daily other elements
+    Acmc,
+    Acmh,
+    Acsc,
+    Acsh,
+    Adpt,
+    Aslp,
+    Astp,
+    Awbt,
+    Awdr,
+    Awnd,
+    Daev,
+    Dapr,
+    Dasf,
+    Datn,
+    Datx,
+    Dawm,
+    Dwpr,
+    Evap,
+    Fmtm,
+    Frgb,
+    Frgt,
+    Frth,
+    Gaht,
+    Mdev,
+    Mdpr,
+    Mdsf,
+    Mdtn,
+    Mdtx,
+    Mdwm,
+    Mnpn,
+    Mxpn,
+    Pgtm,
+    Psun,
+    Rhav,
+    Rhmn,
+    Rhmx,
+    /// `SN*#`, minimum soil temperature for a ground cover (`*`) and depth (`#`) code
+    SoilMin(u8, u8),
+    /// `SX*#`, maximum soil temperature for a ground cover (`*`) and depth (`#`) code
+    SoilMax(u8, u8),
+    Taxn,
+    Tavg,
+    Thic,
+    Tobs,
+    Tsun,
+    Wdf1,
+    Wdf2,
+    Wdf5,
+    Wdfg,
+    Wdfi,
+    Wdfm,
+    Wdmv,
+    Wesd,
+    Wesf,
+    Wsf1,
+    Wsf2,
+    Wsf5,
+    Wsfg,
+    Wsfi,
+    Wsfm,
+    /// `WT**`, weather type, e.g. `WT03` for thunder
+    WeatherType(u8),
+    /// `WV**`, weather in the vicinity, e.g. `WV20` for rain or snow shower
+    WeatherInVicinity(u8),
+
+    #[default]
+    Unknown,
+}
+
+/// The GHCN-Daily elements with a fixed code, i.e. all but the soil and weather type families.
+const FIXED_ELEMENTS: [Element; 61] = [
+    Element::Prcp,
+    Element::Snow,
+    Element::Snwd,
+    Element::Tmax,
+    Element::Tmin,
+    Element::Acmc,
+    Element::Acmh,
+    Element::Acsc,
+    Element::Acsh,
+    Element::Adpt,
+    Element::Aslp,
+    Element::Astp,
+    Element::Awbt,
+    Element::Awdr,
+    Element::Awnd,
+    Element::Daev,
+    Element::Dapr,
+    Element::Dasf,
+    Element::Datn,
+    Element::Datx,
+    Element::Dawm,
+    Element::Dwpr,
+    Element::Evap,
+    Element::Fmtm,
+    Element::Frgb,
+    Element::Frgt,
+    Element::Frth,
+    Element::Gaht,
+    Element::Mdev,
+    Element::Mdpr,
+    Element::Mdsf,
+    Element::Mdtn,
+    Element::Mdtx,
+    Element::Mdwm,
+    Element::Mnpn,
+    Element::Mxpn,
+    Element::Pgtm,
+    Element::Psun,
+    Element::Rhav,
+    Element::Rhmn,
+    Element::Rhmx,
+    Element::Taxn,
+    Element::Tavg,
+    Element::Thic,
+    Element::Tobs,
+    Element::Tsun,
+    Element::Wdf1,
+    Element::Wdf2,
+    Element::Wdf5,
+    Element::Wdfg,
+    Element::Wdfi,
+    Element::Wdfm,
+    Element::Wdmv,
+    Element::Wesd,
+    Element::Wesf,
+    Element::Wsf1,
+    Element::Wsf2,
+    Element::Wsf5,
+    Element::Wsfg,
+    Element::Wsfi,
+    Element::Wsfm,
+];
+
+impl Element {
+    /// Parses a GHCN-Daily element code, e.g. `TMAX` or `WT03`. Case insensitive.
+    pub fn from_str(s: &str) -> Self {
+        let code = s.trim().to_uppercase();
+
+        if let Some(element) = FIXED_ELEMENTS.iter().find(|element| element.code() == code) {
+            return element.clone();
+        }
+
+        let digits = |from: usize| -> Option<Vec<u8>> {
+            let digits = code.get(from..)?;
+            digits
+                .chars()
+                .map(|c| c.to_digit(10).map(|d| d as u8))
+                .collect()
+        };
+
+        match (code.get(..2), code.len(), digits(2)) {
+            (Some("SN"), 4, Some(d)) => Element::SoilMin(d[0], d[1]),
+            (Some("SX"), 4, Some(d)) => Element::SoilMax(d[0], d[1]),
+            (Some("WT"), 4, Some(d)) => Element::WeatherType(d[0] * 10 + d[1]),
+            (Some("WV"), 4, Some(d)) => Element::WeatherInVicinity(d[0] * 10 + d[1]),
+            _ => Element::Unknown,
+        }
+    }
+
+    /// Parses the element suffix of a USHCN monthly file name, e.g. `tmax` in `USH00297610.tob.tmax`.
+    pub fn from_monthly_str(s: &str) -> Self {
+        match s {
+            "tmax" => Element::Max,
+            "tmin" => Element::Min,
+            "tavg" => Element::Avg,
+            "prcp" => Element::Prcp,
+            _ => Element::Unknown,
+        }
+    }
+
+    /// Returns the four character element code, e.g. `TMAX`.
+    ///
+    /// The USHCN monthly elements use the codes of their daily counterparts.
+    pub fn code(&self) -> String {
+        let code = match self {
+            Element::Max | Element::Tmax => "TMAX",
+            Element::Min | Element::Tmin => "TMIN",
+            Element::Avg | Element::Tavg => "TAVG",
+            Element::Prcp => "PRCP",
+            Element::Snow => "SNOW",
+            Element::Snwd => "SNWD",
+            Element::Acmc => "ACMC",
+            Element::Acmh => "ACMH",
+            Element::Acsc => "ACSC",
+            Element::Acsh => "ACSH",
+            Element::Adpt => "ADPT",
+            Element::Aslp => "ASLP",
+            Element::Astp => "ASTP",
+            Element::Awbt => "AWBT",
+            Element::Awdr => "AWDR",
+            Element::Awnd => "AWND",
+            Element::Daev => "DAEV",
+            Element::Dapr => "DAPR",
+            Element::Dasf => "DASF",
+            Element::Datn => "DATN",
+            Element::Datx => "DATX",
+            Element::Dawm => "DAWM",
+            Element::Dwpr => "DWPR",
+            Element::Evap => "EVAP",
+            Element::Fmtm => "FMTM",
+            Element::Frgb => "FRGB",
+            Element::Frgt => "FRGT",
+            Element::Frth => "FRTH",
+            Element::Gaht => "GAHT",
+            Element::Mdev => "MDEV",
+            Element::Mdpr => "MDPR",
+            Element::Mdsf => "MDSF",
+            Element::Mdtn => "MDTN",
+            Element::Mdtx => "MDTX",
+            Element::Mdwm => "MDWM",
+            Element::Mnpn => "MNPN",
+            Element::Mxpn => "MXPN",
+            Element::Pgtm => "PGTM",
+            Element::Psun => "PSUN",
+            Element::Rhav => "RHAV",
+            Element::Rhmn => "RHMN",
+            Element::Rhmx => "RHMX",
+            Element::Taxn => "TAXN",
+            Element::Thic => "THIC",
+            Element::Tobs => "TOBS",
+            Element::Tsun => "TSUN",
+            Element::Wdf1 => "WDF1",
+            Element::Wdf2 => "WDF2",
+            Element::Wdf5 => "WDF5",
+            Element::Wdfg => "WDFG",
+            Element::Wdfi => "WDFI",
+            Element::Wdfm => "WDFM",
+            Element::Wdmv => "WDMV",
+            Element::Wesd => "WESD",
+            Element::Wesf => "WESF",
+            Element::Wsf1 => "WSF1",
+            Element::Wsf2 => "WSF2",
+            Element::Wsf5 => "WSF5",
+            Element::Wsfg => "WSFG",
+            Element::Wsfi => "WSFI",
+            Element::Wsfm => "WSFM",
+            Element::SoilMin(cover, depth) => return format!("SN{}{}", cover, depth),
+            Element::SoilMax(cover, depth) => return format!("SX{}{}", cover, depth),
+            Element::WeatherType(code) => return format!("WT{:02}", code),
+            Element::WeatherInVicinity(code) => return format!("WV{:02}", code),
+            Element::Unknown => "UNKNOWN",
+        };
+
+        code.to_string()
+    }
+
+    /// Returns the divisor converting GHCN-Daily integer values to whole units, e.g. 10 for
+    /// values stored in tenths of degrees C or tenths of mm.
+    pub fn daily_divisor(&self) -> f32 {
+        match self {
+            Element::Prcp
+            | Element::Tmax
+            | Element::Tmin
+            | Element::Adpt
+            | Element::Aslp
+            | Element::Astp
+            | Element::Awbt
+            | Element::Awnd
+            | Element::Evap
+            | Element::Mdev
+            | Element::Mdpr
+            | Element::Mdtn
+            | Element::Mdtx
+            | Element::Mnpn
+            | Element::Mxpn
+            | Element::SoilMin(..)
+            | Element::SoilMax(..)
+            | Element::Taxn
+            | Element::Tavg
+            | Element::Thic
+            | Element::Tobs
+            | Element::Wesd
+            | Element::Wesf
+            | Element::Wsf1
+            | Element::Wsf2
+            | Element::Wsf5
+            | Element::Wsfg
+            | Element::Wsfi
+            | Element::Wsfm => 10.0,
+            _ => 1.0,
+        }
+    }
+
+    /// Returns the divisor converting USHCN monthly integer values to whole units.
+    ///
+    /// Temperatures are in hundredths of a degree Celsius, precipitation in tenths of a millimetre
+    /// (see "USHCN v2.5 readme.txt Section 2.2.1").
+    pub fn monthly_divisor(&self) -> f32 {
+        match self {
+            Element::Prcp => 10.0,
+            _ => 100.0,
+        }
+    }
+
+    /// Returns the unit of the element's values once divided by their divisor.
+    pub fn unit(&self) -> Unit {
+        match self {
+            Element::Max
+            | Element::Min
+            | Element::Avg
+            | Element::Tmax
+            | Element::Tmin
+            | Element::Adpt
+            | Element::Awbt
+            | Element::Mdtn
+            | Element::Mdtx
+            | Element::Mnpn
+            | Element::Mxpn
+            | Element::SoilMin(..)
+            | Element::SoilMax(..)
+            | Element::Taxn
+            | Element::Tavg
+            | Element::Tobs => Unit::Celsius,
+            Element::Prcp
+            | Element::Snow
+            | Element::Snwd
+            | Element::Evap
+            | Element::Mdev
+            | Element::Mdpr
+            | Element::Mdsf
+            | Element::Thic
+            | Element::Wesd
+            | Element::Wesf => Unit::Millimetre,
+            Element::Frgb | Element::Frgt | Element::Frth | Element::Gaht => Unit::Centimetre,
+            Element::Mdwm | Element::Wdmv => Unit::Kilometre,
+            Element::Awnd
+            | Element::Wsf1
+            | Element::Wsf2
+            | Element::Wsf5
+            | Element::Wsfg
+            | Element::Wsfi
+            | Element::Wsfm => Unit::MetrePerSecond,
+            Element::Aslp | Element::Astp => Unit::Hectopascal,
+            Element::Acmc
+            | Element::Acmh
+            | Element::Acsc
+            | Element::Acsh
+            | Element::Psun
+            | Element::Rhav
+            | Element::Rhmn
+            | Element::Rhmx => Unit::Percent,
+            Element::Awdr
+            | Element::Wdf1
+            | Element::Wdf2
+            | Element::Wdf5
+            | Element::Wdfg
+            | Element::Wdfi
+            | Element::Wdfm => Unit::Degree,
+            Element::Daev
+            | Element::Dapr
+            | Element::Dasf
+            | Element::Datn
+            | Element::Datx
+            | Element::Dawm
+            | Element::Dwpr => Unit::Day,
+            Element::Tsun => Unit::Minute,
+            Element::Fmtm | Element::Pgtm => Unit::Hhmm,
+            Element::WeatherType(_) | Element::WeatherInVicinity(_) => Unit::Present,
+            Element::Unknown => Unit::Unknown,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// The daily elements to keep, parsed from a comma separated list of codes or `all`.
+pub enum ElementSelection {
+    All,
+    Only(Vec<Element>),
+}
+
+impl ElementSelection {
+    /// Returns true if the element is selected. Unknown elements are never selected.
+    pub fn contains(&self, element: &Element) -> bool {
+        match self {
+            _ if *element == Element::Unknown => false,
+            ElementSelection::All => true,
+            ElementSelection::Only(elements) => elements.contains(element),
+        }
+    }
+}
+
+impl Default for ElementSelection {
+    /// The core temperature and precipitation elements.
+    fn default() -> Self {
+        ElementSelection::Only(vec![Element::Prcp, Element::Tmax, Element::Tmin])
+    }
+}
+
+impl FromStr for ElementSelection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim().eq_ignore_ascii_case("all") {
+            return Ok(ElementSelection::All);
+        }
+
+        let mut elements = Vec::new();
+        for code in s.split(',').filter(|code| !code.trim().is_empty()) {
+            match Element::from_str(code) {
+                Element::Unknown => {
+                    return Err(format!(
+                        "unknown element '{}', expected a GHCN-Daily code such as TMAX, SNOW or WT03, or 'all'",
+                        code.trim()
+                    ))
+                }
+                element => elements.push(element),
+            }
+        }
+
+        if elements.is_empty() {
+            return Err("no elements given".to_string());
+        }
+
+        Ok(ElementSelection::Only(elements))
+    }
+}
+
+impl fmt::Display for ElementSelection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ElementSelection::All => write!(f, "all"),
+            ElementSelection::Only(elements) => {
+                let codes: Vec<String> = elements.iter().map(Element::code).collect();
+                write!(f, "{}", codes.join(","))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn should_round_trip_element_codes() {
+        for element in &FIXED_ELEMENTS {
+            assert_eq!(&Element::from_str(&element.code()), element);
+        }
+    }
+
+    #[test]
+    fn should_parse_element_families() {
+        assert_eq!(Element::from_str("tmax"), Element::Tmax);
+        assert_eq!(Element::from_str("SN32"), Element::SoilMin(3, 2));
+        assert_eq!(Element::from_str("SX11"), Element::SoilMax(1, 1));
+        assert_eq!(Element::from_str("WT03"), Element::WeatherType(3));
+        assert_eq!(Element::from_str("WV20"), Element::WeatherInVicinity(20));
+        assert_eq!(Element::WeatherType(3).code(), "WT03");
+        assert_eq!(Element::from_str("SNXX"), Element::Unknown);
+        assert_eq!(Element::from_str("ABCD"), Element::Unknown);
+    }
+
+    #[test]
+    fn should_scale_by_element() {
+        assert_eq!(Element::Tmax.daily_divisor(), 10.0);
+        assert_eq!(Element::Prcp.daily_divisor(), 10.0);
+        assert_eq!(Element::Snow.daily_divisor(), 1.0);
+        assert_eq!(Element::Snwd.daily_divisor(), 1.0);
+        assert_eq!(Element::WeatherType(1).daily_divisor(), 1.0);
+        assert_eq!(Element::Max.monthly_divisor(), 100.0);
+        assert_eq!(Element::Prcp.monthly_divisor(), 10.0);
+    }
+
+    #[test]
+    fn should_have_unit_for_every_element() {
+        assert_eq!(Element::Max.unit(), Unit::Celsius);
+        assert_eq!(Element::Snwd.unit(), Unit::Millimetre);
+        assert_eq!(Element::Awnd.unit(), Unit::MetrePerSecond);
+        assert_eq!(Element::WeatherType(3).unit(), Unit::Present);
+        for element in &FIXED_ELEMENTS {
+            assert_ne!(element.unit(), Unit::Unknown, "{:?}", element);
+        }
+    }
+
+    #[test]
+    fn should_parse_element_selection() {
+        let selection: ElementSelection = "all".parse().unwrap();
+        assert!(selection.contains(&Element::WeatherType(3)));
+        assert!(!selection.contains(&Element::Unknown));
+
+        let selection: ElementSelection = "snow,SNWD, wt03".parse().unwrap();
+        assert!(selection.contains(&Element::Snow));
+        assert!(selection.contains(&Element::WeatherType(3)));
+        assert!(!selection.contains(&Element::Tmax));
+        assert_eq!(selection.to_string(), "SNOW,SNWD,WT03");
+
+        assert!("TMAX,BOGUS".parse::<ElementSelection>().is_err());
+        assert_eq!(
+            ElementSelection::default()
+                .to_string()
+                .parse::<ElementSelection>(),
+            Ok(ElementSelection::default())
+        );
+    }
+}
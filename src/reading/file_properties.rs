@@ -1,7 +1,11 @@
 //! Reading dataset and element.
 
+use std::fmt;
+
 use anyhow::Result;
 
+use super::Element;
+
 #[derive(Debug, Clone, Default)]
 /// Represents the type of dataset and the measurement type.
 pub struct FileProperties {
@@ -20,23 +24,13 @@ impl FileProperties {
                     "tob" => Dataset::Tob,
                     _ => Dataset::Unknown,
                 };
-                let element = match parts[2] {
-                    "tmax" => Element::Max,
-                    "tmin" => Element::Min,
-                    "tavg" => Element::Avg,
-                    _ => Element::Unknown,
-                };
+                let element = Element::from_monthly_str(parts[2]);
 
                 Ok(FileProperties { element, dataset })
             }
             4 => {
                 let dataset = Dataset::Fls52;
-                let element = match parts[3] {
-                    "tmax" => Element::Max,
-                    "tmin" => Element::Min,
-                    "tavg" => Element::Avg,
-                    _ => Element::Unknown,
-                };
+                let element = Element::from_monthly_str(parts[3]);
 
                 Ok(FileProperties { dataset, element })
             }
@@ -66,28 +60,21 @@ pub enum Dataset {
     Unknown,
 }
 
-#[derive(Debug, Clone, PartialEq, Default)]
-/// Represents the type of measurement. See the [NOAA documentation](https://www1.ncdc.noaa.gov/pub/data/cdo/documentation/gsom-gsoy_documentation.pdf)
-/// for more information.
-pub enum Element {
-    Max,
-    Min,
-    Avg,
-    Prcp,
-    Tmax,
-    Tmin,
-    #[default]
-    Unknown,
+impl Dataset {
+    /// Returns the name of the dataset, e.g. `FLS52`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Dataset::Raw => "RAW",
+            Dataset::Tob => "TOB",
+            Dataset::Fls52 => "FLS52",
+            Dataset::Unknown => "UNKNOWN",
+        }
+    }
 }
 
-impl Element {
-    pub fn from_str(s: &str) -> Self {
-        match s.to_lowercase().as_str() {
-            "prcp" => Element::Prcp,
-            "tmax" => Element::Tmax,
-            "tmin" => Element::Tmin,
-            _ => Element::Unknown,
-        }
+impl fmt::Display for Dataset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
     }
 }
 
@@ -105,6 +92,10 @@ mod tests {
         let p2 = FileProperties::from_file("USH00118916.FLs.52j.tmin").unwrap();
         assert_eq!(p2.dataset, Dataset::Fls52);
         assert_eq!(p2.element, Element::Min);
+
+        let p3 = FileProperties::from_file("USH00011084.raw.prcp").unwrap();
+        assert_eq!(p3.dataset, Dataset::Raw);
+        assert_eq!(p3.element, Element::Prcp);
     }
 
     #[test]
@@ -113,4 +104,10 @@ mod tests {
         assert_eq!(p.dataset, Dataset::Unknown);
         assert_eq!(p.element, Element::Unknown);
     }
+
+    #[test]
+    fn should_display_dataset() {
+        assert_eq!(Dataset::Fls52.to_string(), "FLS52");
+        assert_eq!(Dataset::Unknown.as_str(), "UNKNOWN");
+    }
 }
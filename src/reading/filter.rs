@@ -0,0 +1,192 @@
+//! Selects which readings are kept during deserialisation.
+
+use std::{collections::HashSet, fmt, str::FromStr, sync::Arc};
+
+use super::{ElementSelection, Reading};
+
+#[derive(Debug, Clone)]
+/// The criteria a reading must meet to be kept.
+pub struct ReadingFilter {
+    /// elements to keep
+    pub elements: ElementSelection,
+    /// first year, or month, to keep
+    pub start: Option<YearMonth>,
+    /// last year, or month, to keep
+    pub end: Option<YearMonth>,
+    /// IDs of the stations to keep, or None for every station
+    pub stations: Option<Arc<HashSet<String>>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A year, or a month of a year, bounding the period of the readings kept.
+pub struct YearMonth {
+    pub year: u16,
+    /// 1 to 12, or None for the whole year
+    pub month: Option<u16>,
+}
+
+impl ReadingFilter {
+    pub fn new(elements: ElementSelection) -> Self {
+        ReadingFilter {
+            elements,
+            start: None,
+            end: None,
+            stations: None,
+        }
+    }
+
+    /// Keeps only the readings from `start` to `end`, both included.
+    pub fn with_period(mut self, start: Option<YearMonth>, end: Option<YearMonth>) -> Self {
+        self.start = start;
+        self.end = end;
+        self
+    }
+
+    /// Keeps only the readings of the given stations, or of every station if None.
+    pub fn with_stations(mut self, stations: Option<HashSet<String>>) -> Self {
+        self.stations = stations.map(Arc::new);
+        self
+    }
+
+    /// Returns true if the file of a single station, named after its ID like
+    /// `USC00011084.dly`, is of a station kept. Files named otherwise are kept.
+    pub fn accepts_file(&self, file_name: &str) -> bool {
+        let Some(stations) = &self.stations else {
+            return true;
+        };
+        match file_name.split_once('.') {
+            Some((station_id, _)) => stations.contains(station_id),
+            None => true,
+        }
+    }
+
+    /// Returns true if the reading meets all criteria.
+    pub fn accepts<R: Reading>(&self, reading: &R) -> bool {
+        self.elements.contains(reading.element())
+    }
+
+    /// Returns true if a line of a year, and month for daily lines, is in the period. A line
+    /// without a month is in the period if any month of its year is.
+    pub fn accepts_date(&self, year: u16, month: Option<u16>) -> bool {
+        let after_start = self
+            .start
+            .is_none_or(|start| (year, month.unwrap_or(12)) >= start.first_month());
+        let before_end = self
+            .end
+            .is_none_or(|end| (year, month.unwrap_or(1)) <= end.last_month());
+
+        after_start && before_end
+    }
+}
+
+impl YearMonth {
+    /// Returns the year and first month of the bound.
+    pub fn first_month(&self) -> (u16, u16) {
+        (self.year, self.month.unwrap_or(1))
+    }
+
+    /// Returns the year and last month of the bound.
+    pub fn last_month(&self) -> (u16, u16) {
+        (self.year, self.month.unwrap_or(12))
+    }
+}
+
+impl FromStr for YearMonth {
+    type Err = String;
+
+    /// Parses a year like `1991`, or a month like `1991-06`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "expected a year like 1991 or a month like 1991-06, got '{}'",
+                s
+            )
+        };
+        let (year, month) = match s.trim().split_once('-') {
+            Some((year, month)) => (year, Some(month)),
+            None => (s.trim(), None),
+        };
+
+        let year = year.parse().map_err(|_| invalid())?;
+        let month = match month {
+            Some(month) => match month.parse() {
+                Ok(month @ 1..=12) => Some(month),
+                _ => return Err(invalid()),
+            },
+            None => None,
+        };
+
+        Ok(YearMonth { year, month })
+    }
+}
+
+impl fmt::Display for YearMonth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.month {
+            Some(month) => write!(f, "{}-{:02}", self.year, month),
+            None => write!(f, "{}", self.year),
+        }
+    }
+}
+
+// -- Tests -------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_parse_year_and_month_bounds() {
+        assert_eq!(
+            "1991".parse(),
+            Ok(YearMonth {
+                year: 1991,
+                month: None
+            })
+        );
+        assert_eq!(
+            "2020-06".parse(),
+            Ok(YearMonth {
+                year: 2020,
+                month: Some(6)
+            })
+        );
+        assert!("2020-13".parse::<YearMonth>().is_err());
+        assert!("June 2020".parse::<YearMonth>().is_err());
+        assert_eq!(
+            "1991-06".parse::<YearMonth>().unwrap().to_string(),
+            "1991-06"
+        );
+    }
+
+    #[test]
+    fn should_accept_dates_in_period() {
+        let filter = ReadingFilter::new(ElementSelection::All)
+            .with_period("1991".parse().ok(), "2020-06".parse().ok());
+
+        assert!(!filter.accepts_date(1990, Some(12)));
+        assert!(filter.accepts_date(1991, Some(1)));
+        assert!(filter.accepts_date(2020, Some(6)));
+        assert!(!filter.accepts_date(2020, Some(7)));
+
+        // a year of monthly values overlapping the period is kept
+        assert!(!filter.accepts_date(1990, None));
+        assert!(filter.accepts_date(1991, None));
+        assert!(filter.accepts_date(2020, None));
+        assert!(!filter.accepts_date(2021, None));
+
+        assert!(ReadingFilter::new(ElementSelection::All).accepts_date(1875, None));
+    }
+
+    #[test]
+    fn should_accept_files_of_selected_stations() {
+        let stations = HashSet::from(["USC00011084".to_string()]);
+        let filter = ReadingFilter::new(ElementSelection::All).with_stations(Some(stations));
+
+        assert!(filter.accepts_file("USC00011084.dly"));
+        assert!(!filter.accepts_file("USC00012813.dly"));
+
+        let filter = ReadingFilter::new(ElementSelection::All);
+        assert!(filter.accepts_file("USC00012813.dly"));
+    }
+}
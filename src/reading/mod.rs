@@ -1,17 +1,44 @@
 //! Definitions for the different types of readings that can be processed.
 
 pub mod daily;
+pub mod element;
 pub mod file_properties;
+pub mod filter;
 pub mod monthly;
+pub mod unit;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 
-pub use daily::DailyReading;
-pub use file_properties::{Dataset, Element, FileProperties};
-pub use monthly::MonthlyReading;
+pub use daily::{DailyFlags, DailyReading};
+pub use element::{Element, ElementSelection};
+pub use file_properties::{Dataset, FileProperties};
+pub use filter::{ReadingFilter, YearMonth};
+pub use monthly::{MonthlyFlags, MonthlyReading};
+pub use unit::{Unit, UnitSystem};
 
 /// Defines a trait for deserializing a line into a Reading.
 pub trait Reading: Sized {
     fn from_line(line: &str, file_name: &str) -> Result<Self>;
+    /// Reads the year, and month of a daily line, without parsing the values.
+    fn line_date(line: &str) -> Result<(u16, Option<u16>)>;
     fn is_valid(&self) -> bool;
+    fn element(&self) -> &Element;
+    /// Clears the values outside the filter's period, for readings of more than one month.
+    fn clear_outside_period(&mut self, _filter: &ReadingFilter) {}
+}
+
+/// Checks that a line can be sliced into fixed-width fields up to `min_length` characters.
+fn check_line(line: &str, min_length: usize) -> Result<()> {
+    if !line.is_ascii() {
+        bail!("Line contains non-ASCII characters");
+    }
+    if line.len() < min_length {
+        bail!(
+            "Line is too short: expected at least {} characters, got {}",
+            min_length,
+            line.len()
+        );
+    }
+
+    Ok(())
 }
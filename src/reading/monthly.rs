@@ -1,8 +1,8 @@
 //! Monthly reading data structure and parsing logic.
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 
-use super::{FileProperties, Reading};
+use super::{check_line, Element, FileProperties, Reading, ReadingFilter};
 
 #[derive(Debug)]
 /// Represents a monthly reading.
@@ -17,19 +17,32 @@ pub struct MonthlyReading {
     pub properties: FileProperties,
     /// reading values
     pub values: Vec<Option<f32>>,
+    /// data measurement, quality control and data source flags for each value
+    pub flags: Vec<MonthlyFlags>,
     /// latitude of the station
     pub lat: Option<f32>,
     /// longitude of the station
     pub lon: Option<f32>,
 }
 
+#[derive(Debug, Clone, Default, PartialEq)]
+/// The flags attached to a monthly value. See "USHCN v2.5 readme.txt Section 2.2.1".
+pub struct MonthlyFlags {
+    /// data measurement flag: `a`-`i` days missing from the monthly mean, or `E` estimated
+    pub dmflag: Option<char>,
+    /// quality control flag, e.g. `M` for manually flagged as erroneous
+    pub qcflag: Option<char>,
+    /// data source flag, set when the value came from the USHCN version 1 dataset
+    pub dsflag: Option<char>,
+}
+
 impl Reading for MonthlyReading {
     fn from_line(line: &str, file_name: &str) -> Result<Self> {
+        check_line(line, 16)?;
         let id = line[0..11].to_string();
-        let year = line[12..16].parse()?;
-        let month = None;
+        let (year, month) = Self::line_date(line)?;
         let properties = FileProperties::from_file(file_name)?;
-        let values = parse_monthly_values(line);
+        let (values, flags) = parse_monthly_values(line, properties.element.monthly_divisor())?;
 
         Ok(MonthlyReading {
             id,
@@ -37,18 +50,40 @@ impl Reading for MonthlyReading {
             month,
             properties,
             values,
+            flags,
             lat: None,
             lon: None,
         })
     }
 
+    fn line_date(line: &str) -> Result<(u16, Option<u16>)> {
+        check_line(line, 16)?;
+        Ok((line[12..16].parse()?, None))
+    }
+
     fn is_valid(&self) -> bool {
         true
     }
+
+    fn element(&self) -> &Element {
+        &self.properties.element
+    }
+
+    fn clear_outside_period(&mut self, filter: &ReadingFilter) {
+        for (index, (value, flags)) in self.values.iter_mut().zip(&mut self.flags).enumerate() {
+            if !filter.accepts_date(self.year, Some(index as u16 + 1)) {
+                *value = None;
+                *flags = MonthlyFlags::default();
+            }
+        }
+    }
 }
 
-/// Parses the daily values from a line.
-fn parse_monthly_values(line: &str) -> Vec<Option<f32>> {
+/// Parses the monthly values and their flags from a line.
+fn parse_monthly_values(
+    line: &str,
+    divisor: f32,
+) -> Result<(Vec<Option<f32>>, Vec<MonthlyFlags>)> {
     // Pad the line with extra spaces to ensure we can extract the expected number of chunks
     let mut padded_line = line.to_string();
     padded_line.push_str("  ");
@@ -60,28 +95,40 @@ fn parse_monthly_values(line: &str) -> Vec<Option<f32>> {
 
     // Ensure the line is long enough to contain the expected number of chunks
     if line_length < start_pos + num_chunks * chunk_length {
-        panic!(
-            "Line length is too short: expected at least {}, got {}",
-            start_pos + num_chunks * chunk_length,
-            line_length
+        bail!(
+            "Line is too short: expected at least {} characters, got {}",
+            start_pos + num_chunks * chunk_length - 2,
+            line.len()
         );
     }
 
-    let values: Vec<Option<f32>> = (0..num_chunks)
+    let values = (0..num_chunks)
         .map(|i| {
             let chunk_start = start_pos + i * chunk_length;
             let chunk_end = start_pos + (i + 1) * chunk_length;
 
             let chunk = &padded_line[chunk_start..chunk_end];
             let first_five = &chunk[..5].trim();
-            match first_five.parse::<i32>() {
-                Ok(v) if v != -9999 => Some((v as f32) / 100.0),
+            let value = match first_five.parse::<i32>() {
+                Ok(v) if v != -9999 => Some((v as f32) / divisor),
                 _ => None,
-            }
+            };
+            let flags = MonthlyFlags {
+                dmflag: parse_flag(chunk, 5),
+                qcflag: parse_flag(chunk, 6),
+                dsflag: parse_flag(chunk, 7),
+            };
+
+            (value, flags)
         })
-        .collect();
+        .unzip();
+
+    Ok(values)
+}
 
-    values
+/// Parses a single flag character, treating blank as no flag.
+fn parse_flag(chunk: &str, index: usize) -> Option<char> {
+    chunk[index..].chars().next().filter(|c| !c.is_whitespace())
 }
 
 #[cfg(test)]
@@ -123,4 +170,54 @@ mod tests {
 
         println!("{:#?}", reading.values);
     }
+
+    #[test]
+    fn should_parse_flags() {
+        let line = "USH0045726711892 -9999      532    -9999    -9999 Q   1869b    2209     2481     2734     2233     1711      777  3   -50  3";
+
+        let filename = "USH00297610.raw.tmax";
+
+        let reading = MonthlyReading::from_line(line, filename).unwrap();
+
+        assert_eq!(reading.flags.len(), 12);
+        assert_eq!(reading.flags[0], MonthlyFlags::default());
+        assert_eq!(reading.flags[3].qcflag, Some('Q'));
+        assert_eq!(reading.flags[4].dmflag, Some('b'));
+        assert_eq!(
+            reading.flags[11],
+            MonthlyFlags {
+                dmflag: None,
+                qcflag: None,
+                dsflag: Some('3')
+            }
+        );
+    }
+
+    #[test]
+    fn should_scale_precipitation_to_mm() {
+        let line = "USH0001108431895   736      371      978      649      744      483     1240       64    -9999     1091      620      827   ";
+
+        let filename = "USH00011084.raw.prcp";
+
+        let reading = MonthlyReading::from_line(line, filename).unwrap();
+
+        assert_eq!(reading.properties.element, Element::Prcp);
+        assert_eq!(reading.values[0], Some(73.6));
+        assert_eq!(reading.values[7], Some(6.4));
+        assert_eq!(reading.values[8], None);
+    }
+
+    #[test]
+    fn should_reject_truncated_line() {
+        let filename = "USH00011084.raw.prcp";
+
+        let line = "USH0001108431895   736      371";
+        let error = MonthlyReading::from_line(line, filename).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Line is too short: expected at least 123 characters, got 31"
+        );
+        assert!(MonthlyReading::from_line("USH00011084", filename).is_err());
+        assert!(MonthlyReading::from_line("", filename).is_err());
+    }
 }
@@ -0,0 +1,115 @@
+//! Units of measurement and conversion between unit systems.
+
+use clap::ValueEnum;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+/// The unit system values are written in.
+pub enum UnitSystem {
+    /// °C, mm, cm, km and m/s as published by NOAA
+    #[default]
+    Metric,
+    /// °F, inches, miles and mph
+    Imperial,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The unit of a value once scaled to whole units.
+pub enum Unit {
+    Celsius,
+    Fahrenheit,
+    Millimetre,
+    Centimetre,
+    Inch,
+    Kilometre,
+    Mile,
+    MetrePerSecond,
+    MilePerHour,
+    Hectopascal,
+    Percent,
+    Degree,
+    Day,
+    Minute,
+    /// time of day as hours and minutes, e.g. 1530
+    Hhmm,
+    /// 1 when the weather type was observed
+    Present,
+    Unknown,
+}
+
+impl Unit {
+    /// Returns the symbol written to the `unit` column and Parquet field metadata.
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Unit::Celsius => "degC",
+            Unit::Fahrenheit => "degF",
+            Unit::Millimetre => "mm",
+            Unit::Centimetre => "cm",
+            Unit::Inch => "in",
+            Unit::Kilometre => "km",
+            Unit::Mile => "mi",
+            Unit::MetrePerSecond => "m/s",
+            Unit::MilePerHour => "mph",
+            Unit::Hectopascal => "hPa",
+            Unit::Percent => "percent",
+            Unit::Degree => "deg",
+            Unit::Day => "days",
+            Unit::Minute => "min",
+            Unit::Hhmm => "HHMM",
+            Unit::Present => "present",
+            Unit::Unknown => "unknown",
+        }
+    }
+
+    /// Returns the unit values of this unit are written in for the unit system.
+    pub fn in_system(&self, system: UnitSystem) -> Unit {
+        match (system, self) {
+            (UnitSystem::Imperial, Unit::Celsius) => Unit::Fahrenheit,
+            (UnitSystem::Imperial, Unit::Millimetre | Unit::Centimetre) => Unit::Inch,
+            (UnitSystem::Imperial, Unit::Kilometre) => Unit::Mile,
+            (UnitSystem::Imperial, Unit::MetrePerSecond) => Unit::MilePerHour,
+            _ => *self,
+        }
+    }
+
+    /// Converts a value in this unit to the unit system.
+    pub fn convert(&self, value: f32, system: UnitSystem) -> f32 {
+        match (system, self) {
+            (UnitSystem::Imperial, Unit::Celsius) => value * 9.0 / 5.0 + 32.0,
+            (UnitSystem::Imperial, Unit::Millimetre) => value / 25.4,
+            (UnitSystem::Imperial, Unit::Centimetre) => value / 2.54,
+            (UnitSystem::Imperial, Unit::Kilometre) => value / 1.609344,
+            (UnitSystem::Imperial, Unit::MetrePerSecond) => value * 2.236936,
+            _ => value,
+        }
+    }
+}
+
+// -- Tests -------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn should_convert_to_imperial() {
+        assert_eq!(Unit::Celsius.convert(100.0, UnitSystem::Imperial), 212.0);
+        assert_eq!(Unit::Celsius.convert(-40.0, UnitSystem::Imperial), -40.0);
+        assert_eq!(Unit::Millimetre.convert(25.4, UnitSystem::Imperial), 1.0);
+        assert_eq!(Unit::Percent.convert(50.0, UnitSystem::Imperial), 50.0);
+        assert_eq!(Unit::Celsius.convert(21.5, UnitSystem::Metric), 21.5);
+
+        assert_eq!(
+            Unit::Celsius.in_system(UnitSystem::Imperial).symbol(),
+            "degF"
+        );
+        assert_eq!(
+            Unit::Centimetre.in_system(UnitSystem::Imperial).symbol(),
+            "in"
+        );
+        assert_eq!(
+            Unit::Millimetre.in_system(UnitSystem::Metric).symbol(),
+            "mm"
+        );
+    }
+}
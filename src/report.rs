@@ -0,0 +1,313 @@
+//! A machine-readable summary of a run, written as JSON with `--report`.
+//!
+//! Commands add a DatasetReport for each file they write, and `all` adds an ErrorReport for each
+//! dataset that failed, so that orchestration can alert on e.g. a NOAA update dropping stations.
+
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Instant,
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::{cache::Cache, deserialise::FailedFile};
+
+/// Collects the reports of a run as it progresses.
+pub struct Report {
+    started_at: DateTime<Utc>,
+    start: Instant,
+    datasets: Mutex<Vec<DatasetReport>>,
+    errors: Mutex<Vec<ErrorReport>>,
+    warnings: Mutex<Vec<String>>,
+}
+
+/// The summary of a run.
+#[derive(Debug, Serialize)]
+pub struct RunReport {
+    /// version of this tool
+    pub version: String,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub seconds: f64,
+    /// true if no dataset failed
+    pub succeeded: bool,
+    pub datasets: Vec<DatasetReport>,
+    pub errors: Vec<ErrorReport>,
+    /// problems that didn't fail a dataset, e.g. monthly archives from different NOAA releases
+    pub warnings: Vec<String>,
+}
+
+/// The summary of an output file.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct DatasetReport {
+    /// e.g. `daily`, `monthly-raw` or `ushcn-stations`
+    pub dataset: String,
+    pub output: PathBuf,
+    pub rows: u64,
+    pub stations: usize,
+    /// first period with readings, `YYYY-MM` for daily data and `YYYY` for monthly data
+    pub first_date: Option<String>,
+    /// last period with readings, formatted like `first_date`
+    pub last_date: Option<String>,
+    /// percentage of readings, or stations, with a latitude and longitude
+    pub coordinate_coverage: f64,
+    /// lines skipped because they failed to parse
+    pub rejected_lines: usize,
+    /// files skipped as a whole, e.g. because they or their archive couldn't be read
+    pub failed_files: Vec<FailedFile>,
+    /// the NOAA files the output was made from
+    pub sources: Vec<SourceReport>,
+    pub seconds: f64,
+}
+
+/// The version of a NOAA file an output was made from.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct SourceReport {
+    pub url: String,
+    /// element of a monthly archive, e.g. `tmax`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub element: Option<String>,
+    /// dataset of a monthly archive, e.g. `raw`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dataset: Option<String>,
+    /// `Last-Modified` of the download, if the server sent one
+    pub last_modified: Option<String>,
+    pub sha256: Option<String>,
+    /// top level directories of the extracted archive, e.g. `ushcn.v2.5.5.20240716`
+    pub versions: Vec<String>,
+}
+
+/// A dataset that failed.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ErrorReport {
+    pub dataset: String,
+    pub message: String,
+}
+
+/// Accumulates the station, date range and coordinate statistics of the readings in a file.
+#[derive(Debug, Default)]
+pub struct DatasetStats {
+    stations: HashSet<String>,
+    first: Option<(u16, Option<u16>)>,
+    last: Option<(u16, Option<u16>)>,
+    readings: u64,
+    with_coords: u64,
+}
+
+impl Default for Report {
+    fn default() -> Self {
+        Report::new()
+    }
+}
+
+impl Report {
+    pub fn new() -> Self {
+        Report {
+            started_at: Utc::now(),
+            start: Instant::now(),
+            datasets: Mutex::new(Vec::new()),
+            errors: Mutex::new(Vec::new()),
+            warnings: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn add_dataset(&self, dataset: DatasetReport) {
+        self.datasets.lock().unwrap().push(dataset);
+    }
+
+    pub fn add_error(&self, dataset: &str, error: &anyhow::Error) {
+        self.errors.lock().unwrap().push(ErrorReport {
+            dataset: dataset.to_string(),
+            message: format!("{:#}", error),
+        });
+    }
+
+    pub fn add_warning(&self, warning: &str) {
+        self.warnings.lock().unwrap().push(warning.to_string());
+    }
+
+    /// Returns the summary of the run so far.
+    pub fn summary(&self) -> RunReport {
+        let errors = self.errors.lock().unwrap().clone();
+        RunReport {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            started_at: self.started_at,
+            finished_at: Utc::now(),
+            seconds: self.start.elapsed().as_secs_f64(),
+            succeeded: errors.is_empty(),
+            datasets: self.datasets.lock().unwrap().clone(),
+            errors,
+            warnings: self.warnings.lock().unwrap().clone(),
+        }
+    }
+
+    /// Writes the summary of the run to a JSON file.
+    pub fn write(&self, file_path: &Path) -> Result<()> {
+        let file = File::create(file_path)
+            .with_context(|| format!("Failed to create report {}", file_path.display()))?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, &self.summary())?;
+        writeln!(writer)?;
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+impl DatasetReport {
+    /// Creates the report of an output file of `rows` rows from the statistics of its readings.
+    pub fn new(dataset: &str, output: &Path, rows: u64, stats: &DatasetStats) -> Self {
+        DatasetReport {
+            dataset: dataset.to_string(),
+            output: output.to_path_buf(),
+            rows,
+            stations: stats.stations.len(),
+            first_date: stats.first.map(format_date),
+            last_date: stats.last.map(format_date),
+            coordinate_coverage: stats.coordinate_coverage(),
+            rejected_lines: 0,
+            failed_files: Vec::new(),
+            sources: Vec::new(),
+            seconds: 0.0,
+        }
+    }
+}
+
+impl SourceReport {
+    /// Returns the version of a NOAA file recorded in the cache.
+    pub fn from_cache(cache: &Cache, url: &str) -> Self {
+        let entry = cache.entry(url);
+        SourceReport {
+            url: url.to_string(),
+            element: None,
+            dataset: None,
+            last_modified: entry.last_modified,
+            sha256: entry.sha256,
+            versions: cache.versions(url),
+        }
+    }
+}
+
+impl DatasetStats {
+    /// Records a reading of a station in a year, and month for daily readings.
+    pub fn record(&mut self, station_id: &str, year: u16, month: Option<u16>, has_coords: bool) {
+        self.record_station(station_id, has_coords);
+
+        let date = (year, month);
+        if self.first.is_none_or(|first| date < first) {
+            self.first = Some(date);
+        }
+        if self.last.is_none_or(|last| date > last) {
+            self.last = Some(date);
+        }
+    }
+
+    /// Records a station, or an undated reading of it.
+    pub fn record_station(&mut self, station_id: &str, has_coords: bool) {
+        if !self.stations.contains(station_id) {
+            self.stations.insert(station_id.to_string());
+        }
+
+        self.readings += 1;
+        if has_coords {
+            self.with_coords += 1;
+        }
+    }
+
+    /// Returns the percentage of readings with coordinates, 0 if there are none.
+    pub fn coordinate_coverage(&self) -> f64 {
+        if self.readings == 0 {
+            return 0.0;
+        }
+        self.with_coords as f64 * 100.0 / self.readings as f64
+    }
+}
+
+fn format_date((year, month): (u16, Option<u16>)) -> String {
+    match month {
+        Some(month) => format!("{}-{:02}", year, month),
+        None => year.to_string(),
+    }
+}
+
+// -- Tests -------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn should_accumulate_dataset_stats() {
+        let mut stats = DatasetStats::default();
+        stats.record("USC00011084", 1926, Some(3), true);
+        stats.record("USC00011084", 1895, Some(11), true);
+        stats.record("USC00011085", 1895, Some(2), false);
+        stats.record("USC00011084", 2024, Some(1), true);
+
+        let report = DatasetReport::new("daily", Path::new("daily.parquet"), 124, &stats);
+
+        assert_eq!(report.rows, 124);
+        assert_eq!(report.stations, 2);
+        assert_eq!(report.first_date.as_deref(), Some("1895-02"));
+        assert_eq!(report.last_date.as_deref(), Some("2024-01"));
+        assert_eq!(report.coordinate_coverage, 75.0);
+    }
+
+    #[test]
+    fn should_report_empty_dataset() {
+        let mut stats = DatasetStats::default();
+        let report = DatasetReport::new("monthly-raw", Path::new("raw.parquet"), 0, &stats);
+
+        assert_eq!(report.stations, 0);
+        assert_eq!(report.first_date, None);
+        assert_eq!(report.coordinate_coverage, 0.0);
+
+        stats.record("USH00011084", 1895, None, true);
+        let report = DatasetReport::new("monthly-raw", Path::new("raw.parquet"), 1, &stats);
+        assert_eq!(report.first_date.as_deref(), Some("1895"));
+    }
+
+    #[test]
+    fn should_write_run_report() {
+        let tmp_dir = TempDir::new().unwrap();
+        let report_path = tmp_dir.path().join("report.json");
+
+        let report = Report::new();
+        let mut stats = DatasetStats::default();
+        stats.record("USH00011084", 1895, None, true);
+        report.add_dataset(DatasetReport {
+            failed_files: vec![FailedFile {
+                file: "USH00011085.raw.tmax".to_string(),
+                reason: "Is a directory (os error 21)".to_string(),
+            }],
+            ..DatasetReport::new("monthly-raw", Path::new("raw.parquet"), 12, &stats)
+        });
+        report.add_error("daily", &anyhow::anyhow!("Failed to download"));
+        report.add_warning("Monthly archives come from 2 NOAA releases");
+        report.write(&report_path).unwrap();
+
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&report_path).unwrap()).unwrap();
+        assert_eq!(json["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(json["succeeded"], false);
+        assert_eq!(json["datasets"][0]["dataset"], "monthly-raw");
+        assert_eq!(json["datasets"][0]["rows"], 12);
+        assert_eq!(json["datasets"][0]["stations"], 1);
+        assert_eq!(json["datasets"][0]["first_date"], "1895");
+        assert_eq!(json["datasets"][0]["coordinate_coverage"], 100.0);
+        assert_eq!(json["datasets"][0]["failed_files"][0]["file"], "USH00011085.raw.tmax");
+        assert_eq!(json["errors"][0]["dataset"], "daily");
+        assert_eq!(json["errors"][0]["message"], "Failed to download");
+        assert_eq!(json["warnings"][0], "Monthly archives come from 2 NOAA releases");
+    }
+}
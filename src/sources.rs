@@ -0,0 +1,388 @@
+//! Where the NOAA files are downloaded from.
+//!
+//! The USHCN v2.5 and GHCN-Daily directories default to NOAA's servers and can be pointed at a
+//! mirror or a local directory (`file://`) with a command line flag, an environment variable or
+//! the `[sources]` table of the config file, in that order of precedence:
+//!
+//! ```toml
+//! [sources]
+//! ushcn = "https://mirror.example.com/noaa/ushcn/v2.5"
+//! ghcn_daily = "file:///data/noaa/ghcn/daily"
+//! ```
+//!
+//! Individual local files, e.g. received via an air-gapped transfer, can also be imported in place
+//! of the downloads, see [`Sources::import_files`].
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use reqwest::Url;
+use serde::Deserialize;
+
+/// NOAA's USHCN v2.5 directory.
+pub const NOAA_USHCN_URL: &str = "https://www.ncei.noaa.gov/pub/data/ushcn/v2.5";
+
+/// NOAA's GHCN-Daily directory.
+pub const NOAA_GHCN_DAILY_URL: &str = "https://www.ncei.noaa.gov/pub/data/ghcn/daily";
+
+#[derive(Debug, Clone, PartialEq)]
+/// The base URLs of the NOAA directories the files are downloaded from.
+pub struct Sources {
+    /// USHCN v2.5 directory, with the monthly archives and the USHCN station list
+    pub ushcn: String,
+    /// GHCN-Daily directory, with the daily archive and the GHCN station list
+    pub ghcn_daily: String,
+    /// local files used in place of the downloads
+    pub imported: Imports,
+}
+
+impl Default for Sources {
+    fn default() -> Self {
+        Sources {
+            ushcn: NOAA_USHCN_URL.to_string(),
+            ghcn_daily: NOAA_GHCN_DAILY_URL.to_string(),
+            imported: Imports::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+/// Local files imported in place of the downloads, by their NOAA file names.
+pub struct Imports {
+    /// `ghcnd_hcn.tar.gz`
+    pub daily_archive: Option<PathBuf>,
+    /// `ushcn.*.latest.*.tar.gz`
+    pub monthly_archives: Vec<PathBuf>,
+    /// `ushcn-v2.5-stations.txt`
+    pub ushcn_stations: Option<PathBuf>,
+    /// `ghcnd-stations.txt`
+    pub ghcn_stations: Option<PathBuf>,
+}
+
+impl Imports {
+    // Adds the file if its name is one of NOAA's, returning false otherwise.
+    fn add(&mut self, path: PathBuf) -> bool {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        if name == "ghcnd_hcn.tar.gz" {
+            self.daily_archive = Some(path);
+        } else if name.starts_with("ushcn.") && name.ends_with(".tar.gz") {
+            self.monthly_archives.push(path);
+            self.monthly_archives.sort();
+        } else if name.starts_with("ghcnd") && name.ends_with("-stations.txt") {
+            self.ghcn_stations = Some(path);
+        } else if name.starts_with("ushcn") && name.ends_with("-stations.txt") {
+            self.ushcn_stations = Some(path);
+        } else {
+            return false;
+        }
+
+        true
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    #[serde(default)]
+    sources: SourcesTable,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SourcesTable {
+    ushcn: Option<String>,
+    ghcn_daily: Option<String>,
+}
+
+impl Sources {
+    /// Resolves the sources from the given URLs, falling back to the config file and then NOAA.
+    /// Without a config file path the default one is read, if it exists.
+    pub fn load(
+        config_file: Option<&Path>,
+        ushcn: Option<String>,
+        ghcn_daily: Option<String>,
+    ) -> Result<Self> {
+        let config = match config_file {
+            Some(path) => read_config_file(path)?,
+            None => match default_config_file().filter(|path| path.exists()) {
+                Some(path) => read_config_file(&path)?,
+                None => ConfigFile::default(),
+            },
+        };
+
+        let defaults = Sources::default();
+        let sources = Sources {
+            ushcn: ushcn.or(config.sources.ushcn).unwrap_or(defaults.ushcn),
+            ghcn_daily: ghcn_daily
+                .or(config.sources.ghcn_daily)
+                .unwrap_or(defaults.ghcn_daily),
+            imported: Imports::default(),
+        };
+        validate_base_url(&sources.ushcn)?;
+        validate_base_url(&sources.ghcn_daily)?;
+
+        Ok(sources)
+    }
+
+    /// Imports local files in place of the downloads, recognised by their NOAA file names:
+    /// `ghcnd_hcn.tar.gz`, `ushcn.*.latest.*.tar.gz`, `ushcn-v2.5-stations.txt` and
+    /// `ghcnd-stations.txt`.
+    pub fn import_files(mut self, paths: &[PathBuf]) -> Result<Self> {
+        for path in paths {
+            let path = fs::canonicalize(path)
+                .map_err(|e| anyhow!("Could not import {}: {}", path.display(), e))?;
+            if !self.imported.add(path.clone()) {
+                return Err(anyhow!(
+                    "Could not import {}, it is not a NOAA archive or station list",
+                    path.display()
+                ));
+            }
+        }
+
+        Ok(self)
+    }
+
+    /// Imports the NOAA files found in a directory, ignoring any others.
+    pub fn import_dir(mut self, dir: &Path) -> Result<Self> {
+        let entries = fs::read_dir(dir)
+            .map_err(|e| anyhow!("Could not import from {}: {}", dir.display(), e))?;
+
+        let mut found = false;
+        for entry in entries {
+            let path = fs::canonicalize(entry?.path())?;
+            if path.is_file() {
+                found |= self.imported.add(path);
+            }
+        }
+
+        if !found {
+            return Err(anyhow!(
+                "No NOAA archives or station lists in {}",
+                dir.display()
+            ));
+        }
+
+        Ok(self)
+    }
+
+    /// Returns the URL of the GHCN-Daily archive of the USHCN stations.
+    pub fn daily_archive_url(&self) -> String {
+        match &self.imported.daily_archive {
+            Some(path) => file_url(path),
+            None => join(&self.ghcn_daily, "ghcnd_hcn.tar.gz"),
+        }
+    }
+
+    /// Returns the URL of the GHCN station list.
+    pub fn ghcn_stations_url(&self) -> String {
+        match &self.imported.ghcn_stations {
+            Some(path) => file_url(path),
+            None => join(&self.ghcn_daily, "ghcnd-stations.txt"),
+        }
+    }
+
+    /// Returns the URL of the USHCN station list.
+    pub fn ushcn_stations_url(&self) -> String {
+        match &self.imported.ushcn_stations {
+            Some(path) => file_url(path),
+            None => join(&self.ushcn, "ushcn-v2.5-stations.txt"),
+        }
+    }
+
+    /// Returns the URLs of the imported monthly archives, empty if none were imported.
+    pub fn imported_monthly_archive_urls(&self) -> Vec<String> {
+        self.imported
+            .monthly_archives
+            .iter()
+            .map(|path| file_url(path))
+            .collect()
+    }
+
+    /// Returns the URL of the latest monthly archive of an element (e.g. `tmax`) and dataset
+    /// (e.g. `FLs.52j`).
+    pub fn monthly_archive_url(&self, element: &str, dataset: &str) -> String {
+        join(
+            &self.ushcn,
+            &format!("ushcn.{}.latest.{}.tar.gz", element, dataset),
+        )
+    }
+}
+
+/// Returns the config file read when none is given, `ushcn/config.toml` in the user's config
+/// directory.
+pub fn default_config_file() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("ushcn").join("config.toml"))
+}
+
+fn read_config_file(path: &Path) -> Result<ConfigFile> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| anyhow!("Could not read config file {}: {}", path.display(), e))?;
+
+    toml::from_str(&contents).map_err(|e| anyhow!("Invalid config file {}: {}", path.display(), e))
+}
+
+fn validate_base_url(url: &str) -> Result<()> {
+    let parsed = Url::parse(url).map_err(|e| anyhow!("Invalid source URL '{}': {}", url, e))?;
+
+    match parsed.scheme() {
+        "http" | "https" | "file" => Ok(()),
+        scheme => Err(anyhow!(
+            "Unsupported scheme '{}' in source URL '{}', use http, https or file",
+            scheme,
+            url
+        )),
+    }
+}
+
+// Returns the file:// URL of an absolute path.
+fn file_url(path: &Path) -> String {
+    Url::from_file_path(path)
+        .map(|url| url.to_string())
+        .unwrap_or_else(|_| format!("file://{}", path.display()))
+}
+
+fn join(base_url: &str, file_name: &str) -> String {
+    format!("{}/{}", base_url.trim_end_matches('/'), file_name)
+}
+
+// -- Tests -------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn should_default_to_noaa() {
+        let sources = Sources::default();
+
+        assert_eq!(
+            sources.daily_archive_url(),
+            "https://www.ncei.noaa.gov/pub/data/ghcn/daily/ghcnd_hcn.tar.gz"
+        );
+        assert_eq!(
+            sources.ushcn_stations_url(),
+            "https://www.ncei.noaa.gov/pub/data/ushcn/v2.5/ushcn-v2.5-stations.txt"
+        );
+        assert_eq!(
+            sources.monthly_archive_url("tmax", "FLs.52j"),
+            "https://www.ncei.noaa.gov/pub/data/ushcn/v2.5/ushcn.tmax.latest.FLs.52j.tar.gz"
+        );
+    }
+
+    #[test]
+    fn should_prefer_given_urls_to_config_file() {
+        let tmp_dir = TempDir::new().unwrap();
+        let config_file = tmp_dir.path().join("config.toml");
+        fs::write(
+            &config_file,
+            "[sources]\nushcn = \"https://mirror.example.com/ushcn/\"\nghcn_daily = \"file:///data/ghcnd\"\n",
+        )
+        .unwrap();
+
+        let sources = Sources::load(Some(&config_file), None, None).unwrap();
+        assert_eq!(
+            sources.ushcn_stations_url(),
+            "https://mirror.example.com/ushcn/ushcn-v2.5-stations.txt"
+        );
+        assert_eq!(
+            sources.ghcn_stations_url(),
+            "file:///data/ghcnd/ghcnd-stations.txt"
+        );
+
+        let sources = Sources::load(
+            Some(&config_file),
+            Some("http://localhost:8080".to_string()),
+            None,
+        )
+        .unwrap();
+        assert_eq!(sources.ushcn, "http://localhost:8080");
+        assert_eq!(sources.ghcn_daily, "file:///data/ghcnd");
+    }
+
+    #[test]
+    fn should_import_local_files() {
+        let tmp_dir = TempDir::new().unwrap();
+        let names = [
+            "ghcnd_hcn.tar.gz",
+            "ushcn.tmax.latest.raw.tar.gz",
+            "ushcn.prcp.latest.FLs.52j.tar.gz",
+            "ushcn-v2.5-stations.txt",
+            "ghcnd-stations.txt",
+            "readme.txt",
+        ];
+        for name in names {
+            fs::write(tmp_dir.path().join(name), "").unwrap();
+        }
+        let dir = fs::canonicalize(tmp_dir.path()).unwrap();
+
+        let sources = Sources::default().import_dir(tmp_dir.path()).unwrap();
+
+        assert_eq!(
+            sources.daily_archive_url(),
+            format!("file://{}/ghcnd_hcn.tar.gz", dir.display())
+        );
+        assert_eq!(
+            sources.ghcn_stations_url(),
+            format!("file://{}/ghcnd-stations.txt", dir.display())
+        );
+        assert_eq!(
+            sources.imported_monthly_archive_urls(),
+            vec![
+                format!("file://{}/ushcn.prcp.latest.FLs.52j.tar.gz", dir.display()),
+                format!("file://{}/ushcn.tmax.latest.raw.tar.gz", dir.display()),
+            ]
+        );
+
+        let sources = Sources::default()
+            .import_files(&[tmp_dir.path().join("ushcn-v2.5-stations.txt")])
+            .unwrap();
+        assert_eq!(
+            sources.ushcn_stations_url(),
+            format!("file://{}/ushcn-v2.5-stations.txt", dir.display())
+        );
+        assert_eq!(
+            sources.daily_archive_url(),
+            "https://www.ncei.noaa.gov/pub/data/ghcn/daily/ghcnd_hcn.tar.gz"
+        );
+
+        assert!(Sources::default()
+            .import_files(&[tmp_dir.path().join("readme.txt")])
+            .is_err());
+        assert!(Sources::default()
+            .import_files(&[tmp_dir.path().join("missing.tar.gz")])
+            .is_err());
+    }
+
+    #[test]
+    fn should_reject_invalid_sources() {
+        let tmp_dir = TempDir::new().unwrap();
+        let config_file = tmp_dir.path().join("config.toml");
+
+        assert!(Sources::load(Some(&config_file), None, None).is_err());
+
+        fs::write(
+            &config_file,
+            "[sources]\nushcn_url = \"https://example.com\"\n",
+        )
+        .unwrap();
+        assert!(Sources::load(Some(&config_file), None, None).is_err());
+
+        fs::write(&config_file, "").unwrap();
+        let error = Sources::load(
+            Some(&config_file),
+            Some("ftp://example.com".to_string()),
+            None,
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("Unsupported scheme 'ftp'"));
+    }
+}